@@ -0,0 +1,245 @@
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use crate::cell_map::CellMap;
+
+/// Kind of pattern found by the analysis
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Classification {
+    /// All the cells are dead
+    Extinct,
+    /// The pattern never changes
+    StillLife,
+    /// The pattern comes back to its initial state at the same place
+    Oscillator { period: u64 },
+    /// The pattern comes back to its initial state, moved by (dx, dy) cells
+    Spaceship { period: u64, dx: i64, dy: i64 },
+    /// No repetition found in the allowed number of generations
+    Undetermined,
+}
+
+/// Result of the analysis of a pattern
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Analysis {
+    pub classification: Classification,
+    /// Generation at which the classification was determined
+    pub generation: u64,
+    /// First generation of the periodic behaviour
+    pub start: u64,
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+/// Hash of the shape of a generation, whatever its position
+pub(crate) fn shape_hash(c: &CellMap) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    (c.width(), c.height(), c.cells()).hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Cells of a cropped generation
+type Shape = Vec<Vec<bool>>;
+
+/// Shapes of the generations met so far, found by their hash and then compared cell by cell
+/// so that a collision of the hashes is not taken for a repetition
+pub(crate) struct Shapes<T> {
+    shapes: HashMap<u64, Vec<(Shape, T)>>,
+}
+
+impl<T: Copy> Shapes<T> {
+    pub(crate) fn new() -> Shapes<T> {
+        Shapes { shapes: HashMap::new() }
+    }
+
+    /// Value of the first generation with the same shape as the cropped one, which is stored with `value` if it is new
+    pub(crate) fn insert(&mut self, cropped: &CellMap, value: T) -> Option<T> {
+        self.insert_hashed(shape_hash(cropped), cropped, value)
+    }
+
+    fn insert_hashed(&mut self, hash: u64, cropped: &CellMap, value: T) -> Option<T> {
+        let shapes = self.shapes.entry(hash).or_default();
+        if let Some(&(_, first)) = shapes.iter().find(|(cells, _)| cells.as_slice() == cropped.cells()) {
+            return Some(first)
+        }
+        shapes.push((cropped.cells().to_vec(), value));
+        None
+    }
+}
+
+/// Run a cell map forward until one of its shapes (auto-cropped generations) comes back,
+/// or until `max_generations` generations were computed
+pub fn analyze(c: &CellMap, max_generations: u64) -> Analysis {
+    let mut c = c.clone();
+    // Generation and position of each shape
+    let mut seen = Shapes::new();
+
+    for generation in 0..=max_generations {
+        if generation > 0 {
+            c.generate_next();
+        }
+        if c.bounding_box().is_none() {
+            return Analysis { classification: Classification::Extinct, generation, start: generation }
+        }
+        let cropped = c.auto_crop();
+
+        let (x, y) = cropped.origin();
+        if let Some((first, (first_x, first_y))) = seen.insert(&cropped, (generation, (x, y))) {
+            let period = generation - first;
+            let (dx, dy) = (x - first_x, y - first_y);
+            let classification = match (period, dx, dy) {
+                (1, 0, 0) => Classification::StillLife,
+                (_, 0, 0) => Classification::Oscillator { period },
+                _ => Classification::Spaceship { period, dx, dy },
+            };
+            return Analysis { classification, generation, start: first }
+        }
+    }
+
+    Analysis { classification: Classification::Undetermined, generation: max_generations, start: max_generations }
+}
+
+impl fmt::Display for Classification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Classification::Extinct => write!(f, "extinct"),
+            Classification::StillLife => write!(f, "still life"),
+            Classification::Oscillator { period } => write!(f, "oscillator with period {}", period),
+            Classification::Spaceship { period, dx, dy } => {
+                // Speed written as a fraction of the speed of light, like c/4 or 2c/5
+                let distance = dx.unsigned_abs().max(dy.unsigned_abs());
+                let d = gcd(distance, period);
+                let speed = match (distance / d, period / d) {
+                    (1, 1) => String::from("c"),
+                    (1, p) => format!("c/{}", p),
+                    (n, 1) => format!("{}c", n),
+                    (n, p) => format!("{}c/{}", n, p),
+                };
+                write!(f, "spaceship with period {}, moving by ({}, {}) per period, speed {}", period, dx, dy, speed)
+            }
+            Classification::Undetermined => write!(f, "undetermined"),
+        }
+    }
+}
+
+
+// --------
+// Tests
+// --------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Test analysis::analyze
+
+    #[test]
+    fn test_analyze_still_life() {
+        let beehive = CellMap::new(vec![
+            vec![false, true, true, false],
+            vec![true, false, false, true],
+            vec![false, true, true, false],
+        ]).unwrap();
+
+        let result = analyze(&beehive, 100);
+
+        assert_eq!(result.classification, Classification::StillLife);
+        assert_eq!(result.generation, 1);
+    }
+
+    #[test]
+    fn test_analyze_oscillator() {
+        let toad = CellMap::new(vec![
+            vec![false, true, true, true],
+            vec![true, true, true, false],
+        ]).unwrap();
+
+        let result = analyze(&toad, 100);
+
+        assert_eq!(result.classification, Classification::Oscillator { period: 2 });
+        assert_eq!(result.generation, 2);
+    }
+
+    #[test]
+    fn test_analyze_glider() {
+        let glider = CellMap::new(vec![
+            vec![false, true, false],
+            vec![false, false, true],
+            vec![true, true, true],
+        ]).unwrap();
+
+        let result = analyze(&glider, 100);
+
+        assert_eq!(result.classification, Classification::Spaceship { period: 4, dx: 1, dy: 1 });
+        assert_eq!(result.classification.to_string(), "spaceship with period 4, moving by (1, 1) per period, speed c/4");
+    }
+
+    #[test]
+    fn test_analyze_lwss() {
+        let lwss = CellMap::new(vec![
+            vec![false, true, false, false, true],
+            vec![true, false, false, false, false],
+            vec![true, false, false, false, true],
+            vec![true, true, true, true, false],
+        ]).unwrap();
+
+        let result = analyze(&lwss, 100);
+
+        assert_eq!(result.classification, Classification::Spaceship { period: 4, dx: -2, dy: 0 });
+        assert!(result.classification.to_string().ends_with("speed c/2"));
+    }
+
+    #[test]
+    fn test_analyze_extinct() {
+        let c = CellMap::new(vec![vec![true, true]]).unwrap();
+
+        let result = analyze(&c, 100);
+
+        assert_eq!(result.classification, Classification::Extinct);
+        assert_eq!(result.generation, 1);
+    }
+
+    #[test]
+    fn test_analyze_undetermined() {
+        // The R-pentomino stabilises after 1103 generations
+        let r_pentomino = CellMap::new(vec![
+            vec![false, true, true],
+            vec![true, true, false],
+            vec![false, true, false],
+        ]).unwrap();
+
+        assert_eq!(analyze(&r_pentomino, 100).classification, Classification::Undetermined);
+    }
+
+    #[test]
+    fn test_analyze_transient() {
+        // A pre-block becomes a block
+        let c = CellMap::new(vec![
+            vec![true, true],
+            vec![true, false],
+        ]).unwrap();
+
+        let result = analyze(&c, 100);
+
+        assert_eq!(result.classification, Classification::StillLife);
+        assert_eq!(result.start, 1);
+    }
+
+    // Test Shapes.insert
+
+    #[test]
+    fn test_shapes_collision() {
+        let block = CellMap::new(vec![vec![true, true], vec![true, true]]).unwrap();
+        let blinker = CellMap::new(vec![vec![true, true, true]]).unwrap();
+        let mut shapes = Shapes::new();
+
+        // Two shapes with the same hash are kept apart
+        assert_eq!(shapes.insert_hashed(0, &block, 1), None);
+        assert_eq!(shapes.insert_hashed(0, &blinker, 2), None);
+        assert_eq!(shapes.insert_hashed(0, &blinker, 3), Some(2));
+        assert_eq!(shapes.insert_hashed(0, &block, 4), Some(1));
+        assert_eq!(shapes.insert(&block, 5), None);
+    }
+}
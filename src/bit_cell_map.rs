@@ -0,0 +1,328 @@
+use std::mem;
+use crate::cell_map::CellMap;
+use crate::error::Error;
+use crate::rule::Rule;
+use crate::topology::Topology;
+
+/// Cell map storing each row as packed `u64` words, bit `j % 64` of word `j / 64` being column `j`
+pub struct BitCellMap {
+    pub w: u32,
+    pub h: u32,
+    actual_generation: Vec<Vec<u64>>,
+    next_generation: Vec<Vec<u64>>,
+    rule: Rule,
+    origin: (i64, i64),
+}
+
+impl BitCellMap {
+    fn words_for(w: u32) -> usize {
+        (w as usize).div_ceil(64)
+    }
+
+    /// Mask of the valid bits in the last word of a row
+    fn last_word_mask(&self) -> u64 {
+        match self.w % 64 {
+            0 => u64::MAX,
+            r => (1u64 << r) - 1,
+        }
+    }
+
+    fn get_in_row(row: &[u64], x: u32) -> bool {
+        row[x as usize / 64] >> (x % 64) & 1 == 1
+    }
+
+    /// Word whose bit `j` holds the cell at column `j - 1`
+    fn west(row: &[u64], k: usize) -> u64 {
+        let carry = if k > 0 { row[k - 1] >> 63 } else { 0 };
+        (row[k] << 1) | carry
+    }
+
+    /// Word whose bit `j` holds the cell at column `j + 1`
+    fn east(row: &[u64], k: usize) -> u64 {
+        let carry = if k + 1 < row.len() { row[k + 1] << 63 } else { 0 };
+        (row[k] >> 1) | carry
+    }
+
+    /// Add an input to a bit-sliced 4 bits counter (s0 being the lowest bit)
+    fn add(counter: &mut [u64; 4], input: u64) {
+        let c0 = counter[0] & input;
+        counter[0] ^= input;
+        let c1 = counter[1] & c0;
+        counter[1] ^= c0;
+        let c2 = counter[2] & c1;
+        counter[2] ^= c1;
+        counter[3] |= c2;
+    }
+
+    /// Add dead cells around the figure
+    fn expand(&mut self, top: bool, right: bool, bottom: bool, left: bool) {
+        if right || left {
+            let new_w = self.w + right as u32 + left as u32;
+            let new_words = Self::words_for(new_w);
+            for row in self.actual_generation.iter_mut().chain(self.next_generation.iter_mut()) {
+                row.resize(new_words, 0);
+            }
+            if left {
+                for row in self.actual_generation.iter_mut() {
+                    for k in (0..row.len()).rev() {
+                        row[k] = Self::west(row, k);
+                    }
+                }
+                self.origin.0 -= 1;
+            }
+            self.w = new_w;
+        }
+        let words = Self::words_for(self.w);
+        if top {
+            self.actual_generation.insert(0, vec![0; words]);
+            self.next_generation.insert(0, vec![0; words]);
+            self.h += 1;
+            self.origin.1 -= 1;
+        }
+        if bottom {
+            self.actual_generation.push(vec![0; words]);
+            self.next_generation.push(vec![0; words]);
+            self.h += 1;
+        }
+    }
+
+    // ---------------------
+
+    /// Create a bit-packed copy of a cell map
+    pub fn from_cell_map(c: &CellMap) -> Result<BitCellMap, Error> {
+        if *c.topology() != Topology::Plane {
+            return Err(Error::Engine("[BitCellMap] Only the infinite plane is supported"))
+        }
+        if c.rule().larger_than_life().is_some() {
+            return Err(Error::Engine("[BitCellMap] Larger than Life rules are not supported"))
+        }
+        if !c.rule().is_totalistic() {
+            return Err(Error::Engine("[BitCellMap] Only outer-totalistic rules are supported"))
+        }
+        if c.rule().has_b0() {
+            return Err(Error::Engine("[BitCellMap] Rules with B0 are not supported"))
+        }
+        let words = Self::words_for(c.width());
+        let actual_generation = c.cells().iter()
+            .map(|line| {
+                let mut row = vec![0u64; words];
+                for (j, _) in line.iter().enumerate().filter(|(_, &v)| v) {
+                    row[j / 64] |= 1 << (j % 64);
+                }
+                row
+            })
+            .collect();
+
+        Ok(BitCellMap {
+            w: c.width(),
+            h: c.height(),
+            actual_generation,
+            next_generation: vec![vec![0; words]; c.height() as usize],
+            rule: *c.rule(),
+            origin: c.origin(),
+        })
+    }
+
+    /// Convert back to a cell map with the same size, position and rule
+    pub fn to_cell_map(&self) -> Result<CellMap, Error> {
+        let mut c = CellMap::new(self.actual_generation())?;
+        c.set_rule(self.rule);
+        c.set_origin(self.origin);
+        Ok(c)
+    }
+
+    /// Unpack the current generation into a two-dimensional vector of booleans
+    pub fn actual_generation(&self) -> Vec<Vec<bool>> {
+        self.actual_generation.iter()
+            .map(|row| (0..self.w).map(|x| Self::get_in_row(row, x)).collect())
+            .collect()
+    }
+
+    /// Number of live cells
+    pub fn population(&self) -> u64 {
+        self.actual_generation.iter().flatten().map(|word| word.count_ones() as u64).sum()
+    }
+
+    /// Generate the next generation following the rule of the cell map
+    pub fn generate_next(&mut self) {
+        // Detect if expand is necessary, the same way CellMap does
+        let last_column = self.w - 1;
+        let top = self.actual_generation[0].iter().any(|&w| w != 0);
+        let bottom = self.actual_generation.last().is_some_and(|row| row.iter().any(|&w| w != 0));
+        let left = self.actual_generation.iter().any(|row| row[0] & 1 == 1);
+        let right = self.actual_generation.iter().any(|row| Self::get_in_row(row, last_column));
+        self.expand(top, right, bottom, left);
+
+        // Masks selecting the cells which are alive in the next generation, per neighbour count
+        let mut when_dead = Vec::new();
+        let mut when_alive = Vec::new();
+        for n in 0..=8 {
+            if self.rule.birth(n) { when_dead.push(n) }
+            if self.rule.survival(n) { when_alive.push(n) }
+        }
+
+        let neighbours = self.rule.neighbourhood().mask();
+        let words = Self::words_for(self.w);
+        let mask = self.last_word_mask();
+        let empty = vec![0u64; words];
+
+        for i in 0..self.actual_generation.len() {
+            let up = if i > 0 { &self.actual_generation[i - 1] } else { &empty };
+            let down = self.actual_generation.get(i + 1).unwrap_or(&empty);
+            let row = &self.actual_generation[i];
+
+            for k in 0..words {
+                let mut counter = [0u64; 4];
+                let inputs = [
+                    (0, Self::west(up, k)), (1, up[k]), (2, Self::east(up, k)),
+                    (3, Self::west(row, k)), (5, Self::east(row, k)),
+                    (6, Self::west(down, k)), (7, down[k]), (8, Self::east(down, k)),
+                ];
+                // Only the cells of the neighbourhood of the rule are counted
+                for (_, input) in inputs.into_iter().filter(|(bit, _)| neighbours >> bit & 1 == 1) {
+                    Self::add(&mut counter, input);
+                }
+
+                let count_is = |n: usize| -> u64 {
+                    (0..4).fold(u64::MAX, |acc, b| {
+                        acc & if n >> b & 1 == 1 { counter[b] } else { !counter[b] }
+                    })
+                };
+                let born = when_dead.iter().fold(0, |acc, &n| acc | count_is(n));
+                let survive = when_alive.iter().fold(0, |acc, &n| acc | count_is(n));
+
+                let mut next = (born & !row[k]) | (survive & row[k]);
+                if k == words - 1 { next &= mask }
+                self.next_generation[i][k] = next;
+            }
+        }
+        // Swap pointers
+        mem::swap(&mut self.actual_generation, &mut self.next_generation);
+    }
+}
+
+
+// --------
+// Tests
+// --------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run both engines side by side and compare every generation
+    fn cross_check(source: Vec<Vec<bool>>, rule: Option<&str>, generations: u32) {
+        let mut naive = CellMap::new(source).unwrap();
+        if let Some(r) = rule {
+            naive.set_rule(Rule::parse(r).unwrap());
+        }
+        let mut packed = BitCellMap::from_cell_map(&naive).unwrap();
+
+        for g in 0..generations {
+            naive.generate_next();
+            packed.generate_next();
+            assert_eq!((packed.w, packed.h), (naive.width(), naive.height()), "size differs at generation {}", g + 1);
+            assert_eq!(packed.actual_generation(), naive.cells(), "cells differ at generation {}", g + 1);
+            assert_eq!(packed.origin, naive.origin(), "origin differs at generation {}", g + 1);
+        }
+    }
+
+    /// Deterministic pseudo-random soup
+    fn soup(w: usize, h: usize, mut seed: u64) -> Vec<Vec<bool>> {
+        let mut map = vec![vec![false; w]; h];
+        for row in map.iter_mut() {
+            for cell in row.iter_mut() {
+                seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                *cell = seed >> 62 == 0;
+            }
+        }
+        map
+    }
+
+    // Test BitCellMap::from_cell_map
+
+    #[test]
+    fn test_from_cell_map() {
+        let c = CellMap::new(soup(130, 3, 7)).unwrap();
+        let b = BitCellMap::from_cell_map(&c).unwrap();
+
+        assert_eq!(b.w, 130);
+        assert_eq!(b.h, 3);
+        assert_eq!(b.actual_generation[0].len(), 3);
+        assert_eq!(b.actual_generation(), c.cells());
+        assert_eq!(b.to_cell_map().unwrap().cells(), c.cells());
+    }
+
+    #[test]
+    fn test_from_cell_map_torus() {
+        let mut c = CellMap::new(soup(10, 10, 3)).unwrap();
+        c.set_topology(Topology::Torus { w: 10, h: 10 }).unwrap();
+
+        assert!(BitCellMap::from_cell_map(&c).is_err());
+    }
+
+    #[test]
+    fn test_from_cell_map_isotropic() {
+        let mut c = CellMap::new(soup(10, 10, 3)).unwrap();
+        c.set_rule(Rule::parse("B2-a/S12").unwrap());
+
+        assert!(BitCellMap::from_cell_map(&c).is_err());
+    }
+
+    // Test BitCellMap.generate_next
+
+    #[test]
+    fn test_generate_next_glider() {
+        cross_check(vec![
+            vec![false, false, false, false, false],
+            vec![false, false, true, false, false],
+            vec![false, false, false, true, false],
+            vec![false, true, true, true, false],
+            vec![false, false, false, false, false]], None, 40);
+    }
+
+    #[test]
+    fn test_generate_next_toad() {
+        cross_check(vec![
+            vec![false, false, false, false, false, false],
+            vec![false, false, false, false, false, false],
+            vec![false, false, true, true, true, false],
+            vec![false, true, true, true, false, false],
+            vec![false, false, false, false, false, false],
+            vec![false, false, false, false, false, false]], None, 10);
+    }
+
+    #[test]
+    fn test_generate_next_beehive() {
+        cross_check(vec![
+            vec![false, false, false, false, false, false],
+            vec![false, false, true, true, false, false],
+            vec![false, true, false, false, true, false],
+            vec![false, false, true, true, false, false],
+            vec![false, false, false, false, false, false]], None, 10);
+    }
+
+    #[test]
+    fn test_generate_next_figure() {
+        // Expands on every side
+        cross_check(vec![
+            vec![false, true, false],
+            vec![true, true, false],
+            vec![false, true, true]], None, 60);
+    }
+
+    #[test]
+    fn test_generate_next_word_boundaries() {
+        // Widths around 64 exercise the carries between words
+        for (w, seed) in [(63, 1), (64, 2), (65, 3), (128, 4)] {
+            cross_check(soup(w, 20, seed), None, 30);
+        }
+    }
+
+    #[test]
+    fn test_generate_next_rules() {
+        for rule in ["B36/S23", "B3678/S34678", "B2/S", "B1357/S1357", "B2/S34H", "B13/S012V"] {
+            cross_check(soup(70, 30, 42), Some(rule), 15);
+        }
+    }
+}
@@ -1,5 +1,11 @@
 use std::cmp::PartialEq;
 use std::mem;
+use crate::error::Error;
+use crate::larger_than_life::LargerThanLife;
+use crate::margolus::Margolus;
+use crate::noise::{Noise, Random};
+use crate::rule::{Neighbourhood, Rule};
+use crate::topology::Topology;
 
 enum Direction {
     Top,
@@ -8,33 +14,49 @@ enum Direction {
     Left,
 }
 
+/// Grid of cells of the Game of Life, growing on the infinite plane when the pattern reaches its borders
+#[derive(Debug, Clone)]
 pub struct CellMap {
-    pub w: u32,
-    pub h: u32,
-    pub actual_generation: Vec<Vec<bool>>,
+    w: u32,
+    h: u32,
+    actual_generation: Vec<Vec<bool>>,
     next_generation: Vec<Vec<bool>>,
+    rule: Rule,
+    topology: Topology,
+    /// Coordinates of the top left cell, which move when the map expands or is cropped
+    origin: (i64, i64),
+    /// State of all the cells outside of the map, which B0 rules turn alive. The cells of the map are stored
+    /// relative to it, a stored live cell being in the opposite state of the background
+    background: bool,
+    /// Whether the generation is odd, the 2x2 blocks of the Margolus rules then starting at odd coordinates
+    odd: bool,
+    /// Probabilities of the transitions of a stochastic simulation and the generator drawing them
+    noise: Option<(Noise, Random)>,
 }
 
 impl PartialEq for Direction {
     fn eq(&self, other: &Self) -> bool {
-        match (self, other) {
-            (Direction::Top, Direction::Top) => true,
-            (Direction::Right, Direction::Right) => true,
-            (Direction::Bottom, Direction::Bottom) => true,
-            (Direction::Left, Direction::Left) => true,
-            _ => false,
-        }
+        matches!(
+            (self, other),
+            (Direction::Top, Direction::Top)
+                | (Direction::Right, Direction::Right)
+                | (Direction::Bottom, Direction::Bottom)
+                | (Direction::Left, Direction::Left)
+        )
     }
 }
 
 impl CellMap {
+    /// Largest number of cells of a map built from a pattern given by coordinates
+    pub const MAX_CELLS: u64 = 1 << 28;
 
     /// Add dead cells around the figure
-    fn expand(&mut self, all_d: &Vec<Direction>) {
+    fn expand(&mut self, all_d: &[Direction]) {
         if all_d.contains(&Direction::Top) {
             self.actual_generation.insert(0, vec![false; self.w as usize]);
             self.next_generation.insert(0, vec![false; self.w as usize]);
             self.h += 1;
+            self.origin.1 -= 1;
         }
         if all_d.contains(&Direction::Bottom) {
             self.actual_generation.push(vec![false; self.w as usize]);
@@ -50,14 +72,17 @@ impl CellMap {
             if all_d.contains(&Direction::Left) {
                 self.actual_generation[i].insert(0, false);
                 self.next_generation[i].insert(0, false);
-                if i == 0 { self.w += 1 }
+                if i == 0 {
+                    self.w += 1;
+                    self.origin.0 -= 1;
+                }
             }
         }
     }
 
     /// Remove empty lines at the start and end of the figure
     fn _trim(&mut self) {
-        if self.actual_generation.len() > 0 {
+        if !self.actual_generation.is_empty() {
             while !self.actual_generation[0].contains(&true) {
                 self.actual_generation.remove(0);
                 self.next_generation.remove(0);
@@ -77,8 +102,24 @@ impl CellMap {
 
     // ---------------------
 
-    /// Reduce the figure to the minimum size removing all the empty columns and lines
+    /// Reduce the figure to the minimum size removing all the empty columns and lines.
+    /// The result is on the infinite plane since it no longer has the size of a bounded grid
     pub fn auto_crop(&self) -> CellMap {
+        if !self.actual_generation.iter().any(|y| y.contains(&true)) {
+            // Nothing alive, keep a single dead cell
+            return CellMap {
+                w: 1,
+                h: 1,
+                actual_generation: vec![vec![false]],
+                next_generation: vec![vec![false]],
+                rule: self.rule,
+                topology: Topology::Plane,
+                origin: self.origin,
+                background: self.background,
+                odd: self.odd,
+                noise: self.noise.clone(),
+            }
+        }
         let (mut start_y, mut end_y): (usize, usize) = ((self.h - 1) as usize, 0);
         let (mut start_x, mut end_x): (usize, usize) = ((self.w - 1) as usize, 0);
 
@@ -115,20 +156,175 @@ impl CellMap {
             w: w as u32,
             h: h as u32,
             actual_generation: new_actual_generation,
-            next_generation: vec![vec![false; w]; h]
+            next_generation: vec![vec![false; w]; h],
+            rule: self.rule,
+            topology: Topology::Plane,
+            origin: (self.origin.0 + start_x as i64, self.origin.1 + start_y as i64),
+            background: self.background,
+            odd: self.odd,
+            noise: self.noise.clone(),
+        }
+    }
+
+    /// Bounding box of the live cells as (min x, min y, max x, max y), None if all cells are dead
+    pub fn bounding_box(&self) -> Option<(i64, i64, i64, i64)> {
+        let start_y = self.actual_generation.iter().position(|y| y.contains(&true))?;
+        let end_y = self.actual_generation.iter().rposition(|y| y.contains(&true))?;
+        let start_x = self.actual_generation.iter().filter_map(|y| y.iter().position(|x| *x)).min()?;
+        let end_x = self.actual_generation.iter().filter_map(|y| y.iter().rposition(|x| *x)).max()?;
+
+        Some((
+            self.origin.0 + start_x as i64,
+            self.origin.1 + start_y as i64,
+            self.origin.0 + end_x as i64,
+            self.origin.1 + end_y as i64,
+        ))
+    }
+
+    /// Number of columns of the map
+    pub fn width(&self) -> u32 {
+        self.w
+    }
+
+    /// Number of rows of the map
+    pub fn height(&self) -> u32 {
+        self.h
+    }
+
+    /// Cells of the current generation, row by row, `true` being alive
+    pub fn cells(&self) -> &[Vec<bool>] {
+        &self.actual_generation
+    }
+
+    /// Number of live cells
+    pub fn population(&self) -> u64 {
+        self.actual_generation.iter().flatten().filter(|v| **v).count() as u64
+    }
+
+    /// Shape of the universe
+    pub fn topology(&self) -> &Topology {
+        &self.topology
+    }
+
+    /// Change the shape of the universe, the live cells are centered in a bounded grid
+    pub fn set_topology(&mut self, topology: Topology) -> Result<(), Error> {
+        if let Some((w, h)) = topology.size() {
+            let torus = matches!(topology, Topology::Torus { .. });
+            if self.rule.margolus().is_some() && !(torus && w % 2 == 0 && h % 2 == 0) {
+                return Err(Error::CellMap("[CellMap creation] The blocks of a Margolus rule need a torus of even size"))
+            }
+            // A bounded grid has no cells outside of it
+            self.set_background(false);
+            let cropped = self.auto_crop();
+            if cropped.w > w || cropped.h > h {
+                return Err(Error::CellMap("[CellMap creation] The pattern does not fit in the bounded grid"))
+            }
+            let (left, top) = ((w - cropped.w) / 2, (h - cropped.h) / 2);
+
+            let mut map = vec![vec![false; w as usize]; h as usize];
+            for (i, row) in cropped.actual_generation.iter().enumerate() {
+                for (j, &v) in row.iter().enumerate() {
+                    map[i + top as usize][j + left as usize] = v;
+                }
+            }
+            self.actual_generation = map;
+            self.next_generation = vec![vec![false; w as usize]; h as usize];
+            (self.w, self.h) = (w, h);
+            self.origin = (cropped.origin.0 - left as i64, cropped.origin.1 - top as i64);
+        }
+        self.topology = topology;
+        Ok(())
+    }
+
+    /// Move the grid of a bounded topology so that its top left cell is at the given coordinates, the live cells
+    /// keeping theirs. Fails if a live cell would be out of the grid
+    pub fn set_grid_origin(&mut self, origin: (i64, i64)) -> Result<(), Error> {
+        let (w, h) = (self.w as i64, self.h as i64);
+        let mut map = vec![vec![false; self.w as usize]; self.h as usize];
+        for (i, row) in self.actual_generation.iter().enumerate() {
+            for (j, _) in row.iter().enumerate().filter(|(_, &v)| v) {
+                let (x, y) = (self.origin.0 + j as i64 - origin.0, self.origin.1 + i as i64 - origin.1);
+                if !(0..w).contains(&x) || !(0..h).contains(&y) {
+                    return Err(Error::CellMap("[CellMap creation] The pattern does not fit in the bounded grid"))
+                }
+                map[y as usize][x as usize] = true;
+            }
+        }
+        self.actual_generation = map;
+        self.origin = origin;
+        Ok(())
+    }
+
+    /// Coordinates of the top left cell of the map
+    pub fn origin(&self) -> (i64, i64) {
+        self.origin
+    }
+
+    /// Move the map so that its top left cell is at the given coordinates
+    pub fn set_origin(&mut self, origin: (i64, i64)) {
+        self.origin = origin;
+    }
+
+    /// State of the cell at the given coordinates, the background outside of the map
+    pub fn is_alive(&self, x: i64, y: i64) -> bool {
+        let (x, y) = (x - self.origin.0, y - self.origin.1);
+        if x < 0 || y < 0 {
+            return self.background
+        }
+        self.actual_generation.get(y as usize)
+            .and_then(|row| row.get(x as usize))
+            .is_some_and(|v| *v) != self.background
+    }
+
+    /// State of the cells outside of the map, alive every other generation with a B0 rule without S8.
+    /// The cells, the population and the bounding box are the ones of the cells in the opposite state
+    pub fn background(&self) -> bool {
+        self.background
+    }
+
+    /// Change the state of the cells outside of the map, the cells of the map keeping their state
+    pub fn set_background(&mut self, background: bool) {
+        if background != self.background {
+            self.actual_generation.iter_mut().flatten().for_each(|v| *v = !*v);
+            self.background = background;
         }
     }
 
+    /// Probabilities of the transitions, None if the simulation is deterministic
+    pub fn noise(&self) -> Option<&Noise> {
+        self.noise.as_ref().map(|(noise, _)| noise)
+    }
+
+    /// Make the next generations stochastic, the generator starting again from the seed of the noise.
+    /// Only the cells of the map are reached, on the infinite plane the map grows with the pattern and the
+    /// background stays deterministic: a background turning alive (B0, Critters) needs a bounded grid
+    pub fn set_noise(&mut self, noise: Option<Noise>) -> Result<(), Error> {
+        if noise.is_some() && self.topology == Topology::Plane && self.rule.next_background(false) {
+            return Err(Error::CellMap("[CellMap] Noise with a background turning alive needs a bounded grid"))
+        }
+        self.noise = noise.map(|noise| (noise, Random::new(noise.seed)));
+        Ok(())
+    }
+
+    /// Set the background and the position of the Margolus blocks to the ones of a generation,
+    /// for a pattern read at another generation than 0
+    pub fn set_phase(&mut self, generation: i64) {
+        if self.topology == Topology::Plane {
+            self.set_background(self.rule.background_at(generation));
+        }
+        self.odd = generation.rem_euclid(2) == 1;
+    }
+
     /// Create a new instance of a CellMap from a two-dimensional vector of booleans
-    pub fn new(source: Vec<Vec<bool>>) -> Result<CellMap, &'static str> {
+    pub fn new(source: Vec<Vec<bool>>) -> Result<CellMap, Error> {
         let col_size = source.len();
-        if col_size == 0 {
-            return Err("[CellMap creation] The source can't be empty")
+        if col_size == 0 || source[0].is_empty() {
+            return Err(Error::CellMap("[CellMap creation] The source can't be empty"))
         }
         let row_size = source[0].len();
         for r in source.iter() {
             if r.len() != row_size {
-                return Err("[CellMap creation] All rows must have the same size")
+                return Err(Error::CellMap("[CellMap creation] All rows must have the same size"))
             }
         }
         Ok (CellMap {
@@ -136,54 +332,220 @@ impl CellMap {
             h: col_size as u32,
             actual_generation: source,
             next_generation: vec![vec![false; row_size]; col_size],
+            rule: Rule::default(),
+            topology: Topology::Plane,
+            origin: (0, 0),
+            background: false,
+            odd: false,
+            noise: None,
         })
     }
 
-    /// Generate the next generation following the rule of the game of life
+    /// Rule used to compute the next generations
+    pub fn rule(&self) -> &Rule {
+        &self.rule
+    }
+
+    /// Change the rule used to compute the next generations
+    pub fn set_rule(&mut self, rule: Rule) {
+        self.rule = rule;
+    }
+
+    /// Generate the next generation following the rule of the cell map, and the noise of a stochastic simulation
     pub fn generate_next(&mut self) {
-        // Detect if expand is necessary
-        let mut all_directions = vec![];
-        if self.actual_generation[0].contains(&true) {
-            all_directions.push(Direction::Top);
-        }
-        if self.actual_generation.last().map_or(false, |row| row.contains(&true)) {
-            all_directions.push(Direction::Bottom);
+        let background = self.background;
+        if let Some(rule) = self.rule.larger_than_life().copied() {
+            self.generate_next_larger_than_life(&rule);
+        } else if let Some(rule) = self.rule.margolus().copied() {
+            self.generate_next_margolus(&rule);
+            self.odd = !self.odd;
+        } else {
+            self.generate_next_3x3();
         }
-        if self.actual_generation.iter().any(|row| row.first() == Some(&true)) {
-            all_directions.push(Direction::Left);
-        }
-        if self.actual_generation.iter().any(|row| row.last() == Some(&true)) {
-            all_directions.push(Direction::Right);
-        }
-        self.expand(&all_directions);
+        self.apply_noise(background);
+    }
 
+    /// Generate the next generation of a rule of the 3x3 neighbourhood, cell by cell
+    fn generate_next_3x3(&mut self) {
+        // Bounded grids never grow, and have no background
+        let mut rule = self.rule;
+        if self.topology == Topology::Plane {
+            self.expand_if_needed();
+            rule = self.rule.relative_to(self.background);
+            self.background = self.rule.next_background(self.background);
+        }
 
         for i in 0..self.actual_generation.len() {
             for j in 0..self.actual_generation[i].len() {
-                let (i, j) = (i as i32, j as i32);
-                // Count alive cells
-                let mut alive = 0;
-                let coord = [(i-1, j-1), (i-1, j), (i-1, j+1), (i, j-1), (i, j+1), (i+1, j-1), (i+1, j), (i+1, j+1)];
-                for (y, x) in coord {
-                    if let Some(row) = self.actual_generation.get(y as usize) {
-                        if let Some(v) = row.get(x as usize) {
-                            if *v { alive += 1 }
+                let (i, j) = (i as i64, j as i64);
+                // Neighbourhood of the cell, bit `3 * dy + dx` being the cell at (j + dx - 1, i + dy - 1)
+                let mut neighbourhood = 0;
+                for (bit, (dy, dx)) in (0..3).flat_map(|dy| (0..3).map(move |dx| (dy, dx))).enumerate() {
+                    if let Some((x, y)) = self.topology.wrap(j + dx - 1, i + dy - 1) {
+                        if let Some(row) = self.actual_generation.get(y) {
+                            if let Some(v) = row.get(x) {
+                                if *v { neighbourhood |= 1 << bit }
+                            }
                         }
                     }
                 }
 
                 // Apply game rules
                 let (i, j) = (i as usize, j as usize);
-                self.next_generation[i][j] = match (self.actual_generation[i][j], alive) {
-                    (true, 2) | (true, 3) => true,
-                    (false, 3) => true,
-                    _ => false,
+                self.next_generation[i][j] = rule.next_state_of(neighbourhood);
+            }
+        }
+        // Swap pointers
+        mem::swap(&mut self.actual_generation, &mut self.next_generation);
+    }
+
+    /// Change the transitions of the last generation following the probabilities of the noise, the previous
+    /// generation being kept in the other buffer
+    fn apply_noise(&mut self, previous_background: bool) {
+        let Some((noise, random)) = self.noise.as_mut() else { return };
+        for (row, previous) in self.actual_generation.iter_mut().zip(&self.next_generation) {
+            for (v, &was_stored) in row.iter_mut().zip(previous) {
+                let (was_alive, alive) = (was_stored != previous_background, *v != self.background);
+                let mut next = match (was_alive, alive) {
+                    (false, true) => random.chance(noise.birth),
+                    (true, false) => !random.chance(noise.death),
+                    _ => alive,
+                };
+                if random.chance(noise.flip) { next = !next }
+                *v = next != self.background;
+            }
+        }
+    }
+
+    /// Go back to the previous generation of a reversible Margolus rule
+    pub fn generate_previous(&mut self) -> Result<(), Error> {
+        let Some(inverse) = self.rule.margolus().and_then(Margolus::inverse) else {
+            return Err(Error::Rule("[CellMap] Only the reversible Margolus rules can be run backwards"))
+        };
+        // The blocks of the previous generation are replaced back by the inverse rule
+        self.odd = !self.odd;
+        self.generate_next_margolus(&inverse);
+        Ok(())
+    }
+
+    /// Replace each 2x2 block by the one given by the Margolus rule. The blocks start at even coordinates
+    /// on even generations and at odd ones on odd generations
+    fn generate_next_margolus(&mut self, rule: &Margolus) {
+        // The blocks of the live cells must be in the map
+        let plane = self.topology == Topology::Plane;
+        let (input, output) = if plane {
+            self.expand_to_margin(1);
+            let full = |background: bool| if background { Margolus::FULL } else { 0 };
+            let next_background = rule.next_block(full(self.background)) == Margolus::FULL;
+            let blocks = (full(self.background), full(next_background));
+            self.background = next_background;
+            blocks
+        } else {
+            (0, 0)
+        };
+
+        let (w, h) = (self.w as i64, self.h as i64);
+        let (x0, y0) = (self.origin.0 + self.odd as i64, self.origin.1 + self.odd as i64);
+        self.next_generation.iter_mut().for_each(|row| row.fill(false));
+        for i in (-y0.rem_euclid(2)..h).step_by(2) {
+            for j in (-x0.rem_euclid(2)..w).step_by(2) {
+                let cells = [(j, i), (j + 1, i), (j, i + 1), (j + 1, i + 1)].map(|(x, y)| self.topology.wrap(x, y));
+                let block = cells.iter().enumerate()
+                    .filter(|(_, cell)| cell.is_some_and(|(x, y)| self.actual_generation.get(y).and_then(|row| row.get(x)) == Some(&true)))
+                    .fold(0, |block, (bit, _)| block | 1 << bit);
+
+                let next = rule.next_block(block ^ input) ^ output;
+                for (bit, cell) in cells.iter().enumerate() {
+                    if let Some((x, y)) = *cell {
+                        if let Some(v) = self.next_generation.get_mut(y).and_then(|row| row.get_mut(x)) {
+                            *v = next >> bit & 1 == 1;
+                        }
+                    }
                 }
             }
         }
+        mem::swap(&mut self.actual_generation, &mut self.next_generation);
+    }
+
+    /// Add dead cells so that the live cells are at least `margin` cells away from the borders
+    fn expand_to_margin(&mut self, margin: u32) {
+        let Some((min_x, min_y, max_x, max_y)) = self.bounding_box() else { return };
+        let margin = margin as i64;
+        let (x, y) = self.origin;
+        let missing = [
+            (Direction::Top, margin - (min_y - y)),
+            (Direction::Bottom, margin - (y + self.h as i64 - 1 - max_y)),
+            (Direction::Left, margin - (min_x - x)),
+            (Direction::Right, margin - (x + self.w as i64 - 1 - max_x)),
+        ];
+        for (direction, n) in missing {
+            for _ in 0..n {
+                self.expand(std::slice::from_ref(&direction));
+            }
+        }
+    }
+
+    /// Generate the next generation of a Larger than Life rule. The neighbours are counted with
+    /// a summed-area table, so that a cell needs a few lookups whatever the range of the rule
+    fn generate_next_larger_than_life(&mut self, rule: &LargerThanLife) {
+        let r = rule.range() as i64;
+        if self.topology == Topology::Plane {
+            self.expand_to_margin(rule.range());
+        }
+
+        // Live cells of the map and of a border of `r` cells around it, seen through the topology
+        let (w, h) = ((self.w as i64 + 2 * r) as usize, (self.h as i64 + 2 * r) as usize);
+        let mut sums = vec![vec![0u32; w + 1]; h + 1];
+        for py in 0..h {
+            let mut row = 0;
+            for px in 0..w {
+                let alive = self.topology.wrap(px as i64 - r, py as i64 - r)
+                    .and_then(|(x, y)| self.actual_generation.get(y)?.get(x).copied())
+                    .unwrap_or(false);
+                row += alive as u32;
+                sums[py + 1][px + 1] = sums[py][px + 1] + row;
+            }
+        }
+        // Live cells in the rectangle of the padded map between the columns x0..=x1 and the rows y0..=y1
+        let area = |x0: usize, y0: usize, x1: usize, y1: usize| {
+            sums[y1 + 1][x1 + 1] + sums[y0][x0] - sums[y0][x1 + 1] - sums[y1 + 1][x0]
+        };
+
+        for i in 0..self.actual_generation.len() {
+            for j in 0..self.actual_generation[i].len() {
+                // The cell (j, i) is at (j + r, i + r) in the padded map
+                let (x, y) = (j as i64 + r, i as i64 + r);
+                let count = if rule.neighbourhood() == Neighbourhood::Moore {
+                    area((x - r) as usize, (y - r) as usize, (x + r) as usize, (y + r) as usize)
+                } else {
+                    (-r..=r).map(|dy| {
+                        let (start, end) = rule.row_span(dy);
+                        area((x + start) as usize, (y + dy) as usize, (x + end) as usize, (y + dy) as usize)
+                    }).sum()
+                };
+                self.next_generation[i][j] = rule.next_state(self.actual_generation[i][j], count);
+            }
+        }
         // Swap pointers
         mem::swap(&mut self.actual_generation, &mut self.next_generation);
     }
+
+    fn expand_if_needed(&mut self) {
+        let mut all_directions = vec![];
+        if self.actual_generation[0].contains(&true) {
+            all_directions.push(Direction::Top);
+        }
+        if self.actual_generation.last().is_some_and(|row| row.contains(&true)) {
+            all_directions.push(Direction::Bottom);
+        }
+        if self.actual_generation.iter().any(|row| row.first() == Some(&true)) {
+            all_directions.push(Direction::Left);
+        }
+        if self.actual_generation.iter().any(|row| row.last() == Some(&true)) {
+            all_directions.push(Direction::Right);
+        }
+        self.expand(&all_directions);
+    }
 }
 
 
@@ -195,10 +557,38 @@ impl CellMap {
 mod tests {
     use super::*;
 
+    fn r_pentomino() -> Vec<Vec<bool>> {
+        vec![
+            vec![false, true, true],
+            vec![true, true, false],
+            vec![false, true, false]]
+    }
+
+    /// Run the R-pentomino for 7 generations on the plane and on a torus large enough to never wrap it, and check
+    /// that both have the same cells. `check` is also given the plane after every generation
+    fn assert_torus_matches_plane(rule: &str, check: impl Fn(&CellMap, i64)) -> Result<(), Error> {
+        let mut c = CellMap::new(r_pentomino())?;
+        c.set_rule(Rule::parse(rule).map_err(Error::Rule)?);
+        let mut torus = c.clone();
+        torus.set_topology(Topology::Torus { w: 30, h: 30 })?;
+
+        for g in 1..=7 {
+            c.generate_next();
+            torus.generate_next();
+
+            check(&c, g);
+            let (x0, y0) = torus.origin();
+            for (y, x) in (y0..y0 + 30).flat_map(|y| (x0..x0 + 30).map(move |x| (y, x))) {
+                assert_eq!(c.is_alive(x, y), torus.is_alive(x, y), "{} generation {} at ({}, {})", rule, g, x, y);
+            }
+        }
+        Ok(())
+    }
+
     // Test CellMap::new
 
     #[test]
-    fn test_new_1() -> Result<(), &'static str> {
+    fn test_new_1() -> Result<(), Error> {
         let c = CellMap::new(
             vec![
                 vec![false, true, false],
@@ -219,8 +609,10 @@ mod tests {
     fn test_new_2() {
         match CellMap::new(vec![]) {
             Ok(_) => panic!("The result should not be Ok"),
-            Err(e) => assert_eq!(e, "[CellMap creation] The source can't be empty"),
+            Err(e) => assert_eq!(e.to_string(), "[CellMap creation] The source can't be empty"),
         }
+        // A map without columns
+        assert!(CellMap::new(vec![vec![], vec![]]).is_err());
     }
 
     #[test]
@@ -231,14 +623,14 @@ mod tests {
             vec![true, false],
         ]) {
             Ok(_) => panic!("The result should not be Ok"),
-            Err(e) => assert_eq!(e, "[CellMap creation] All rows must have the same size"),
+            Err(e) => assert_eq!(e.to_string(), "[CellMap creation] All rows must have the same size"),
         }
     }
 
     // Test CellMap.generate_next
 
     #[test]
-    fn test_generate_next_figure() -> Result<(), String> {
+    fn test_generate_next_figure() -> Result<(), Error> {
         let mut c = CellMap::new(
             vec![
                 vec![false, true, false],
@@ -259,7 +651,7 @@ mod tests {
     }
 
     #[test]
-    fn test_generate_next_blinker() -> Result<(), String> {
+    fn test_generate_next_blinker() -> Result<(), Error> {
         // Test for a blinker pattern
         let mut c = CellMap::new(
             vec![
@@ -283,7 +675,7 @@ mod tests {
     }
 
     #[test]
-    fn test_generate_next_glider() -> Result<(), String> {
+    fn test_generate_next_glider() -> Result<(), Error> {
         // Test for a glider pattern
         let mut c = CellMap::new(
             vec![
@@ -307,7 +699,7 @@ mod tests {
     }
 
     #[test]
-    fn test_generate_next_beehive() -> Result<(), String> {
+    fn test_generate_next_beehive() -> Result<(), Error> {
         // Test for a beehive pattern (stable)
         let mut c = CellMap::new(
             vec![
@@ -331,7 +723,7 @@ mod tests {
     }
 
     #[test]
-    fn test_generate_next_toad() -> Result<(), String> {
+    fn test_generate_next_toad() -> Result<(), Error> {
         // Test for a toad pattern (period 2 oscillator)
         let mut c = CellMap::new(
             vec![
@@ -356,6 +748,347 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_generate_next_rule() -> Result<(), Error> {
+        // Seeds (B2/S): every live cell dies, dead cells with two neighbours are born
+        let mut c = CellMap::new(
+            vec![
+                vec![false, false, false, false],
+                vec![false, true, true, false],
+                vec![false, false, false, false]]
+        )?;
+        c.set_rule(Rule::parse("B2/S").map_err(Error::Rule)?);
+
+        c.generate_next();
+
+        assert_eq!(c.actual_generation, vec![
+            vec![false, true, true, false],
+            vec![false, false, false, false],
+            vec![false, true, true, false],
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_generate_next_isotropic() -> Result<(), Error> {
+        // The cells above and below a domino have an edge and an adjacent corner (2a)
+        let domino = vec![
+            vec![false, false, false, false],
+            vec![false, true, true, false],
+            vec![false, false, false, false]];
+        let mut c = CellMap::new(domino.clone())?;
+        c.set_rule(Rule::parse("B2a/S").map_err(Error::Rule)?);
+        c.generate_next();
+        assert_eq!(c.actual_generation, vec![
+            vec![false, true, true, false],
+            vec![false, false, false, false],
+            vec![false, true, true, false],
+        ]);
+
+        let mut c = CellMap::new(domino)?;
+        c.set_rule(Rule::parse("B2-a/S").map_err(Error::Rule)?);
+        c.generate_next();
+        assert_eq!(c.population(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_generate_next_neighbourhoods() -> Result<(), Error> {
+        let dot = vec![
+            vec![false, false, false],
+            vec![false, true, false],
+            vec![false, false, false]];
+
+        // Von Neumann neighbourhood: only the orthogonal cells are neighbours
+        let mut c = CellMap::new(dot.clone())?;
+        c.set_rule(Rule::parse("B1/SV").map_err(Error::Rule)?);
+        c.generate_next();
+        assert_eq!(c.actual_generation, vec![
+            vec![false, true, false],
+            vec![true, false, true],
+            vec![false, true, false],
+        ]);
+
+        // Hexagonal neighbourhood: the top right and bottom left cells are not neighbours
+        let mut c = CellMap::new(dot)?;
+        c.set_rule(Rule::parse("B1/SH").map_err(Error::Rule)?);
+        c.generate_next();
+        assert_eq!(c.actual_generation, vec![
+            vec![true, true, false],
+            vec![true, false, true],
+            vec![false, true, true],
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_generate_next_larger_than_life() -> Result<(), Error> {
+        // Range 1 rules behave like the rules of the 3x3 neighbourhoods
+        for (larger, rule) in [("R1,C0,M0,S2..3,B3,NM", "B3/S23"), ("R1,M1,S3..4,B3..3,NM", "B3/S23"),
+                               ("R1,M0,S1..2,B1,NN", "B1/S12V"), ("R1,M0,S2,B2,NH", "B2/S2H")] {
+            let mut c = CellMap::new(r_pentomino())?;
+            c.set_rule(Rule::parse(larger).map_err(Error::Rule)?);
+            let mut expected = CellMap::new(r_pentomino())?;
+            expected.set_rule(Rule::parse(rule).map_err(Error::Rule)?);
+
+            for _ in 0..30 {
+                c.generate_next();
+                expected.generate_next();
+            }
+
+            assert_eq!(c.auto_crop().cells(), expected.auto_crop().cells(), "{}", larger);
+            assert_eq!(c.bounding_box(), expected.bounding_box(), "{}", larger);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_generate_next_larger_than_life_range() -> Result<(), Error> {
+        // Every cell within range 2 of a single cell is born, the map growing by two cells on each side
+        for (rule, population) in [("R2,M0,S0,B1,NM", 25), ("R2,M0,S0,B1,NN", 13), ("R2,M0,S0,B1,NH", 19)] {
+            let mut c = CellMap::new(vec![vec![true]])?;
+            c.set_rule(Rule::parse(rule).map_err(Error::Rule)?);
+
+            c.generate_next();
+
+            assert_eq!(c.population(), population, "{}", rule);
+            assert_eq!(c.bounding_box(), Some((-2, -2, 2, 2)), "{}", rule);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_generate_next_torus() -> Result<(), Error> {
+        // A glider crosses the edges of a 6x6 torus and comes back after 24 generations
+        let glider = vec![
+            vec![false, true, false],
+            vec![false, false, true],
+            vec![true, true, true]];
+        let mut c = CellMap::new(glider)?;
+        c.set_topology(Topology::Torus { w: 6, h: 6 })?;
+        let start = c.actual_generation.clone();
+
+        for g in 1..=24 {
+            c.generate_next();
+            assert_eq!((c.w, c.h), (6, 6));
+            assert_eq!(c.actual_generation.iter().flatten().filter(|v| **v).count(), 5, "generation {}", g);
+        }
+
+        assert_eq!(c.actual_generation, start);
+        Ok(())
+    }
+
+    #[test]
+    fn test_generate_next_torus_larger_than_life() -> Result<(), Error> {
+        // Same glider with a range 1 Larger than Life rule
+        let glider = vec![
+            vec![false, true, false],
+            vec![false, false, true],
+            vec![true, true, true]];
+        let mut c = CellMap::new(glider)?;
+        c.set_rule(Rule::parse("R1,C0,M0,S2..3,B3..3,NM").map_err(Error::Rule)?);
+        c.set_topology(Topology::Torus { w: 6, h: 6 })?;
+        let start = c.actual_generation.clone();
+
+        for _ in 0..24 {
+            c.generate_next();
+        }
+
+        assert_eq!((c.w, c.h), (6, 6));
+        assert_eq!(c.actual_generation, start);
+        Ok(())
+    }
+
+    #[test]
+    fn test_generate_next_bounded_plane() -> Result<(), Error> {
+        // A blinker against the edge of a bounded plane loses its outer cell
+        let mut c = CellMap::new(vec![
+            vec![true, false, false],
+            vec![true, false, false],
+            vec![true, false, false]])?;
+        c.set_topology(Topology::BoundedPlane { w: 1, h: 3 })?;
+
+        c.generate_next();
+
+        assert_eq!(c.actual_generation, vec![vec![false], vec![true], vec![false]]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_generate_next_twisted_edges() -> Result<(), Error> {
+        let source = vec![
+            vec![true, true, false, true],
+            vec![false, false, false, false],
+            vec![false, false, false, false],
+            vec![false, false, false, false]];
+
+        // Left and right edges joined with a twist
+        let mut klein = CellMap::new(source.clone())?;
+        klein.topology = Topology::KleinBottle { w: 4, h: 4, twisted_top_bottom: false };
+        klein.generate_next();
+        assert_eq!(klein.actual_generation, vec![
+            vec![true, false, false, false],
+            vec![false, false, false, false],
+            vec![false, false, false, false],
+            vec![true, false, false, false],
+        ]);
+
+        // Both pairs of edges joined with a twist
+        let mut cross = CellMap::new(source.clone())?;
+        cross.topology = Topology::CrossSurface { w: 4, h: 4 };
+        cross.generate_next();
+        assert_eq!(cross.actual_generation, vec![
+            vec![true, false, false, false],
+            vec![false, false, false, false],
+            vec![false, false, false, false],
+            vec![false, false, false, true],
+        ]);
+
+        // Without twist
+        let mut torus = CellMap::new(source)?;
+        torus.topology = Topology::Torus { w: 4, h: 4 };
+        torus.generate_next();
+        assert_eq!(torus.actual_generation, vec![
+            vec![true, false, false, false],
+            vec![true, false, false, false],
+            vec![false, false, false, false],
+            vec![true, false, false, false],
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_generate_next_b0() -> Result<(), Error> {
+        // The background strobes: the cells far from the live cell are born, then die with 8 live neighbours
+        let mut c = CellMap::new(vec![vec![true]])?;
+        c.set_rule(Rule::parse("B0/S").map_err(Error::Rule)?);
+
+        c.generate_next();
+        assert!(c.background());
+        assert!(c.is_alive(-2, 0) && c.is_alive(100, 100));
+        assert!(!c.is_alive(0, 0) && !c.is_alive(1, 1));
+        assert_eq!(c.population(), 9);
+
+        c.generate_next();
+        assert!(!c.background());
+        assert_eq!(c.auto_crop().cells(), vec![vec![true]]);
+        assert_eq!(c.bounding_box(), Some((0, 0, 0, 0)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_generate_next_b0_torus() -> Result<(), Error> {
+        // The background of a torus is simulated cell by cell, with and without S8
+        for rule in ["B03/S23", "B0123478/S0123467", "B01/S238"] {
+            assert_torus_matches_plane(rule, |c, g| {
+                assert_eq!(c.background(), c.rule().background_at(g), "{} generation {}", rule, g);
+            })?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_generate_next_margolus() -> Result<(), Error> {
+        // A lone ball of the billiard-ball machine moves diagonally
+        let mut c = CellMap::new(vec![vec![true]])?;
+        c.set_rule(Rule::parse("BBM").map_err(Error::Rule)?);
+
+        for _ in 0..10 {
+            c.generate_next();
+        }
+
+        assert_eq!(c.population(), 1);
+        assert_eq!(c.bounding_box(), Some((10, 10, 10, 10)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_generate_next_margolus_torus() -> Result<(), Error> {
+        // The strobing background of Critters and Tron is simulated cell by cell on a torus
+        for rule in ["Critters", "Tron", "BBM"] {
+            assert_torus_matches_plane(rule, |_, _| {})?;
+        }
+
+        let mut c = CellMap::new(r_pentomino())?;
+        c.set_rule(Rule::parse("Critters").map_err(Error::Rule)?);
+        assert!(c.set_topology(Topology::Torus { w: 31, h: 30 }).is_err());
+        assert!(c.set_topology(Topology::BoundedPlane { w: 30, h: 30 }).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_generate_next_noise() -> Result<(), Error> {
+        // Births never happen, the center of the blinker survives alone
+        let mut c = CellMap::new(vec![vec![false, false, false], vec![true, true, true], vec![false, false, false]])?;
+        c.set_noise(Some(Noise { birth: 0.0, ..Noise::default() }))?;
+        c.generate_next();
+        assert_eq!(c.auto_crop().cells(), vec![vec![true]]);
+
+        // The same seed gives the same generations
+        let pattern = CellMap::new(r_pentomino())?;
+        let run = |noise: Option<Noise>| {
+            let mut c = pattern.clone();
+            c.set_noise(noise).unwrap();
+            (0..30).for_each(|_| c.generate_next());
+            (c.bounding_box(), c.auto_crop().cells().to_vec())
+        };
+        let noise = Noise { birth: 0.9, death: 0.95, flip: 0.001, seed: 42 };
+        assert_eq!(run(Some(noise)), run(Some(noise)));
+        assert_ne!(run(Some(noise)), run(Some(Noise { seed: 43, ..noise })));
+        assert_eq!(run(Some(Noise::default())), run(None));
+        Ok(())
+    }
+
+    // Test CellMap.generate_previous
+
+    #[test]
+    fn test_generate_previous() -> Result<(), Error> {
+        let pattern = vec![
+            vec![false, true, true, false, true],
+            vec![true, true, false, false, false],
+            vec![false, true, false, true, true]];
+        for rule in ["Critters", "Tron", "BBM"] {
+            let mut c = CellMap::new(pattern.clone())?;
+            c.set_rule(Rule::parse(rule).map_err(Error::Rule)?);
+            let mut torus = c.clone();
+            torus.set_topology(Topology::Torus { w: 12, h: 10 })?;
+            let start = torus.clone();
+
+            for _ in 0..15 {
+                c.generate_next();
+                torus.generate_next();
+            }
+            for _ in 0..15 {
+                c.generate_previous()?;
+                torus.generate_previous()?;
+            }
+
+            assert!(!c.background(), "{}", rule);
+            assert_eq!(c.auto_crop().cells(), pattern, "{}", rule);
+            assert_eq!(torus.cells(), start.cells(), "{}", rule);
+        }
+
+        let mut c = CellMap::new(pattern)?;
+        assert!(c.generate_previous().is_err());
+        c.set_rule(Rule::parse("M0,0,2,3,4,5,6,7,8,9,10,11,12,13,14,15").map_err(Error::Rule)?);
+        assert!(c.generate_previous().is_err());
+        Ok(())
+    }
+
+    // Test CellMap.set_background
+
+    #[test]
+    fn test_set_background() {
+        let mut c = CellMap::new(vec![vec![true, false]]).unwrap();
+
+        c.set_background(true);
+
+        assert!(c.is_alive(0, 0) && !c.is_alive(1, 0) && c.is_alive(5, 0));
+        assert_eq!(c.cells(), vec![vec![false, true]]);
+        c.set_topology(Topology::Torus { w: 4, h: 1 }).unwrap();
+        assert!(!c.background());
+    }
+
     // Test CellMap.auto_crop
 
     #[test]
@@ -389,6 +1122,65 @@ mod tests {
         assert_eq!(cropped_c.next_generation[0].len(), 3);
     }
 
+    #[test]
+    fn test_auto_crop_keeps_rule() {
+        let mut c = CellMap::new(vec![
+            vec![false, false, false],
+            vec![false, true, false],
+            vec![false, false, false],
+        ]).unwrap();
+        c.set_rule(Rule::parse("B36/S23").unwrap());
+
+        assert_eq!(c.auto_crop().rule().to_string(), "B36/S23");
+    }
+
+    #[test]
+    fn test_auto_crop_empty() {
+        let c = CellMap::new(vec![vec![false; 4]; 3]).unwrap();
+
+        let cropped_c = c.auto_crop();
+
+        assert_eq!(cropped_c.actual_generation, vec![vec![false]]);
+        assert_eq!(c.bounding_box(), None);
+    }
+
+    // Test CellMap.population
+
+    #[test]
+    fn test_population() {
+        let c = CellMap::new(vec![
+            vec![false, true, false],
+            vec![false, false, true],
+            vec![true, true, true],
+        ]).unwrap();
+
+        assert_eq!(c.population(), 5);
+        assert_eq!(CellMap::new(vec![vec![false; 3]; 2]).unwrap().population(), 0);
+    }
+
+    // Test CellMap.origin
+
+    #[test]
+    fn test_origin() {
+        let mut c = CellMap::new(vec![
+            vec![false, false, false],
+            vec![true, true, true],
+            vec![false, false, false],
+        ]).unwrap();
+
+        // The blinker touches the left and right borders
+        c.generate_next();
+        assert_eq!(c.origin(), (-1, 0));
+        assert_eq!(c.bounding_box(), Some((1, 0, 1, 2)));
+        assert!(c.is_alive(1, 0));
+        assert!(!c.is_alive(0, 1));
+
+        let cropped_c = c.auto_crop();
+        assert_eq!(cropped_c.origin(), (1, 0));
+        assert!(cropped_c.is_alive(1, 2));
+        assert!(!cropped_c.is_alive(-1, 2));
+    }
+
     // Test CellMap.expand
 
     #[test]
@@ -399,7 +1191,7 @@ mod tests {
             vec![true, true, true],
         ]).unwrap();
 
-        c.expand(&vec![Direction::Left]);
+        c.expand(&[Direction::Left]);
 
         assert_eq!(c.w, 4);
         assert_eq!(c.h, 3);
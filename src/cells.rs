@@ -0,0 +1,236 @@
+use crate::cell_map::CellMap;
+use crate::error::{Error, SourceError};
+use crate::rule::Rule;
+use crate::topology::Topology;
+
+/// Pattern in the plaintext format: `!` comment lines then one line of `.` and `O` per row
+#[derive(Debug)]
+pub struct Cells {
+    comments: Vec<String>,
+    rule: Rule,
+    topology: Topology,
+    cells: Vec<Vec<bool>>,
+}
+
+impl Cells {
+    const DECODER: &'static str = "Plaintext decoder";
+    /// The format has no rule field, it is kept in a comment line like `!Rule: B36/S23:T20,20`
+    const RULE: &'static str = "!Rule:";
+
+    // ---------
+
+    pub fn parse(file_content: String) -> Result<Cells, Error> {
+        let (mut comments, mut rule, mut topology) = (Vec::new(), Rule::default(), Topology::Plane);
+        let mut cells: Vec<Vec<bool>> = Vec::new();
+
+        for (i, text) in file_content.lines().enumerate() {
+            if let Some(r) = text.strip_prefix(Self::RULE) {
+                let (r, suffix) = r.split_once(':').unwrap_or((r, ""));
+                let column = Self::RULE.len();
+                rule = Rule::parse(r).map_err(|_| {
+                    Error::Header(SourceError::new(Self::DECODER, "Invalid rule", r.trim()).at(i + 1, column, text))
+                })?;
+                topology = Topology::parse(suffix).map_err(|_| {
+                    Error::Header(SourceError::new(Self::DECODER, "Invalid topology", suffix.trim()).at(i + 1, column, text))
+                })?;
+                continue
+            }
+            if text.starts_with('!') {
+                comments.push(text.to_string());
+                continue
+            }
+            let mut row = Vec::with_capacity(text.len());
+            for (column, c) in text.trim_end().char_indices() {
+                match c {
+                    '.' => row.push(false),
+                    // Some files use `*` for live cells
+                    'O' | '*' => row.push(true),
+                    _ => {
+                        let e = SourceError::new(Self::DECODER, "Unknown character", &c.to_string()).at(i + 1, column, text);
+                        return Err(Error::Body(e))
+                    }
+                }
+            }
+            cells.push(row);
+        }
+
+        // Trailing empty lines are not part of the pattern
+        while cells.last().is_some_and(|row| row.is_empty()) {
+            cells.pop();
+        }
+        if cells.is_empty() {
+            return Err(Error::Body(SourceError::new(Self::DECODER, "No cells found", "")))
+        }
+        // Dead cells at the end of the rows can be omitted
+        let width = cells.iter().map(|row| row.len()).max().unwrap_or_default().max(1);
+        for row in cells.iter_mut() {
+            row.resize(width, false);
+        }
+
+        Ok(Cells { comments, rule, topology, cells })
+    }
+
+    /// The whole grid of a bounded topology is kept, the format having no position
+    pub fn from_cell_map(c: &CellMap, comments: Option<&Vec<String>>) -> Cells {
+        let topology = *c.topology();
+        let cells = if topology.size().is_some() { c.cells().to_vec() } else { c.auto_crop().cells().to_vec() };
+
+        Cells {
+            comments: if let Some(c) = comments { c.clone() } else { Vec::<String>::new() },
+            rule: *c.rule(),
+            topology,
+            cells,
+        }
+    }
+
+    pub fn to_cell_map(&self) -> Result<CellMap, Error> {
+        let mut cell_map = CellMap::new(self.cells.clone())?;
+        cell_map.set_rule(self.rule);
+        cell_map.set_topology(self.topology)?;
+        if self.topology.size().is_some() {
+            // The rows are the top of the grid rather than a pattern to center
+            cell_map.set_grid_origin((0, 0))?;
+        }
+        Ok(cell_map)
+    }
+
+    /// Comment lines (`!Name: ...`, `!...`) of the file
+    pub fn comments(&self) -> &[String] {
+        &self.comments
+    }
+
+    pub fn export(&self) -> String {
+        // Add comments
+        let mut content = String::from("!Generated by ALife\n");
+        if self.rule != Rule::default() || self.topology != Topology::Plane {
+            content += &format!("{} {}{}\n", Self::RULE, self.rule, self.topology);
+        }
+        for comment in self.comments.iter() {
+            content += comment;
+            content += "\n";
+        }
+        // Add cells
+        for row in self.cells.iter() {
+            content.extend(row.iter().map(|&alive| if alive { 'O' } else { '.' }));
+            content += "\n";
+        }
+        content
+    }
+
+    pub fn file_to_cell_map(file_content: String) -> Result<CellMap, Error> {
+        Self::parse(file_content)?.to_cell_map()
+    }
+
+    pub fn cell_map_to_file(c: &CellMap, comments: Option<&Vec<String>>) -> String {
+        Self::from_cell_map(c, comments).export()
+    }
+}
+
+
+// --------
+// Tests
+// --------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Test Cells::parse
+
+    #[test]
+    fn test_parse_1() {
+        let content = String::from("!Name: Glider
+!The smallest spaceship.
+.O
+..O
+OOO
+");
+
+        let result = Cells::parse(content).unwrap();
+
+        assert_eq!(result.comments, vec!["!Name: Glider", "!The smallest spaceship."]);
+        assert_eq!(result.cells, vec![
+            vec![false, true, false],
+            vec![false, false, true],
+            vec![true, true, true],
+        ]);
+    }
+
+    #[test]
+    fn test_parse_empty_rows() {
+        let content = String::from("!Name: Two blocks\nOO\nOO\n\n\nOO\nOO\n\n");
+
+        let result = Cells::parse(content).unwrap();
+
+        assert_eq!(result.cells.len(), 6);
+        assert_eq!(result.cells[2], vec![false, false]);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        match Cells::parse(String::from("!Name: Blinker\nOOo")) {
+            Err(Error::Body(e)) => {
+                assert_eq!(e.token, "o");
+                assert_eq!(e.location.map(|l| (l.line, l.column)), Some((2, 3)));
+            }
+            _ => panic!("A body error was expected"),
+        }
+        assert!(Cells::parse(String::from("!Name: Nothing\n")).is_err());
+        assert!(matches!(Cells::parse(String::from("!Rule: B3/S2x\nO")), Err(Error::Header(_))));
+        assert!(matches!(Cells::parse(String::from("!Rule: B3/S23:Q4\nO")), Err(Error::Header(_))));
+    }
+
+    #[test]
+    fn test_parse_rule() {
+        let result = Cells::parse(String::from("!Name: Glider\n!Rule: B36/S23:T8,6\n.O\n..O\nOOO\n")).unwrap();
+
+        assert_eq!(result.comments, vec!["!Name: Glider"]);
+        assert_eq!(result.rule, Rule::parse("B36/S23").unwrap());
+        assert_eq!(result.topology, Topology::Torus { w: 8, h: 6 });
+    }
+
+    // Test Cells.export
+
+    #[test]
+    fn test_export() {
+        let c = CellMap::new(vec![
+            vec![false, false, false, false],
+            vec![false, true, true, false],
+            vec![false, true, false, false],
+            vec![false, false, false, false],
+        ]).unwrap();
+
+        let result = Cells::cell_map_to_file(&c, Some(&vec![String::from("!Name: Pre-block")]));
+
+        assert_eq!(result, "!Generated by ALife\n!Name: Pre-block\nOO\nO.\n");
+    }
+
+    #[test]
+    fn test_export_rule_topology() {
+        let mut c = CellMap::new(vec![
+            vec![false, true, false],
+            vec![false, false, true],
+            vec![true, true, true],
+        ]).unwrap();
+        c.set_rule(Rule::parse("B36/S23").unwrap());
+        c.set_topology(Topology::Torus { w: 5, h: 4 }).unwrap();
+
+        let result = Cells::cell_map_to_file(&c, None);
+
+        assert_eq!(result, "!Generated by ALife\n!Rule: B36/S23:T5,4\n..O..\n...O.\n.OOO.\n.....\n");
+        let back = Cells::file_to_cell_map(result).unwrap();
+        assert_eq!(back.rule(), c.rule());
+        assert_eq!(back.topology(), c.topology());
+        assert_eq!(back.cells(), c.cells());
+    }
+
+    // Test Cells::file_to_cell_map
+
+    #[test]
+    fn test_file_to_cell_map() {
+        let c = Cells::file_to_cell_map(String::from("*.*\n.*")).unwrap();
+
+        assert_eq!((c.width(), c.height()), (3, 2));
+        assert_eq!(c.population(), 3);
+    }
+}
@@ -0,0 +1,173 @@
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Place of an error in a pattern file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location {
+    /// Line number, starting at 1
+    pub line: usize,
+    /// Column number in characters, starting at 1
+    pub column: usize,
+    /// Content of the line
+    pub text: String,
+}
+
+/// Error found while decoding a pattern file
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceError {
+    /// Name of the decoder of the format, like `RLE decoder`
+    pub decoder: &'static str,
+    pub message: &'static str,
+    /// Part of the file which caused the error
+    pub token: String,
+    pub location: Option<Location>,
+    pub path: Option<PathBuf>,
+}
+
+impl SourceError {
+    pub fn new(decoder: &'static str, message: &'static str, token: &str) -> SourceError {
+        SourceError { decoder, message, token: token.to_string(), location: None, path: None }
+    }
+
+    /// Set the position of the token, `column` being a byte offset in the line starting at 0
+    pub fn at(mut self, line: usize, column: usize, text: &str) -> SourceError {
+        let column = text.get(..column).map_or(column, |before| before.chars().count()) + 1;
+        self.location = Some(Location { line, column, text: text.to_string() });
+        self
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// A file could not be read or written
+    Io { path: PathBuf, source: io::Error },
+    /// The header of a pattern file (like the `x = ..., y = ...` line of RLE) is missing or incorrect
+    Header(SourceError),
+    /// The encoded cells are incorrect
+    Body(SourceError),
+    /// The rule or the topology is unknown or incorrect
+    Rule(&'static str),
+    /// The cells do not form a valid cell map
+    CellMap(&'static str),
+    /// The simulation engine does not support the pattern
+    Engine(&'static str),
+    /// Incompatible or missing command line arguments
+    Arguments(&'static str),
+    /// An image, GIF or video could not be created
+    Export(String),
+}
+
+impl Error {
+    /// Attach the path of the decoded file to header and body errors
+    pub fn with_path(self, path: &Path) -> Error {
+        match self {
+            Error::Header(e) => Error::Header(SourceError { path: Some(path.to_path_buf()), ..e }),
+            Error::Body(e) => Error::Body(SourceError { path: Some(path.to_path_buf()), ..e }),
+            e => e,
+        }
+    }
+
+    /// Description of the error, showing the offending line with a caret under the token when it is known
+    pub fn diagnostic(&self) -> String {
+        let e = match self {
+            Error::Header(e) | Error::Body(e) => e,
+            _ => return self.to_string(),
+        };
+        let Some(location) = &e.location else {
+            return self.to_string()
+        };
+
+        let number = location.line.to_string();
+        let margin = " ".repeat(number.len());
+        let path = e.path.as_ref().map_or(String::from("<input>"), |p| p.display().to_string());
+        let underline = "^".repeat(e.token.chars().count().max(1));
+        format!(
+            "{}\n{}--> {}:{}:{}\n{} |\n{} | {}\n{} | {}{}",
+            self, margin, path, location.line, location.column,
+            margin,
+            number, location.text,
+            margin, " ".repeat(location.column - 1), underline
+        )
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io { path, source } => write!(f, "[File reader] {}: {}", path.display(), source),
+            Error::Header(e) | Error::Body(e) => {
+                write!(f, "[{}] {}", e.decoder, e.message)?;
+                if !e.token.is_empty() {
+                    write!(f, " `{}`", e.token)?;
+                }
+                if let Some(location) = &e.location {
+                    write!(f, " at line {}, column {}", location.line, location.column)?;
+                }
+                Ok(())
+            }
+            Error::Rule(message)
+            | Error::CellMap(message)
+            | Error::Engine(message)
+            | Error::Arguments(message) => write!(f, "{}", message),
+            Error::Export(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<image::ImageError> for Error {
+    fn from(e: image::ImageError) -> Self {
+        Error::Export(format!("[Image exporter] {}", e))
+    }
+}
+
+
+// --------
+// Tests
+// --------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Test Error.to_string
+
+    #[test]
+    fn test_display() {
+        let e = Error::Body(SourceError::new("RLE decoder", "Unknown character", "a").at(3, 5, "2b2oab!"));
+        assert_eq!(e.to_string(), "[RLE decoder] Unknown character `a` at line 3, column 6");
+
+        let e = Error::Header(SourceError::new("RLE decoder", "Header line not found", ""));
+        assert_eq!(e.to_string(), "[RLE decoder] Header line not found");
+    }
+
+    // Test Error.diagnostic
+
+    #[test]
+    fn test_diagnostic() {
+        let e = Error::Header(SourceError::new("RLE decoder", "Invalid height", "1a").at(12, 8, "x = 3, y = 1a"))
+            .with_path(Path::new("glider.rle"));
+
+        assert_eq!(e.diagnostic(), "[RLE decoder] Invalid height `1a` at line 12, column 9
+  --> glider.rle:12:9
+   |
+12 | x = 3, y = 1a
+   |         ^^");
+    }
+
+    #[test]
+    fn test_diagnostic_without_location() {
+        let e = Error::CellMap("[CellMap creation] The source can't be empty");
+
+        assert_eq!(e.diagnostic(), "[CellMap creation] The source can't be empty");
+    }
+}
@@ -0,0 +1,198 @@
+use std::path::Path;
+use crate::cell_map::CellMap;
+use crate::cells::Cells;
+use crate::error::Error;
+use crate::life_105::Life105;
+use crate::life_106::Life106;
+use crate::macrocell::Macrocell;
+use crate::rle::RLE;
+
+/// File format of a pattern
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Run Length Encoded, `.rle`
+    Rle,
+    /// Rows of `.` and `O`, `.cells`
+    Plaintext,
+    /// `#P x y` blocks of `.` and `*` rows, `.lif`
+    Life105,
+    /// `x y` coordinates of the live cells, `.lif`
+    Life106,
+    /// Quadtree of the pattern, `.mc`
+    Macrocell,
+}
+
+impl Format {
+    /// Format given by the extension of a file, None when the extension is unknown or shared by several formats
+    pub fn from_extension(path: &Path) -> Option<Format> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "rle" => Some(Format::Rle),
+            "cells" => Some(Format::Plaintext),
+            "mc" => Some(Format::Macrocell),
+            _ => None,
+        }
+    }
+
+    /// Format recognised from the first non-empty line of a file
+    pub fn detect(content: &str) -> Option<Format> {
+        let line = content.lines().map(str::trim).find(|line| !line.is_empty())?;
+        if line.starts_with("#Life 1.05") {
+            return Some(Format::Life105)
+        }
+        if line.starts_with("#Life 1.06") {
+            return Some(Format::Life106)
+        }
+        if line.starts_with("[M2]") {
+            return Some(Format::Macrocell)
+        }
+        match line.chars().next()? {
+            '#' | 'x' => Some(Format::Rle),
+            '!' | '.' | 'O' | '*' => Some(Format::Plaintext),
+            _ => None,
+        }
+    }
+
+    /// Extension of the files of the format, without the dot
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Rle => "rle",
+            Format::Plaintext => "cells",
+            Format::Life105 | Format::Life106 => "lif",
+            Format::Macrocell => "mc",
+        }
+    }
+
+    /// Decode the content of a file into a cell map, its generation (0 if the format has none)
+    /// and the comment lines of the file
+    pub fn parse(&self, content: String) -> Result<(CellMap, i64, Vec<String>), Error> {
+        match self {
+            Format::Rle => {
+                let rle = RLE::parse(content)?;
+                Ok((rle.to_cell_map()?, rle.generation(), rle.comments().to_vec()))
+            }
+            Format::Plaintext => {
+                let cells = Cells::parse(content)?;
+                Ok((cells.to_cell_map()?, 0, cells.comments().to_vec()))
+            }
+            Format::Life105 => {
+                let life = Life105::parse(content)?;
+                Ok((life.to_cell_map()?, 0, life.comments().to_vec()))
+            }
+            Format::Life106 => {
+                let life = Life106::parse(content)?;
+                Ok((life.to_cell_map()?, 0, life.comments().to_vec()))
+            }
+            Format::Macrocell => {
+                let macrocell = Macrocell::parse(content)?;
+                Ok((macrocell.to_cell_map()?, macrocell.generation(), macrocell.comments().to_vec()))
+            }
+        }
+    }
+
+    /// Encode a cell map, the generation being written by the formats which store it
+    /// and the comment lines in the syntax of the format. The formats without a generation
+    /// cannot give the phase of a B0 rule whose background is alive
+    pub fn export(&self, c: &CellMap, generation: i64, comments: &[String]) -> Result<String, Error> {
+        let comments = comments.to_vec();
+        let has_generation = matches!(self, Format::Rle | Format::Macrocell);
+        if c.background() && !has_generation {
+            return Err(Error::Export(String::from(
+                "[Pattern exporter] The background is alive at this generation, only RLE and macrocell files keep its phase"
+            )))
+        }
+        Ok(match self {
+            Format::Rle => {
+                let mut rle = RLE::from_cell_map(c, Some(&comments));
+                rle.set_generation(generation);
+                rle.export()
+            }
+            Format::Plaintext => Cells::cell_map_to_file(c, Some(&comments)),
+            Format::Life105 => Life105::cell_map_to_file(c, Some(&comments)),
+            Format::Life106 => Life106::cell_map_to_file(c, Some(&comments)),
+            Format::Macrocell => {
+                let mut macrocell = Macrocell::from_cell_map(c, Some(&comments));
+                macrocell.set_generation(generation);
+                macrocell.export()
+            }
+        })
+    }
+
+    /// Comment line holding a text
+    pub fn comment(&self, text: &str) -> String {
+        match self {
+            Format::Rle | Format::Macrocell => format!("#C {}", text),
+            Format::Plaintext => format!("!{}", text),
+            Format::Life105 | Format::Life106 => format!("#D {}", text),
+        }
+    }
+}
+
+
+// --------
+// Tests
+// --------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Test Format::from_extension
+
+    #[test]
+    fn test_from_extension() {
+        assert_eq!(Format::from_extension(Path::new("glider.rle")), Some(Format::Rle));
+        assert_eq!(Format::from_extension(Path::new("patterns/Glider.CELLS")), Some(Format::Plaintext));
+        assert_eq!(Format::from_extension(Path::new("glider.txt")), None);
+        assert_eq!(Format::from_extension(Path::new("glider.lif")), None);
+        assert_eq!(Format::from_extension(Path::new("metapixel.mc")), Some(Format::Macrocell));
+        assert_eq!(Format::from_extension(Path::new("glider")), None);
+    }
+
+    // Test Format::detect
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(Format::detect("#N Glider\nx = 3, y = 3\nbo$2bo$3o!"), Some(Format::Rle));
+        assert_eq!(Format::detect("\nx = 3, y = 1\n3o!"), Some(Format::Rle));
+        assert_eq!(Format::detect("!Name: Glider\n.O\n..O\nOOO"), Some(Format::Plaintext));
+        assert_eq!(Format::detect("..O\nOOO"), Some(Format::Plaintext));
+        assert_eq!(Format::detect("#Life 1.05\n#D Glider\n#P -1 -1\n.*"), Some(Format::Life105));
+        assert_eq!(Format::detect("#Life 1.06\n0 -1"), Some(Format::Life106));
+        assert_eq!(Format::detect("[M2] (golly 4.2)\n#R B3/S23\n.*$..*$***$"), Some(Format::Macrocell));
+        assert_eq!(Format::detect("Hello"), None);
+        assert_eq!(Format::detect(""), None);
+    }
+
+    // Test Format.parse and Format.export
+
+    #[test]
+    fn test_conversion() {
+        let (c, _, comments) = Format::Plaintext.parse(String::from("!Name: Glider\n.O\n..O\nOOO\n")).unwrap();
+
+        assert_eq!(comments, vec!["!Name: Glider"]);
+        assert_eq!(Format::Rle.export(&c, 0, &[Format::Rle.comment("Glider")]).unwrap(), "#C Generated by ALife\n#C Glider\nx = 3, y = 3, rule = B3/S23\nbo$2bo$3o!");
+
+        let (c, _, _) = Format::Rle.parse(String::from("x = 3, y = 3\nbo$2bo$3o!")).unwrap();
+        assert_eq!(Format::Plaintext.export(&c, 0, &[]).unwrap(), "!Generated by ALife\n.O.\n..O\nOOO\n");
+
+        // The position and the generation are kept by the RLE format
+        let (c, generation, _) = Format::Rle.parse(String::from("#CXRLE Pos=-1,-1 Gen=12\nx = 3, y = 3\nbo$2bo$3o!")).unwrap();
+        assert_eq!((c.origin(), generation), ((-1, -1), 12));
+        assert_eq!(Format::Rle.export(&c, 16, &[]).unwrap(), "#CXRLE Pos=-1,-1 Gen=16\n#C Generated by ALife\nx = 3, y = 3, rule = B3/S23\nbo$2bo$3o!");
+    }
+
+    #[test]
+    fn test_export_b0() {
+        // The background is alive at odd generations, only the formats with a generation keep its phase
+        let (mut c, _, _) = Format::Rle.parse(String::from("x = 2, y = 1, rule = B0/S\n2o!")).unwrap();
+        c.generate_next();
+
+        assert!(Format::Rle.export(&c, 1, &[]).is_ok());
+        assert!(Format::Macrocell.export(&c, 1, &[]).is_ok());
+        for format in [Format::Plaintext, Format::Life105, Format::Life106] {
+            assert!(matches!(format.export(&c, 1, &[]), Err(Error::Export(_))));
+        }
+        c.generate_next();
+        assert!(Format::Plaintext.export(&c, 2, &[]).is_ok());
+    }
+}
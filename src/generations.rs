@@ -0,0 +1,167 @@
+use std::fmt;
+use std::str::FromStr;
+use crate::rule::Rule;
+
+/// Generations rule: a live cell which does not survive goes through `states - 2` dying states
+/// before it is dead, dying cells being neither counted as neighbours nor reborn.
+/// Written `B2/S/C3` or in Golly's `S/B/C` form (`/2/3`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Generations {
+    rule: Rule,
+    states: u16,
+}
+
+impl Default for Generations {
+    /// Conway's Game of Life, with no dying state
+    fn default() -> Self {
+        Generations { rule: Rule::default(), states: 2 }
+    }
+}
+
+impl From<Rule> for Generations {
+    fn from(rule: Rule) -> Self {
+        Generations { rule, states: 2 }
+    }
+}
+
+impl Generations {
+    /// Largest number of states, the states being stored in a byte
+    pub const MAX_STATES: u16 = 256;
+
+    fn parse_states(input: &str) -> Result<u16, &'static str> {
+        match input.parse::<u16>() {
+            Ok(n) if (2..=Self::MAX_STATES).contains(&n) => Ok(n),
+            _ => Err("[Rule parser] The number of states must be between 2 and 256"),
+        }
+    }
+
+    // ---------
+
+    /// Parse a rule written as `B2/S/C3`, `/2/3` (survival/birth/states) or as a two-state rule
+    pub fn parse(input: &str) -> Result<Generations, &'static str> {
+        // The base64 characters of a MAP rule are case sensitive and can be slashes
+        if input.trim().starts_with("MAP") {
+            return Ok(Generations::from(Rule::parse(input)?))
+        }
+        let lower = input.trim().to_ascii_lowercase();
+        let parts: Vec<&str> = lower.split('/').collect();
+
+        let generations = match parts[..] {
+            [_, .., last] if last.starts_with(['c', 'g']) => Generations {
+                rule: Rule::parse(&lower[..lower.len() - last.len() - 1])?,
+                states: Self::parse_states(&last[1..])?,
+            },
+            [survival, birth, states] if !lower.starts_with(['b', 's']) => Generations {
+                rule: Rule::parse(&format!("{}/{}", survival, birth))?,
+                states: Self::parse_states(states)?,
+            },
+            _ => Generations::from(Rule::parse(&lower)?),
+        };
+        if generations.states > 2 && generations.rule.larger_than_life().is_some() {
+            return Err("[Rule parser] Larger than Life rules with more than two states are not supported")
+        }
+        if generations.states > 2 && generations.rule.margolus().is_some() {
+            return Err("[Rule parser] Margolus rules with more than two states are not supported")
+        }
+        if generations.states > 2 && generations.rule.has_b0() {
+            return Err("[Rule parser] Generations rules with B0 are not supported")
+        }
+        Ok(generations)
+    }
+
+    /// Birth and survival conditions of the live cells
+    pub fn rule(&self) -> &Rule {
+        &self.rule
+    }
+
+    /// Number of states, the dead and live ones included
+    pub fn states(&self) -> u16 {
+        self.states
+    }
+
+    /// Compute the next state of a cell from its state and its 3x3 neighbourhood of live cells (state 1),
+    /// bit `3 * y + x` being set when the cell at (x, y) is alive
+    pub fn next_state(&self, state: u8, neighbourhood: usize) -> u8 {
+        match state {
+            0 => self.rule.next_state_of(neighbourhood) as u8,
+            1 if self.rule.next_state_of(neighbourhood) => 1,
+            // Dying cells get older until they reach the last state
+            _ if state as u16 + 1 < self.states => state + 1,
+            _ => 0,
+        }
+    }
+}
+
+impl fmt::Display for Generations {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.states == 2 {
+            write!(f, "{}", self.rule)
+        } else {
+            write!(f, "{}/C{}", self.rule, self.states)
+        }
+    }
+}
+
+impl FromStr for Generations {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Generations::parse(s)
+    }
+}
+
+
+// --------
+// Tests
+// --------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Test Generations::parse
+
+    #[test]
+    fn test_parse_1() {
+        // Brian's Brain
+        let rule = Generations::parse("B2/S/C3").unwrap();
+        assert_eq!(rule.states(), 3);
+        assert_eq!(rule.to_string(), "B2/S/C3");
+        assert_eq!(Generations::parse("/2/3").unwrap(), rule);
+    }
+
+    #[test]
+    fn test_parse_2() {
+        // Star Wars
+        let rule = Generations::parse("345/2/4").unwrap();
+        assert_eq!(rule.to_string(), "B2/S345/C4");
+        assert_eq!(Generations::parse("b2/s345/g4").unwrap(), rule);
+    }
+
+    #[test]
+    fn test_parse_3() {
+        assert_eq!(Generations::parse("B3/S23").unwrap(), Generations::default());
+        assert_eq!(Generations::parse("23/3").unwrap().to_string(), "B3/S23");
+        assert!(Generations::parse("B2/S/C1").is_err());
+        assert!(Generations::parse("B2/S/C257").is_err());
+        assert!(Generations::parse("WireWorld").is_err());
+        assert!(Generations::parse("R2,C0,M0,S1,B1,NM/C3").is_err());
+        assert!(Generations::parse("B02/S/C3").is_err());
+        assert_eq!(Generations::parse("Critters").unwrap().to_string(), "Critters");
+        assert!(Generations::parse("BBM/C3").is_err());
+    }
+
+    // Test Generations.next_state
+
+    #[test]
+    fn test_next_state() {
+        let rule = Generations::parse("B2/S3/C4").unwrap();
+        assert_eq!(rule.next_state(0, 0b000_000_011), 1);
+        assert_eq!(rule.next_state(0, 0b000_000_111), 0);
+        assert_eq!(rule.next_state(1, 0b000_010_111), 1);
+        assert_eq!(rule.next_state(1, 0b000_010_011), 2);
+        assert_eq!(rule.next_state(2, 0b000_000_011), 3);
+        assert_eq!(rule.next_state(3, 0b000_000_011), 0);
+        assert_eq!(Generations::default().next_state(1, 0b000_010_001), 0);
+    }
+}
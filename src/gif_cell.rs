@@ -0,0 +1,270 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::ops::RangeInclusive;
+use std::path::Path;
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::{Delay, DynamicImage, Frame};
+use crate::cell_map::CellMap;
+use crate::error::Error;
+use crate::img_cell::{Renderable, Viewport};
+
+/// Settings of the animated GIF writer
+#[derive(Debug, Clone)]
+pub struct GifOptions {
+    /// Display time of each frame in milliseconds
+    pub delay: u32,
+    /// Number of times the animation is repeated, forever when None
+    pub loops: Option<u16>,
+    /// Display live cells in white on a black background
+    pub inverted: bool,
+}
+
+impl Default for GifOptions {
+    fn default() -> Self {
+        GifOptions {
+            delay: 100,
+            loops: None,
+            inverted: false,
+        }
+    }
+}
+
+pub struct GifCell {
+    options: GifOptions,
+}
+
+impl GifCell {
+    /// Speed of the colour quantization, the frames only use a few colours
+    const ENCODER_SPEED: i32 = 30;
+
+    fn error(message: &str) -> Error {
+        Error::Export(format!("[GIF exporter] {}", message))
+    }
+
+    // ---------
+
+    pub fn new(options: GifOptions) -> GifCell {
+        GifCell { options }
+    }
+
+    /// Render one generation every `step` generations of the range and write them into an animated GIF
+    pub fn generate(&self, c: &CellMap, generations: RangeInclusive<u64>, step: u64, output: &Path) -> Result<(), Error> {
+        if step == 0 {
+            return Err(Self::error("The step between two frames must be positive"))
+        }
+        if generations.is_empty() {
+            return Err(Self::error("The range of generations is empty"))
+        }
+
+        // A first run finds the viewport, the frames are then encoded as they are computed
+        let mut writer = self.start(Viewport::of_generations(c, &generations), output)?;
+        let mut c = c.clone();
+        for _ in 0..*generations.start() {
+            c.generate_next();
+        }
+        writer.write_frame(&c)?;
+        let mut generation = *generations.start();
+        while generation + step <= *generations.end() {
+            for _ in 0..step {
+                c.generate_next();
+            }
+            generation += step;
+            writer.write_frame(&c)?;
+        }
+        writer.finish()
+    }
+
+    /// Write generations computed by a simulation into an animated GIF, one frame each
+    pub fn generate_frames(&self, frames: &[impl Renderable], output: &Path) -> Result<(), Error> {
+        // The same viewport for every frame keeps the size of the image stable
+        let viewport = Viewport::of_frames(frames).ok_or_else(|| Self::error("There is no generation to render"))?;
+        let mut writer = self.start(viewport, output)?;
+        for c in frames {
+            writer.write_frame(c)?;
+        }
+        writer.finish()
+    }
+
+    /// Create the GIF file, every frame drawing the same viewport
+    pub fn start(&self, viewport: Viewport, output: &Path) -> Result<GifWriter<'_>, Error> {
+        let file = File::create(output).map_err(|source| Error::Io { path: output.to_path_buf(), source })?;
+        let file = BufWriter::new(file);
+        let mut encoder = GifEncoder::new_with_speed(file, Self::ENCODER_SPEED);
+        encoder.set_repeat(match self.options.loops {
+            Some(n) => Repeat::Finite(n),
+            None => Repeat::Infinite,
+        })?;
+        Ok(GifWriter { gif: self, viewport, encoder })
+    }
+}
+
+/// Animated GIF being written, one frame at a time
+pub struct GifWriter<'a> {
+    gif: &'a GifCell,
+    viewport: Viewport,
+    encoder: GifEncoder<BufWriter<File>>,
+}
+
+impl GifWriter<'_> {
+    /// Render a generation and append it to the GIF
+    pub fn write_frame<F: Renderable + ?Sized>(&mut self, c: &F) -> Result<(), Error> {
+        let delay = Delay::from_numer_denom_ms(self.gif.options.delay, 1);
+        let img = c.render(&self.viewport, Some(self.gif.options.inverted)).into_image();
+        let frame = Frame::from_parts(DynamicImage::ImageRgb8(img).to_rgba8(), 0, 0, delay);
+        Ok(self.encoder.encode_frame(frame)?)
+    }
+
+    /// End the GIF, the encoder writing its trailer when dropped
+    pub fn finish(self) -> Result<(), Error> {
+        drop(self.encoder);
+        Ok(())
+    }
+}
+
+
+// --------
+// Tests
+// --------
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::BufReader;
+    use image::AnimationDecoder;
+    use image::codecs::gif::GifDecoder;
+    use crate::rule_table::RuleTable;
+    use crate::state_map::{StateMap, StateRule};
+    use super::*;
+
+    const PATH: &str = "tests/samples";
+
+    /// Test init before each
+    fn initialize(path: &str) {
+        let path = Path::new(path);
+        if !path.exists() {
+            fs::create_dir_all(path).expect("Failed to create directories");
+        } else if !path.is_dir() {
+            panic!("Path already exists but it's not a directory");
+        }
+    }
+
+    fn decode(path: &str) -> Vec<Frame> {
+        let file = BufReader::new(File::open(path).unwrap());
+        GifDecoder::new(file).unwrap().into_frames().collect_frames().unwrap()
+    }
+
+    // Test GifCell.generate
+
+    #[test]
+    fn test_generate_1() {
+        initialize(PATH);
+        let path = PATH.to_string() + "/gif_cell_test_1.gif";
+        let glider = CellMap::new(vec![
+            vec![false, true, false],
+            vec![false, false, true],
+            vec![true, true, true]
+        ]).unwrap();
+
+        GifCell::new(GifOptions::default()).generate(&glider, 0..=16, 1, Path::new(&path)).unwrap();
+
+        let frames = decode(&path);
+        assert_eq!(frames.len(), 17);
+        // 7x7 cells of 14 pixels and the grid
+        assert!(frames.iter().all(|f| f.buffer().dimensions() == (7 * 14 + 1, 7 * 14 + 1)));
+        assert_eq!(frames[0].delay().numer_denom_ms(), (100, 1));
+    }
+
+    #[test]
+    fn test_generate_2() {
+        initialize(PATH);
+        let path = PATH.to_string() + "/gif_cell_test_2.gif";
+        let blinker = CellMap::new(vec![vec![true, true, true]]).unwrap();
+        let gif_cell = GifCell::new(GifOptions { delay: 250, loops: Some(2), inverted: true });
+
+        gif_cell.generate(&blinker, 3..=12, 4, Path::new(&path)).unwrap();
+
+        // Generations 3, 7 and 11
+        let frames = decode(&path);
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[2].delay().numer_denom_ms(), (250, 1));
+    }
+
+    // Test GifCell.generate_frames
+
+    #[test]
+    fn test_generate_frames_states() {
+        initialize(PATH);
+        let path = PATH.to_string() + "/gif_cell_test_states.gif";
+        // A spaceship of Brian's Brain, its dying cells are drawn in red
+        let mut s = StateMap::new(vec![vec![2, 1], vec![2, 1]]).unwrap();
+        s.set_rule(StateRule::parse("B2/S/C3"));
+        let mut frames = vec![s.clone()];
+        for _ in 0..3 {
+            s.generate_next().unwrap();
+            frames.push(s.clone());
+        }
+
+        GifCell::new(GifOptions::default()).generate_frames(&frames, Path::new(&path)).unwrap();
+
+        // 5x2 cells of 15 pixels and the grid
+        let frames = decode(&path);
+        assert_eq!(frames.len(), 4);
+        assert!(frames.iter().all(|f| f.buffer().dimensions() == (5 * 15 + 1, 2 * 15 + 1)));
+        let [r, g, b, _] = frames[0].buffer().get_pixel(7, 7).0;
+        assert!(r > 150 && g < 100 && b < 100);
+    }
+
+    #[test]
+    fn test_generate_frames_rule_table() {
+        initialize(PATH);
+        let path = PATH.to_string() + "/gif_cell_test_rule_table.gif";
+        // An electron running along a wire of WireWorld, drawn with the colours of the rule file
+        let table = RuleTable::find("WireWorld", Path::new("rules")).unwrap().unwrap();
+        let mut s = StateMap::new(vec![vec![2, 1, 3, 3, 3]]).unwrap();
+        s.set_rule(StateRule::Table(table));
+        let mut frames = vec![s.clone()];
+        for _ in 0..3 {
+            s.generate_next().unwrap();
+            frames.push(s.clone());
+        }
+
+        GifCell::new(GifOptions::default()).generate_frames(&frames, Path::new(&path)).unwrap();
+
+        // The head moves from the second cell to the fifth one
+        let frames = decode(&path);
+        assert_eq!(frames.len(), 4);
+        let [r, g, b, _] = frames[0].buffer().get_pixel(15 + 7, 7).0;
+        assert!(r < 50 && (100..160).contains(&g) && b > 200);
+        let [r, g, b, _] = frames[3].buffer().get_pixel(4 * 15 + 7, 7).0;
+        assert!(r < 50 && (100..160).contains(&g) && b > 200);
+    }
+
+    // Test GifCell.start
+
+    #[test]
+    fn test_start() {
+        initialize(PATH);
+        let path = PATH.to_string() + "/gif_cell_test_start.gif";
+        let gif_cell = GifCell::new(GifOptions::default());
+        let mut c = CellMap::new(vec![vec![true, true, true]]).unwrap();
+
+        // The frames are written as they are computed, all of them drawing the given viewport
+        let mut writer = gif_cell.start(Viewport { x: -1, y: -1, w: 5, h: 3 }, Path::new(&path)).unwrap();
+        for _ in 0..3 {
+            writer.write_frame(&c).unwrap();
+            c.generate_next();
+        }
+        writer.finish().unwrap();
+
+        let frames = decode(&path);
+        assert_eq!(frames.len(), 3);
+        assert!(frames.iter().all(|f| f.buffer().dimensions() == (5 * 15 + 1, 3 * 15 + 1)));
+    }
+
+    #[test]
+    fn test_generate_3() {
+        let blinker = CellMap::new(vec![vec![true, true, true]]).unwrap();
+
+        assert!(GifCell::new(GifOptions::default()).generate(&blinker, 0..=10, 0, Path::new("output.gif")).is_err());
+    }
+}
@@ -0,0 +1,814 @@
+use std::collections::HashMap;
+use std::mem;
+use crate::cell_map::CellMap;
+use crate::error::Error;
+use crate::macrocell::{MacroNode, Macrocell};
+use crate::rule::Rule;
+use crate::topology::Topology;
+
+/// Index of a node in the arena of a HashLife universe
+type NodeId = usize;
+
+const DEAD: NodeId = 0;
+const ALIVE: NodeId = 1;
+
+/// Node of the quadtree, a square of 2^level cells per side
+#[derive(Clone, Copy)]
+struct Node {
+    level: u8,
+    population: u64,
+    nw: NodeId,
+    ne: NodeId,
+    sw: NodeId,
+    se: NodeId,
+}
+
+/// Quadtree universe computing generations with the HashLife algorithm.
+/// Nodes are canonicalised so that identical squares are stored once and
+/// the result of advancing a node is memoised.
+pub struct HashLife {
+    nodes: Vec<Node>,
+    canonical: HashMap<[NodeId; 4], NodeId>,
+    results: HashMap<(NodeId, u8), NodeId>,
+    /// Number of nodes triggering the next garbage collection
+    max_nodes: usize,
+    empty: Vec<NodeId>,
+    root: NodeId,
+    /// Coordinates of the top left corner of the root
+    x: i64,
+    y: i64,
+    generation: i64,
+    rule: Rule,
+}
+
+impl HashLife {
+    /// Largest level of the root, whose coordinates stay in 64 bits integers
+    const MAX_LEVEL: u8 = 63;
+    /// Number of nodes above which the nodes out of the pattern and the memoised results are first dropped
+    const MAX_NODES: usize = 1 << 22;
+
+    fn new(rule: Rule) -> Result<HashLife, Error> {
+        if rule.margolus().is_some() {
+            return Err(Error::Engine("[HashLife] Margolus rules are not supported"))
+        }
+        if rule.has_b0() {
+            return Err(Error::Engine("[HashLife] Rules with B0 are not supported"))
+        }
+        if rule.larger_than_life().is_some() {
+            return Err(Error::Engine("[HashLife] Larger than Life rules are not supported"))
+        }
+        let leaf = |population| Node { level: 0, population, nw: DEAD, ne: DEAD, sw: DEAD, se: DEAD };
+        Ok(HashLife {
+            nodes: vec![leaf(0), leaf(1)],
+            canonical: HashMap::new(),
+            results: HashMap::new(),
+            max_nodes: Self::MAX_NODES,
+            empty: vec![DEAD],
+            root: DEAD,
+            x: 0,
+            y: 0,
+            generation: 0,
+            rule,
+        })
+    }
+
+    /// Canonical node made of four children of the same level
+    fn join(&mut self, nw: NodeId, ne: NodeId, sw: NodeId, se: NodeId) -> NodeId {
+        if let Some(&id) = self.canonical.get(&[nw, ne, sw, se]) {
+            return id
+        }
+        let population = [nw, ne, sw, se].iter()
+            .fold(0u64, |acc, &n| acc.saturating_add(self.nodes[n].population));
+        let id = self.nodes.len();
+        self.nodes.push(Node { level: self.nodes[nw].level + 1, population, nw, ne, sw, se });
+        self.canonical.insert([nw, ne, sw, se], id);
+        id
+    }
+
+    /// Canonical empty node of the given level
+    fn empty(&mut self, level: u8) -> NodeId {
+        while self.empty.len() <= level as usize {
+            let e = *self.empty.last().unwrap();
+            let id = self.join(e, e, e, e);
+            self.empty.push(id);
+        }
+        self.empty[level as usize]
+    }
+
+    /// Surround the root with empty cells, doubling its size and keeping it centered
+    fn expand(&mut self) -> Result<(), Error> {
+        let root = self.nodes[self.root];
+        if root.level >= Self::MAX_LEVEL {
+            return Err(Error::Engine("[HashLife] The pattern grows out of the largest universe"))
+        }
+        let e = self.empty(root.level - 1);
+        let nw = self.join(e, e, e, root.nw);
+        let ne = self.join(e, e, root.ne, e);
+        let sw = self.join(e, root.sw, e, e);
+        let se = self.join(root.se, e, e, e);
+        self.root = self.join(nw, ne, sw, se);
+        let shift = 1i64 << (root.level - 1);
+        self.x -= shift;
+        self.y -= shift;
+        Ok(())
+    }
+
+    /// Copy the nodes of the root into a new arena, dropping the other nodes and the memoised results
+    fn collect_garbage(&mut self) {
+        let old = mem::take(&mut self.nodes);
+        self.nodes.extend_from_slice(&old[..=ALIVE]);
+        self.canonical.clear();
+        self.results.clear();
+        self.empty = vec![DEAD];
+        self.root = self.copy(&old, self.root, &mut HashMap::new());
+        // A large pattern keeps many nodes, it is not copied again on every jump
+        self.max_nodes = self.max_nodes.max(2 * self.nodes.len());
+    }
+
+    /// Copy a node of the old arena and its children, `ids` giving the nodes already copied
+    fn copy(&mut self, old: &[Node], n: NodeId, ids: &mut HashMap<NodeId, NodeId>) -> NodeId {
+        if n <= ALIVE {
+            return n
+        }
+        if let Some(&id) = ids.get(&n) {
+            return id
+        }
+        let node = old[n];
+        let [nw, ne, sw, se] = [node.nw, node.ne, node.sw, node.se].map(|c| self.copy(old, c, ids));
+        let id = self.join(nw, ne, sw, se);
+        ids.insert(n, id);
+        id
+    }
+
+    /// Node of the level of `a` whose top left corner is at (x, y) in the square made of `a`, `b` on its right,
+    /// `c` below it and `d`, with x and y smaller than the size of `a`. `windows` holds the nodes already built
+    fn window(&mut self, [a, b, c, d]: [NodeId; 4], x: u64, y: u64, windows: &mut HashMap<[NodeId; 4], NodeId>) -> NodeId {
+        if x == 0 && y == 0 {
+            return a
+        }
+        if [a, b, c, d].iter().all(|&n| self.nodes[n].population == 0) {
+            return a
+        }
+        if let Some(&id) = windows.get(&[a, b, c, d]) {
+            return id
+        }
+        let [a_, b_, c_, d_] = [a, b, c, d].map(|n| self.nodes[n]);
+        let grid = [
+            [a_.nw, a_.ne, b_.nw, b_.ne],
+            [a_.sw, a_.se, b_.sw, b_.se],
+            [c_.nw, c_.ne, d_.nw, d_.ne],
+            [c_.sw, c_.se, d_.sw, d_.se],
+        ];
+        let half = 1u64 << (a_.level - 1);
+        let (j, i) = ((x / half) as usize, (y / half) as usize);
+        let [nw, ne, sw, se] = [(i, j), (i, j + 1), (i + 1, j), (i + 1, j + 1)].map(|(i, j)| {
+            let square = [grid[i][j], grid[i][j + 1], grid[i + 1][j], grid[i + 1][j + 1]];
+            self.window(square, x % half, y % half, windows)
+        });
+        let id = self.join(nw, ne, sw, se);
+        windows.insert([a, b, c, d], id);
+        id
+    }
+
+    /// Expand the root until the pattern fits in the square of the same level centered on (0, 0), and make it the root
+    fn center_on_origin(&mut self) -> Result<(), Error> {
+        let level = self.nodes[self.root].level;
+        let half = 1i128 << (level - 1);
+        if (self.x as i128, self.y as i128) == (-half, -half) {
+            return Ok(())
+        }
+        // The center of the root does not move, the pattern stays within a quarter of the size around it
+        let (cx, cy) = (self.x as i128 + half, self.y as i128 + half);
+        self.expand()?;
+        while cx.abs().max(cy.abs()) > 1i128 << (self.nodes[self.root].level - 2) {
+            self.expand()?;
+        }
+
+        // The root is put beside an empty node, on the side of the origin, and the centered square is taken from both
+        let level = self.nodes[self.root].level;
+        let (size, half) = (1i128 << level, 1i128 << (level - 1));
+        let e = self.empty(level);
+        let mut square = [e; 4];
+        square[2 * (cy > 0) as usize + (cx > 0) as usize] = self.root;
+        let offset = |c: i128| (if c > 0 { size - c } else { -c }) as u64;
+        self.root = self.window(square, offset(cx), offset(cy), &mut HashMap::new());
+        (self.x, self.y) = (-half as i64, -half as i64);
+        Ok(())
+    }
+
+    /// Square of half the size at the center of a node
+    fn centre(&mut self, n: NodeId) -> NodeId {
+        let n = self.nodes[n];
+        let (nw, ne, sw, se) = (self.nodes[n.nw].se, self.nodes[n.ne].sw, self.nodes[n.sw].ne, self.nodes[n.se].nw);
+        self.join(nw, ne, sw, se)
+    }
+
+    /// Advance the center 2x2 square of a 4x4 node by one generation
+    fn step_base(&mut self, n: NodeId) -> NodeId {
+        let mut cells = [[false; 4]; 4];
+        let n = self.nodes[n];
+        for (q, (x0, y0)) in [(n.nw, (0, 0)), (n.ne, (2, 0)), (n.sw, (0, 2)), (n.se, (2, 2))] {
+            let q = self.nodes[q];
+            for (leaf, (dx, dy)) in [(q.nw, (0, 0)), (q.ne, (1, 0)), (q.sw, (0, 1)), (q.se, (1, 1))] {
+                cells[y0 + dy][x0 + dx] = leaf == ALIVE;
+            }
+        }
+
+        let mut next = [DEAD; 4];
+        for (k, (x, y)) in [(1, 1), (2, 1), (1, 2), (2, 2)].into_iter().enumerate() {
+            let mut neighbourhood = 0;
+            for dy in 0..3 {
+                for dx in 0..3 {
+                    if cells[y + dy - 1][x + dx - 1] { neighbourhood |= 1 << (3 * dy + dx) }
+                }
+            }
+            if self.rule.next_state_of(neighbourhood) { next[k] = ALIVE }
+        }
+        self.join(next[0], next[1], next[2], next[3])
+    }
+
+    /// Center of a node of level k advanced by 2^j generations (j <= k - 2)
+    fn step(&mut self, n: NodeId, j: u8) -> NodeId {
+        let node = self.nodes[n];
+        if node.population == 0 {
+            return self.empty(node.level - 1)
+        }
+        if node.level == 2 {
+            return self.step_base(n)
+        }
+        if let Some(&r) = self.results.get(&(n, j)) {
+            return r
+        }
+
+        let (nw, ne, sw, se) = (self.nodes[node.nw], self.nodes[node.ne], self.nodes[node.sw], self.nodes[node.se]);
+        let n01 = self.join(nw.ne, ne.nw, nw.se, ne.sw);
+        let n10 = self.join(nw.sw, nw.se, sw.nw, sw.ne);
+        let n11 = self.join(nw.se, ne.sw, sw.ne, se.nw);
+        let n12 = self.join(ne.sw, ne.se, se.nw, se.ne);
+        let n21 = self.join(sw.ne, se.nw, sw.se, se.sw);
+        let sub = [node.nw, n01, node.ne, n10, n11, n12, node.sw, n21, node.se];
+
+        // At full speed both halves advance 2^(k-3) generations, otherwise only the second one moves
+        let full_speed = j == node.level - 2;
+        let mut r = [DEAD; 9];
+        for (i, &s) in sub.iter().enumerate() {
+            r[i] = if full_speed { self.step(s, node.level - 3) } else { self.centre(s) };
+        }
+
+        let inner_j = if full_speed { node.level - 3 } else { j };
+        let c00 = self.join(r[0], r[1], r[3], r[4]);
+        let c01 = self.join(r[1], r[2], r[4], r[5]);
+        let c10 = self.join(r[3], r[4], r[6], r[7]);
+        let c11 = self.join(r[4], r[5], r[7], r[8]);
+        let s00 = self.step(c00, inner_j);
+        let s01 = self.step(c01, inner_j);
+        let s10 = self.step(c10, inner_j);
+        let s11 = self.step(c11, inner_j);
+        let result = self.join(s00, s01, s10, s11);
+
+        self.results.insert((n, j), result);
+        result
+    }
+
+    /// Build the node of the given level whose top left corner is at (x, y) in the map
+    fn build(&mut self, map: &[Vec<bool>], level: u8, x: usize, y: usize) -> NodeId {
+        if y >= map.len() || x >= map[0].len() {
+            return self.empty(level)
+        }
+        if level == 0 {
+            return if map[y][x] { ALIVE } else { DEAD }
+        }
+        let half = 1 << (level - 1);
+        let nw = self.build(map, level - 1, x, y);
+        let ne = self.build(map, level - 1, x + half, y);
+        let sw = self.build(map, level - 1, x, y + half);
+        let se = self.build(map, level - 1, x + half, y + half);
+        self.join(nw, ne, sw, se)
+    }
+
+    /// Bounding box (min x, min y, max x, max y) of the live cells of a node, relative to its top left corner.
+    /// The boxes are memoised since the same nodes are met many times in large patterns
+    fn node_bounding_box(&self, n: NodeId, boxes: &mut HashMap<NodeId, Option<(i64, i64, i64, i64)>>) -> Option<(i64, i64, i64, i64)> {
+        let node = self.nodes[n];
+        if node.population == 0 {
+            return None
+        }
+        if node.level == 0 {
+            return Some((0, 0, 0, 0))
+        }
+        if let Some(&bounding_box) = boxes.get(&n) {
+            return bounding_box
+        }
+        let half = 1i64 << (node.level - 1);
+        let bounding_box = [(node.nw, 0, 0), (node.ne, half, 0), (node.sw, 0, half), (node.se, half, half)].iter()
+            .filter_map(|&(c, dx, dy)| {
+                self.node_bounding_box(c, boxes).map(|(x0, y0, x1, y1)| (x0 + dx, y0 + dy, x1 + dx, y1 + dy))
+            })
+            .reduce(|a, b| (a.0.min(b.0), a.1.min(b.1), a.2.max(b.2), a.3.max(b.3)));
+        boxes.insert(n, bounding_box);
+        bounding_box
+    }
+
+    /// Lower bound of the number of rows holding live cells in a node, the rows of two nodes side by side
+    /// being at least the rows of one of them
+    fn node_rows(&self, n: NodeId, rows: &mut HashMap<NodeId, u64>) -> u64 {
+        let node = self.nodes[n];
+        if node.population == 0 || node.level == 0 {
+            return node.population
+        }
+        if let Some(&count) = rows.get(&n) {
+            return count
+        }
+        let count = self.node_rows(node.nw, rows).max(self.node_rows(node.ne, rows))
+            + self.node_rows(node.sw, rows).max(self.node_rows(node.se, rows));
+        rows.insert(n, count);
+        count
+    }
+
+    /// Set the live cells of a node placed at (x, y) in a map whose top left corner is at (x0, y0)
+    fn fill(&self, n: NodeId, x: i64, y: i64, origin: (i64, i64), map: &mut [Vec<bool>]) {
+        let node = self.nodes[n];
+        if node.population == 0 {
+            return
+        }
+        if node.level == 0 {
+            map[(y - origin.1) as usize][(x - origin.0) as usize] = true;
+            return
+        }
+        let half = 1i64 << (node.level - 1);
+        self.fill(node.nw, x, y, origin, map);
+        self.fill(node.ne, x + half, y, origin, map);
+        self.fill(node.sw, x, y + half, origin, map);
+        self.fill(node.se, x + half, y + half, origin, map);
+    }
+
+    /// Number in the macrocell nodes of a node, written after its children
+    fn write(&self, n: NodeId, numbers: &mut HashMap<NodeId, usize>, nodes: &mut Vec<MacroNode>) -> usize {
+        let node = self.nodes[n];
+        if node.population == 0 {
+            return 0
+        }
+        if let Some(&number) = numbers.get(&n) {
+            return number
+        }
+        let macro_node = if node.level == Macrocell::LEAF_LEVEL {
+            let mut rows = [0u8; 8];
+            self.fill_leaf(n, 0, 0, &mut rows);
+            MacroNode::Leaf(rows)
+        } else {
+            let children = [node.nw, node.ne, node.sw, node.se].map(|c| self.write(c, numbers, nodes));
+            MacroNode::Node { level: node.level, children }
+        };
+        nodes.push(macro_node);
+        numbers.insert(n, nodes.len());
+        nodes.len()
+    }
+
+    /// Set the bits of the live cells of a node placed at (x, y) in the rows of a macrocell leaf
+    fn fill_leaf(&self, n: NodeId, x: usize, y: usize, rows: &mut [u8; 8]) {
+        let node = self.nodes[n];
+        if node.population == 0 {
+            return
+        }
+        if node.level == 0 {
+            rows[y] |= 1 << x;
+            return
+        }
+        let half = 1 << (node.level - 1);
+        self.fill_leaf(node.nw, x, y, rows);
+        self.fill_leaf(node.ne, x + half, y, rows);
+        self.fill_leaf(node.sw, x, y + half, rows);
+        self.fill_leaf(node.se, x + half, y + half, rows);
+    }
+
+    /// First row at or after `row` holding live cells in a node whose top row is `y`
+    fn next_row(&self, n: NodeId, y: i64, row: i64) -> Option<i64> {
+        let node = self.nodes[n];
+        // The bottom of the largest nodes does not fit in 64 bits
+        if node.population == 0 || (y as i128 + (1i128 << node.level)) <= row as i128 {
+            return None
+        }
+        if node.level == 0 {
+            return Some(y)
+        }
+        let half = 1i64 << (node.level - 1);
+        let top = [self.next_row(node.nw, y, row), self.next_row(node.ne, y, row)];
+        top.into_iter().flatten().min().or_else(|| {
+            let bottom = [self.next_row(node.sw, y + half, row), self.next_row(node.se, y + half, row)];
+            bottom.into_iter().flatten().min()
+        })
+    }
+
+    /// Append the runs of live cells (first x, last x) of a row to `runs`, from a node placed at (x, y)
+    fn row_runs(&self, n: NodeId, x: i64, y: i64, row: i64, runs: &mut Vec<(i64, i64)>) {
+        let node = self.nodes[n];
+        if node.population == 0 || row < y || (y as i128 + (1i128 << node.level)) <= row as i128 {
+            return
+        }
+        if node.level == 0 || (node.level < 32 && node.population == 1 << (2 * node.level)) {
+            // A full node is a single run, merged with the previous one when they touch
+            let last_x = x + ((1i64 << node.level) - 1);
+            match runs.last_mut() {
+                Some(run) if run.1 + 1 == x => run.1 = last_x,
+                _ => runs.push((x, last_x)),
+            }
+            return
+        }
+        let half = 1i64 << (node.level - 1);
+        if row < y + half {
+            self.row_runs(node.nw, x, y, row, runs);
+            self.row_runs(node.ne, x + half, y, row, runs);
+        } else {
+            self.row_runs(node.sw, x, y + half, row, runs);
+            self.row_runs(node.se, x + half, y + half, row, runs);
+        }
+    }
+
+    // ---------------------
+
+    /// Bounding box of the live cells as (min x, min y, max x, max y), None if all cells are dead
+    pub fn bounding_box(&self) -> Option<(i64, i64, i64, i64)> {
+        self.node_bounding_box(self.root, &mut HashMap::new())
+            .map(|(x0, y0, x1, y1)| (self.x + x0, self.y + y0, self.x + x1, self.y + y1))
+    }
+
+    /// Lower bound of the number of rows holding live cells, computed without visiting them
+    pub fn min_rows(&self) -> u64 {
+        self.node_rows(self.root, &mut HashMap::new())
+    }
+
+    /// Give the rows holding live cells to `visit` from top to bottom, with their runs of live cells (first x, last x)
+    /// from left to right, so that a pattern too large for a cell map can be encoded
+    pub fn visit_rows(&self, mut visit: impl FnMut(i64, &[(i64, i64)]) -> Result<(), Error>) -> Result<(), Error> {
+        let mut runs = Vec::new();
+        let mut row = Some(self.y);
+        while let Some(y) = row.and_then(|row| self.next_row(self.root, self.y, row)) {
+            runs.clear();
+            self.row_runs(self.root, self.x, self.y, y, &mut runs);
+            visit(y, &runs)?;
+            row = y.checked_add(1);
+        }
+        Ok(())
+    }
+
+    /// Rule of the universe
+    pub fn rule(&self) -> &Rule {
+        &self.rule
+    }
+
+    /// Create a universe from a cell map, keeping its coordinates
+    pub fn from_cell_map(c: &CellMap) -> Result<HashLife, Error> {
+        if *c.topology() != Topology::Plane {
+            return Err(Error::Engine("[HashLife] Only the infinite plane is supported"))
+        }
+        let mut universe = Self::new(*c.rule())?;
+        let mut level = 3;
+        while (1u64 << level) < c.width().max(c.height()) as u64 {
+            level += 1;
+        }
+        universe.root = universe.build(c.cells(), level, 0, 0);
+        (universe.x, universe.y) = c.origin();
+        Ok(universe)
+    }
+
+    /// Create a universe from the quadtree of a macrocell file, whose root is centered on (0, 0).
+    /// The pattern does not need to fit in a cell map
+    pub fn from_macrocell(m: &Macrocell) -> Result<HashLife, Error> {
+        Self::read_macrocell(m, *m.rule())
+    }
+
+    /// Create a universe from the quadtree of a macrocell file, simulated with another rule than the one of the file
+    pub(crate) fn read_macrocell(m: &Macrocell, rule: Rule) -> Result<HashLife, Error> {
+        let mut universe = Self::new(rule)?;
+        let mut ids: Vec<NodeId> = Vec::with_capacity(m.nodes().len());
+        for node in m.nodes() {
+            let id = match *node {
+                MacroNode::Leaf(rows) => {
+                    let cells: Vec<Vec<bool>> = rows.iter().map(|r| (0..8).map(|x| r >> x & 1 == 1).collect()).collect();
+                    universe.build(&cells, Macrocell::LEAF_LEVEL, 0, 0)
+                }
+                MacroNode::Node { level, children } => {
+                    let [nw, ne, sw, se] = children.map(|c| if c == 0 { universe.empty(level - 1) } else { ids[c - 1] });
+                    universe.join(nw, ne, sw, se)
+                }
+            };
+            ids.push(id);
+        }
+        universe.root = match ids.last() {
+            Some(&root) => root,
+            None => universe.empty(Macrocell::LEAF_LEVEL),
+        };
+        let half = 1i64 << (universe.nodes[universe.root].level - 1);
+        (universe.x, universe.y) = (-half, -half);
+        universe.generation = m.generation();
+        Ok(universe)
+    }
+
+    /// Quadtree of the universe in the macrocell format, whose root is centered on (0, 0) to keep the position
+    /// of the pattern. Fails if the pattern is too far from the origin
+    pub fn macrocell(&mut self, comments: &[String]) -> Result<Macrocell, Error> {
+        self.center_on_origin()?;
+        let (mut numbers, mut nodes) = (HashMap::new(), Vec::new());
+        // The root is never smaller than a leaf of 8x8 cells
+        self.write(self.root, &mut numbers, &mut nodes);
+        Ok(Macrocell::from_nodes(nodes, self.rule, self.generation, comments))
+    }
+
+    /// Number of generations computed since the creation of the universe, added to the one of the macrocell file
+    pub fn generation(&self) -> i64 {
+        self.generation
+    }
+
+    /// Change the number of the current generation
+    pub fn set_generation(&mut self, generation: i64) {
+        self.generation = generation;
+    }
+
+    /// Number of live cells
+    pub fn population(&self) -> u64 {
+        self.nodes[self.root].population
+    }
+
+    /// Advance the universe by `generations` generations, using power-of-two jumps.
+    /// Fails when the jumps or the pattern outgrow the largest universe
+    pub fn run(&mut self, generations: u64) -> Result<(), Error> {
+        if generations >> (Self::MAX_LEVEL - 2) != 0 || self.generation.checked_add(generations as i64).is_none() {
+            return Err(Error::Engine("[HashLife] Too many generations to compute at once"))
+        }
+        for j in 0..64u8 {
+            if generations >> j & 1 == 1 {
+                self.step_pow2(j)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Advance the universe by 2^j generations
+    pub fn step_pow2(&mut self, j: u8) -> Result<(), Error> {
+        if self.nodes.len() > self.max_nodes {
+            self.collect_garbage();
+        }
+        // The pattern must stay in the center quarter, far enough from the result borders
+        loop {
+            let level = self.nodes[self.root].level;
+            let inner = self.centre(self.root);
+            let inner = self.centre(inner);
+            if level >= j + 3 && self.nodes[inner].population == self.population() {
+                break
+            }
+            self.expand()?;
+        }
+        let shift = 1i64 << (self.nodes[self.root].level - 2);
+        self.root = self.step(self.root, j);
+        self.x += shift;
+        self.y += shift;
+        self.generation += 1 << j;
+        Ok(())
+    }
+
+    /// Convert the live part of the universe to a cell map, cropped to the pattern
+    pub fn to_cell_map(&self) -> Result<CellMap, Error> {
+        let (min_x, min_y, max_x, max_y) = self.bounding_box()
+            .unwrap_or((self.x, self.y, self.x, self.y));
+        let (w, h) = ((max_x - min_x + 1) as u64, (max_y - min_y + 1) as u64);
+        if w.saturating_mul(h) > CellMap::MAX_CELLS {
+            return Err(Error::Engine("[HashLife] The pattern is too large to be converted to a cell map"))
+        }
+
+        let mut map = vec![vec![false; w as usize]; h as usize];
+        self.fill(self.root, self.x, self.y, (min_x, min_y), &mut map);
+        let mut c = CellMap::new(map)?;
+        c.set_rule(self.rule);
+        c.set_origin((min_x, min_y));
+        Ok(c)
+    }
+}
+
+
+// --------
+// Tests
+// --------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wolfram::Wolfram;
+
+    fn glider() -> CellMap {
+        CellMap::new(vec![
+            vec![false, true, false],
+            vec![false, false, true],
+            vec![true, true, true],
+        ]).unwrap()
+    }
+
+    // Test HashLife::from_cell_map
+
+    #[test]
+    fn test_from_cell_map() {
+        let h = HashLife::from_cell_map(&glider()).unwrap();
+
+        assert_eq!(h.population(), 5);
+        assert_eq!(h.nodes[h.root].level, 3);
+        assert_eq!(h.to_cell_map().unwrap().cells(), glider().cells());
+    }
+
+    #[test]
+    fn test_from_cell_map_b0() {
+        let mut c = glider();
+        c.set_rule(Rule::parse("B03/S23").unwrap());
+
+        assert!(HashLife::from_cell_map(&c).is_err());
+    }
+
+    #[test]
+    fn test_from_cell_map_larger_than_life() {
+        let mut c = glider();
+        c.set_rule(Rule::parse("R5,C0,M1,S34..58,B34..45,NM").unwrap());
+
+        assert!(HashLife::from_cell_map(&c).is_err());
+    }
+
+    // Test HashLife::from_macrocell and HashLife.macrocell
+
+    #[test]
+    fn test_macrocell() {
+        for origin in [(-1, -1), (0, 0), (100, -37), (-5000, 123_456)] {
+            let mut c = glider();
+            c.set_origin(origin);
+            let mut h = HashLife::from_cell_map(&c).unwrap();
+
+            let m = h.macrocell(&[]).unwrap();
+            let back = HashLife::from_macrocell(&Macrocell::parse(m.export()).unwrap()).unwrap();
+
+            assert_eq!(back.population(), 5);
+            let back = back.to_cell_map().unwrap();
+            assert_eq!(back.cells(), glider().cells());
+            assert_eq!(back.origin(), origin);
+        }
+    }
+
+    #[test]
+    fn test_macrocell_too_large() {
+        // Two gliders 2^40 cells apart cannot be converted to a cell map but can be simulated
+        let mut content = String::from("[M2]\n#G 7\n.*$..*$***$\n");
+        content += "4 1 0 0 0\n";
+        for level in 5..=40 {
+            content += &format!("{} {} 0 0 0\n", level, level - 3);
+        }
+        content += "41 38 0 0 38\n";
+        let m = Macrocell::parse(content).unwrap();
+        let mut h = HashLife::from_macrocell(&m).unwrap();
+
+        h.run(1 << 10).unwrap();
+
+        assert_eq!(h.generation(), 7 + (1 << 10));
+        assert_eq!(h.population(), 10);
+        assert!(h.to_cell_map().is_err());
+        assert!(m.to_cell_map().is_err());
+    }
+
+    // Test HashLife.run
+
+    #[test]
+    fn test_run_against_cell_map() {
+        // R-pentomino, compared with the naive engine
+        let mut c = CellMap::new(vec![
+            vec![false, true, true],
+            vec![true, true, false],
+            vec![false, true, false],
+        ]).unwrap();
+        let mut h = HashLife::from_cell_map(&c).unwrap();
+
+        for _ in 0..100 {
+            c.generate_next();
+        }
+        h.run(100).unwrap();
+
+        assert_eq!(h.generation(), 100);
+        assert_eq!(h.to_cell_map().unwrap().cells(), c.auto_crop().cells());
+    }
+
+    #[test]
+    fn test_run_rule() {
+        // HighLife replicator
+        let mut c = CellMap::new(vec![
+            vec![false, false, true, true, true],
+            vec![false, true, false, false, true],
+            vec![true, false, false, false, true],
+            vec![true, false, false, true, false],
+            vec![true, true, true, false, false],
+        ]).unwrap();
+        c.set_rule(Rule::parse("B36/S23").unwrap());
+        let mut h = HashLife::from_cell_map(&c).unwrap();
+
+        for _ in 0..37 {
+            c.generate_next();
+        }
+        h.run(37).unwrap();
+
+        assert_eq!(h.to_cell_map().unwrap().rule().to_string(), "B36/S23");
+        assert_eq!(h.to_cell_map().unwrap().cells(), c.auto_crop().cells());
+    }
+
+    #[test]
+    fn test_run_isotropic() {
+        // R-pentomino under an isotropic non-totalistic rule, compared with the naive engine
+        let mut c = CellMap::new(vec![
+            vec![false, true, true],
+            vec![true, true, false],
+            vec![false, true, false],
+        ]).unwrap();
+        c.set_rule(Rule::parse("B2-a3/S12-k3").unwrap());
+        let mut h = HashLife::from_cell_map(&c).unwrap();
+
+        for _ in 0..50 {
+            c.generate_next();
+        }
+        h.run(50).unwrap();
+
+        assert_eq!(h.to_cell_map().unwrap().rule().to_string(), "B2-a3/S12-k3");
+        assert_eq!(h.to_cell_map().unwrap().cells(), c.auto_crop().cells());
+    }
+
+    #[test]
+    fn test_run_wolfram() {
+        // Spacetime diagram of a Wolfram rule, compared with the one-dimensional engine
+        let rule = Wolfram::parse("W30").unwrap();
+        let mut c = CellMap::new(vec![vec![true]]).unwrap();
+        c.set_rule(rule.into());
+        let mut h = HashLife::from_cell_map(&c).unwrap();
+
+        h.run(64).unwrap();
+
+        let expected = rule.spacetime_from_cell(64).unwrap();
+        assert_eq!(h.to_cell_map().unwrap().cells(), expected.auto_crop().cells());
+        assert_eq!(h.to_cell_map().unwrap().rule().to_string(), "W30");
+    }
+
+    #[test]
+    fn test_run_glider_position() {
+        // A glider moves by one cell diagonally every 4 generations
+        let mut h = HashLife::from_cell_map(&glider()).unwrap();
+
+        h.run(1 << 20).unwrap();
+
+        assert_eq!(h.population(), 5);
+        assert_eq!(h.bounding_box(), Some((1 << 18, 1 << 18, (1 << 18) + 2, (1 << 18) + 2)));
+        assert_eq!(h.to_cell_map().unwrap().cells(), glider().cells());
+        assert_eq!(h.to_cell_map().unwrap().origin(), (1 << 18, 1 << 18));
+    }
+
+    #[test]
+    fn test_run_long() {
+        // A blinker is still there after a billion generations (even number)
+        let c = CellMap::new(vec![vec![true, true, true]]).unwrap();
+        let mut h = HashLife::from_cell_map(&c).unwrap();
+
+        h.run(1_000_000_000).unwrap();
+
+        assert_eq!(h.generation(), 1_000_000_000);
+        assert_eq!(h.to_cell_map().unwrap().cells(), vec![vec![true, true, true]]);
+    }
+
+    #[test]
+    fn test_run_too_far() {
+        let c = CellMap::new(vec![vec![true, true, true]]).unwrap();
+        let mut h = HashLife::from_cell_map(&c).unwrap();
+
+        // The largest jump fits in the largest universe
+        h.run(1 << 60).unwrap();
+
+        assert_eq!(h.to_cell_map().unwrap().cells(), vec![vec![true, true, true]]);
+        assert!(h.run(u64::MAX).is_err());
+        assert!(h.run(1 << 61).is_err());
+        assert_eq!(h.generation(), 1 << 60);
+
+        // A glider leaves the largest universe
+        let mut h = HashLife::from_cell_map(&glider()).unwrap();
+        assert!((0..8).try_for_each(|_| h.run(1 << 60)).is_err());
+    }
+
+    // Test HashLife.collect_garbage
+
+    #[test]
+    fn test_collect_garbage() {
+        let r_pentomino = CellMap::new(vec![
+            vec![false, true, true],
+            vec![true, true, false],
+            vec![false, true, false]]).unwrap();
+        let mut c = r_pentomino.clone();
+        let mut h = HashLife::from_cell_map(&r_pentomino).unwrap();
+        h.max_nodes = 100;
+
+        for _ in 0..64 {
+            h.run(3).unwrap();
+            (0..3).for_each(|_| c.generate_next());
+        }
+
+        // Without collection, the universe keeps about 30000 nodes
+        assert!(h.nodes.len() < 2000);
+        assert_eq!(h.to_cell_map().unwrap().cells(), c.auto_crop().cells());
+        assert_eq!(h.to_cell_map().unwrap().origin(), c.auto_crop().origin());
+    }
+}
@@ -1,10 +1,116 @@
+use std::ops::RangeInclusive;
+use std::path::Path;
 use image::{ImageBuffer, Rgb, RgbImage};
 use imageproc::drawing;
 use imageproc::rect::Rect;
 use crate::cell_map::CellMap;
+use crate::error::Error;
+use crate::rule::Neighbourhood;
+use crate::state_map::{StateMap, StateRule};
 
+/// Image of a generation, live cells being black on a white background unless inverted.
+/// The dying states of multi-state patterns fade from red to the background, and the rows
+/// of hexagonal rules are skewed by half a cell so that each cell touches its six neighbours
 pub struct ImgCell {
-    pub img: RgbImage
+    img: RgbImage
+}
+
+/// Rectangle of cells to draw, in the coordinates of the cell map
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Viewport {
+    pub x: i64,
+    pub y: i64,
+    pub w: u32,
+    pub h: u32,
+}
+
+impl Viewport {
+    /// Viewport covering the whole cell map
+    pub fn of_map(c: &CellMap) -> Viewport {
+        let (x, y) = c.origin();
+        Viewport { x, y, w: c.width(), h: c.height() }
+    }
+
+    /// Smallest viewport containing all the live cells (a single cell if there is none)
+    pub fn of_live_cells(c: &CellMap) -> Viewport {
+        match c.bounding_box() {
+            Some((min_x, min_y, max_x, max_y)) => Viewport {
+                x: min_x,
+                y: min_y,
+                w: (max_x - min_x + 1) as u32,
+                h: (max_y - min_y + 1) as u32,
+            },
+            None => {
+                let (x, y) = c.origin();
+                Viewport { x, y, w: 1, h: 1 }
+            }
+        }
+    }
+
+    /// Smallest viewport containing the pattern during a range of generations
+    pub fn of_generations(c: &CellMap, generations: &RangeInclusive<u64>) -> Viewport {
+        let mut c = c.clone();
+        for _ in 0..*generations.start() {
+            c.generate_next();
+        }
+        let mut viewport = Viewport::of_live_cells(&c);
+        for _ in *generations.start()..*generations.end() {
+            c.generate_next();
+            viewport = viewport.union(&Viewport::of_live_cells(&c));
+        }
+        viewport
+    }
+
+    /// Smallest viewport containing the cells which are not dead of a multi-state map (a single cell if there is none)
+    pub fn of_states(s: &StateMap) -> Viewport {
+        let cropped = s.auto_crop();
+        let (x, y) = cropped.origin();
+        Viewport { x, y, w: cropped.width(), h: cropped.height() }
+    }
+
+    /// Smallest viewport containing the live cells of all the frames of an animation
+    pub fn of_frames(frames: &[impl Renderable]) -> Option<Viewport> {
+        frames.iter()
+            .map(Renderable::viewport)
+            .reduce(|viewport, other| viewport.union(&other))
+    }
+
+    /// Smallest viewport containing both viewports
+    pub fn union(&self, other: &Viewport) -> Viewport {
+        let (x, y) = (self.x.min(other.x), self.y.min(other.y));
+        let max_x = (self.x + self.w as i64).max(other.x + other.w as i64);
+        let max_y = (self.y + self.h as i64).max(other.y + other.h as i64);
+        Viewport { x, y, w: (max_x - x) as u32, h: (max_y - y) as u32 }
+    }
+}
+
+/// Generation drawn in the frames of an animation, two-state or multi-state
+pub trait Renderable {
+    /// Smallest viewport containing the cells which are not dead
+    fn viewport(&self) -> Viewport;
+
+    /// Image of a fixed part of the generation
+    fn render(&self, viewport: &Viewport, inverted: Option<bool>) -> ImgCell;
+}
+
+impl Renderable for CellMap {
+    fn viewport(&self) -> Viewport {
+        Viewport::of_live_cells(self)
+    }
+
+    fn render(&self, viewport: &Viewport, inverted: Option<bool>) -> ImgCell {
+        ImgCell::from_viewport(self, viewport, inverted)
+    }
+}
+
+impl Renderable for StateMap {
+    fn viewport(&self) -> Viewport {
+        Viewport::of_states(self)
+    }
+
+    fn render(&self, viewport: &Viewport, inverted: Option<bool>) -> ImgCell {
+        ImgCell::from_state_viewport(self, viewport, inverted)
+    }
 }
 
 impl ImgCell {
@@ -13,6 +119,8 @@ impl ImgCell {
     const LIMIT_MIN : u32 = 5;
     const LIMIT_MAX : u32 = 100;
     const GRID_LIMIT : u32 = 300;
+    /// Colour of the first dying state of a multi-state pattern
+    const DYING_COLOR: Rgb<u8> = Rgb([220, 40, 30]);
 
     fn calculate_cell_size(w: u32, h: u32) -> u32 {
         let v = w.max(h);
@@ -27,33 +135,107 @@ impl ImgCell {
         }
     }
 
+    /// Rendered image
+    pub fn image(&self) -> &RgbImage {
+        &self.img
+    }
+
+    /// Take the rendered image
+    pub fn into_image(self) -> RgbImage {
+        self.img
+    }
+
+    /// Save the image, its format being chosen from the extension of the path
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        Ok(self.img.save(path)?)
+    }
+
     /// Create an image from a cell map
     pub fn from_cell_map(c: &CellMap, inverted: Option<bool>, cropped: Option<bool>) -> ImgCell {
-        let mut c = c;
-        let grid_color = Rgb([90, 90, 90]);
-
-        let inverted = inverted.unwrap_or(false);
         let cropped = cropped.unwrap_or(true);
-        let cropped_source: CellMap;
 
-        if cropped {
-            cropped_source = c.auto_crop();
-            c = &cropped_source;
+        let viewport = if cropped {
+            Viewport::of_live_cells(c)
+        } else {
+            Viewport::of_map(c)
+        };
+
+        Self::from_viewport(c, &viewport, inverted)
+    }
+
+    /// Colour of a state of a multi-state pattern, the dying states (2 and more) being on a gradient
+    /// from red to the background colour
+    fn state_color(state: u8, states: u16, inverted: bool) -> Rgb<u8> {
+        let (background, foreground) = if inverted {
+            (Rgb([0, 0, 0]), Rgb([255, 255, 255]))
+        } else {
+            (Rgb([255, 255, 255]), Rgb([0, 0, 0]))
+        };
+        match state {
+            0 => background,
+            1 => foreground,
+            _ => {
+                let t = (state - 2) as f64 / (states.max(3) - 1) as f64;
+                let mix = |a: u8, b: u8| (a as f64 + (b as f64 - a as f64) * t).round() as u8;
+                let (c, b) = (Self::DYING_COLOR.0, background.0);
+                Rgb([mix(c[0], b[0]), mix(c[1], b[1]), mix(c[2], b[2])])
+            }
         }
+    }
+
+    /// Create an image of a multi-state pattern cropped to the cells which are not dead
+    pub fn from_state_map(s: &StateMap, inverted: Option<bool>) -> ImgCell {
+        Self::from_state_viewport(s, &Viewport::of_states(s), inverted)
+    }
+
+    /// Create an image of a fixed part of a multi-state map, so that successive generations have the same size
+    pub fn from_state_viewport(s: &StateMap, viewport: &Viewport, inverted: Option<bool>) -> ImgCell {
+        // Without a known rule, the gradient goes up to the largest state of the pattern
+        let states = s.rule().states()
+            .unwrap_or_else(|| s.cells().iter().flatten().max().map_or(2, |&m| m as u16 + 1));
+        let inverted = inverted.unwrap_or(false);
+        let hexagonal = match s.rule() {
+            StateRule::Generations(rule) => rule.rule().neighbourhood() == Neighbourhood::Hexagonal,
+            StateRule::Table(table) => table.neighbourhood() == Neighbourhood::Hexagonal,
+            StateRule::Other(_) => false,
+        };
+        // The colours of a rule file replace the gradient
+        let color = |state: u8| match s.rule() {
+            StateRule::Table(table) => table.color(state).map(Rgb),
+            _ => None,
+        }.unwrap_or_else(|| Self::state_color(state, states, inverted));
+
+        Self::draw(viewport, hexagonal, color(0), |x, y| color(s.state(x, y)))
+    }
+
+    /// Create an image of a fixed part of a cell map, so that successive generations have the same size
+    pub fn from_viewport(c: &CellMap, viewport: &Viewport, inverted: Option<bool>) -> ImgCell {
+        let inverted = inverted.unwrap_or(false);
+        let hexagonal = c.rule().neighbourhood() == Neighbourhood::Hexagonal;
+        let color = |alive: bool| if alive == inverted { Rgb([255, 255, 255]) } else { Rgb([0, 0, 0]) };
+
+        Self::draw(viewport, hexagonal, color(c.background()), |x, y| color(c.is_alive(x, y)))
+    }
 
-        let mut cell_size: u32 = Self::calculate_cell_size(c.w, c.h);
-        let display_grid = c.w.max(c.h) <= Self::GRID_LIMIT;
+    /// Draw the cells of a viewport, `color` giving the colour of the cell at some coordinates.
+    /// Hexagonal grids have each row shifted by half a cell to the left of the row above it
+    fn draw(viewport: &Viewport, hexagonal: bool, background: Rgb<u8>, color: impl Fn(i64, i64) -> Rgb<u8>) -> ImgCell {
+        let grid_color = Rgb([90, 90, 90]);
+
+        let mut cell_size: u32 = Self::calculate_cell_size(viewport.w, viewport.h);
+        let display_grid = viewport.w.max(viewport.h) <= Self::GRID_LIMIT;
         if !display_grid { cell_size = 1 }
+        let shift = |i: u32| if hexagonal { (viewport.h - 1 - i) * cell_size / 2 } else { 0 };
 
-        let mut width = c.w * cell_size;
-        let mut height = c.h * cell_size;
+        let mut width = viewport.w * cell_size + shift(0);
+        let mut height = viewport.h * cell_size;
         if display_grid {
             width += 1;
             height += 1;
         }
 
-        let mut image: RgbImage = ImageBuffer::new(width, height);
-        if display_grid {
+        let mut image: RgbImage = ImageBuffer::from_pixel(width, height, background);
+        if display_grid && !hexagonal {
             drawing::draw_hollow_rect_mut(
                 &mut image,
                 Rect::at(0, 0).of_size(width, height),
@@ -61,14 +243,15 @@ impl ImgCell {
         }
 
         // Dessiner le quadrillage
-        for i in 0..c.actual_generation.len() {
-            for j in 0..c.actual_generation[i].len() {
+        for i in 0..viewport.h {
+            for j in 0..viewport.w {
+                let x0 = (j * cell_size + shift(i)) as i32;
                 if display_grid {
-                    let border = Rect::at(j as i32 * cell_size as i32, i as i32 * cell_size as i32).of_size(cell_size, cell_size);
+                    let border = Rect::at(x0, i as i32 * cell_size as i32).of_size(cell_size + 1, cell_size + 1);
                     drawing::draw_hollow_rect_mut(&mut image, border, grid_color);
                 }
 
-                let mut x = j as i32 * cell_size as i32;
+                let mut x = x0;
                 let mut y = i as i32 * cell_size as i32;
                 let mut cell_width = cell_size;
                 let mut cell_height = cell_size;
@@ -79,11 +262,7 @@ impl ImgCell {
                     cell_height -= 1;
                 }
                 let rect = Rect::at(x, y).of_size(cell_width, cell_height);
-                let color = if c.actual_generation[i][j] == inverted {
-                    Rgb([255, 255, 255])
-                } else {
-                    Rgb([0, 0, 0])
-                };
+                let color = color(viewport.x + j as i64, viewport.y + i as i64);
                 drawing::draw_filled_rect_mut(&mut image, rect, color);
             }
         }
@@ -141,6 +320,112 @@ mod tests {
         v
     }
 
+    // Test Viewport
+
+    #[test]
+    fn test_viewport() {
+        let mut c = CellMap::new(vec![
+            vec![false, false, false],
+            vec![true, true, true],
+            vec![false, false, false],
+        ]).unwrap();
+        let first = Viewport::of_live_cells(&c);
+        c.generate_next();
+        let second = Viewport::of_live_cells(&c);
+
+        assert_eq!(first, Viewport { x: 0, y: 1, w: 3, h: 1 });
+        assert_eq!(second, Viewport { x: 1, y: 0, w: 1, h: 3 });
+        assert_eq!(first.union(&second), Viewport { x: 0, y: 0, w: 3, h: 3 });
+        assert_eq!(Viewport::of_map(&c), Viewport { x: -1, y: 0, w: 5, h: 3 });
+    }
+
+    #[test]
+    fn test_viewport_of_generations() {
+        let glider = CellMap::new(vec![
+            vec![false, true, false],
+            vec![false, false, true],
+            vec![true, true, true]
+        ]).unwrap();
+
+        assert_eq!(Viewport::of_generations(&glider, &(0..=8)), Viewport { x: 0, y: 0, w: 5, h: 5 });
+        assert_eq!(Viewport::of_generations(&glider, &(4..=4)), Viewport { x: 1, y: 1, w: 3, h: 3 });
+    }
+
+    // Test ImgCell::from_viewport
+
+    #[test]
+    fn test_from_viewport() {
+        let c = CellMap::new(vec![vec![true, true, true]]).unwrap();
+        let viewport = Viewport { x: -1, y: -1, w: 5, h: 3 };
+
+        let i = ImgCell::from_viewport(&c, &viewport, None);
+
+        // 15 pixels per cell and a grid line
+        assert_eq!(i.img.dimensions(), (5 * 15 + 1, 3 * 15 + 1));
+        assert_eq!(*i.img.get_pixel(1, 1), Rgb([255, 255, 255]));
+        assert_eq!(*i.img.get_pixel(16, 16), Rgb([0, 0, 0]));
+    }
+
+    #[test]
+    fn test_from_viewport_hexagonal() {
+        let mut c = CellMap::new(vec![vec![true, false], vec![false, false], vec![false, true]]).unwrap();
+        c.set_rule(crate::rule::Rule::parse("B2/S34H").unwrap());
+
+        let i = ImgCell::from_viewport(&c, &Viewport::of_map(&c), None);
+
+        // Each row is shifted by half a cell to the right of the row below it
+        assert_eq!(i.img.dimensions(), (2 * 15 + 15 + 1, 3 * 15 + 1));
+        assert_eq!(*i.img.get_pixel(16, 1), Rgb([0, 0, 0]));
+        assert_eq!(*i.img.get_pixel(1, 1), Rgb([255, 255, 255]));
+        assert_eq!(*i.img.get_pixel(16, 31), Rgb([0, 0, 0]));
+        assert_eq!(*i.img.get_pixel(31, 16), Rgb([255, 255, 255]));
+    }
+
+    // Test ImgCell::from_state_map
+
+    #[test]
+    fn test_from_state_map() {
+        let mut s = StateMap::new(vec![vec![1, 2, 3, 4, 0]]).unwrap();
+        s.set_rule(crate::state_map::StateRule::parse("B2/S/C5"));
+
+        let i = ImgCell::from_state_map(&s, None);
+
+        assert_eq!(i.img.dimensions(), (4 * 15 + 1, 15 + 1));
+        assert_eq!(*i.img.get_pixel(1, 1), Rgb([0, 0, 0]));
+        assert_eq!(*i.img.get_pixel(16, 1), ImgCell::DYING_COLOR);
+        // The older dying states are lighter
+        let (third, fourth) = (i.img.get_pixel(31, 1).0, i.img.get_pixel(46, 1).0);
+        assert!(third[1] > ImgCell::DYING_COLOR.0[1] && fourth[1] > third[1]);
+        assert!(fourth[1] < 255);
+    }
+
+    #[test]
+    fn test_from_state_map_rule_table() {
+        let mut s = StateMap::new(vec![vec![1, 0, 3]]).unwrap();
+        s.set_rule(StateRule::parse("WireWorld").resolve(std::path::Path::new("rules")).unwrap());
+
+        let i = ImgCell::from_state_map(&s, None);
+
+        // Colours of the @COLORS section of the rule file
+        assert_eq!(*i.img.get_pixel(1, 1), Rgb([0, 128, 255]));
+        assert_eq!(*i.img.get_pixel(16, 1), Rgb([48, 48, 48]));
+        assert_eq!(*i.img.get_pixel(31, 1), Rgb([255, 128, 0]));
+    }
+
+    #[test]
+    fn test_from_viewport_b0() {
+        // The cells outside of the map are drawn alive when the background is
+        let mut c = CellMap::new(vec![vec![true]]).unwrap();
+        c.set_rule(crate::rule::Rule::parse("B0/S").unwrap());
+        c.generate_next();
+
+        let i = ImgCell::from_viewport(&c, &Viewport { x: -3, y: 0, w: 3, h: 1 }, None);
+
+        assert_eq!(*i.img.get_pixel(1, 1), Rgb([0, 0, 0]));
+        assert_eq!(*i.img.get_pixel(16, 1), Rgb([0, 0, 0]));
+        assert_eq!(*i.img.get_pixel(31, 1), Rgb([255, 255, 255]));
+    }
+
     // Test ImgCell::from_cell_map
 
     #[test]
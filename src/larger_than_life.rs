@@ -0,0 +1,232 @@
+use std::fmt;
+use std::str::FromStr;
+use crate::rule::Neighbourhood;
+
+/// Larger than Life rule: the neighbours of a cell are all the cells within a range of up to 500 cells,
+/// a cell being born or surviving when their number is in an interval.
+/// Written in Golly's syntax `R5,C0,M1,S34..58,B34..45,NM` or in Kellie Evans' `5,34,45,34,58` (range, birth, survival)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LargerThanLife {
+    range: u32,
+    /// Whether the cell itself is counted among its neighbours
+    middle: bool,
+    survival: (u32, u32),
+    birth: (u32, u32),
+    neighbourhood: Neighbourhood,
+}
+
+impl LargerThanLife {
+    /// Largest range of the neighbourhood
+    pub const MAX_RANGE: u32 = 500;
+
+    fn parse_number(input: &str) -> Result<u32, &'static str> {
+        input.parse().map_err(|_| "[Rule parser] Larger than Life values must be positive numbers")
+    }
+
+    /// Parse an interval written `34..58`, a single number being an interval of one value
+    fn parse_interval(input: &str) -> Result<(u32, u32), &'static str> {
+        let (min, max) = input.split_once("..").unwrap_or((input, input));
+        Ok((Self::parse_number(min)?, Self::parse_number(max)?))
+    }
+
+    /// Parse the Golly's form, each part starting with its letter
+    fn parse_parts(parts: &[&str]) -> Result<LargerThanLife, &'static str> {
+        let (mut range, mut states, mut middle, mut survival, mut birth, mut neighbourhood) = (None, None, None, None, None, None);
+        for part in parts {
+            let value = &part[1.min(part.len())..];
+            let field = match part.chars().next() {
+                Some('r') => range.replace(Self::parse_number(value)?).is_some(),
+                Some('c') => states.replace(Self::parse_number(value)?).is_some(),
+                Some('m') => match value {
+                    "0" => middle.replace(false).is_some(),
+                    "1" => middle.replace(true).is_some(),
+                    _ => return Err("[Rule parser] The middle cell is counted with M1 or not with M0"),
+                },
+                Some('s') => survival.replace(Self::parse_interval(value)?).is_some(),
+                Some('b') => birth.replace(Self::parse_interval(value)?).is_some(),
+                Some('n') => match value {
+                    "m" => neighbourhood.replace(Neighbourhood::Moore).is_some(),
+                    "n" => neighbourhood.replace(Neighbourhood::VonNeumann).is_some(),
+                    "h" => neighbourhood.replace(Neighbourhood::Hexagonal).is_some(),
+                    _ => return Err("[Rule parser] Unknown neighbourhood, expected NM, NN or NH"),
+                },
+                _ => return Err("[Rule parser] Each part of a Larger than Life rule must start with R, C, M, S, B or N"),
+            };
+            if field {
+                return Err("[Rule parser] A part of the Larger than Life rule is given twice")
+            }
+        }
+        if !matches!(states, None | Some(0) | Some(2)) {
+            return Err("[Rule parser] Larger than Life rules with more than two states are not supported")
+        }
+        match (range, survival, birth) {
+            (Some(range), Some(survival), Some(birth)) => Ok(LargerThanLife {
+                range,
+                middle: middle.unwrap_or(false),
+                survival,
+                birth,
+                neighbourhood: neighbourhood.unwrap_or_default(),
+            }),
+            _ => Err("[Rule parser] A Larger than Life rule needs a range R, survival S and birth B"),
+        }
+    }
+
+    // ---------
+
+    /// Parse a rule written `R5,C0,M1,S34..58,B34..45,NM` or `5,34,45,34,58`
+    pub fn parse(input: &str) -> Result<LargerThanLife, &'static str> {
+        let lower = input.trim().to_ascii_lowercase();
+        let parts: Vec<&str> = lower.split(',').map(str::trim).collect();
+
+        let rule = match parts[..] {
+            [range, birth_min, birth_max, survival_min, survival_max] if range.starts_with(|c: char| c.is_ascii_digit()) => LargerThanLife {
+                range: Self::parse_number(range)?,
+                middle: true,
+                survival: (Self::parse_number(survival_min)?, Self::parse_number(survival_max)?),
+                birth: (Self::parse_number(birth_min)?, Self::parse_number(birth_max)?),
+                neighbourhood: Neighbourhood::Moore,
+            },
+            _ => Self::parse_parts(&parts)?,
+        };
+
+        if !(1..=Self::MAX_RANGE).contains(&rule.range) {
+            return Err("[Rule parser] The range of a Larger than Life rule must be between 1 and 500")
+        }
+        if rule.survival.0 > rule.survival.1 || rule.birth.0 > rule.birth.1 {
+            return Err("[Rule parser] The minimum of an interval can't be larger than its maximum")
+        }
+        if rule.survival.1.max(rule.birth.1) > rule.size() {
+            return Err("[Rule parser] Neighbour counts can't be larger than the size of the neighbourhood")
+        }
+        if rule.birth.0 == 0 {
+            return Err("[Rule parser] Larger than Life rules with B0 are not supported")
+        }
+        Ok(rule)
+    }
+
+    /// Distance up to which cells are neighbours
+    pub fn range(&self) -> u32 {
+        self.range
+    }
+
+    /// Whether the cell itself is counted among its neighbours
+    pub fn middle(&self) -> bool {
+        self.middle
+    }
+
+    /// Shape of the neighbourhood: square (Moore), diamond (von Neumann) or hexagon
+    pub fn neighbourhood(&self) -> Neighbourhood {
+        self.neighbourhood
+    }
+
+    /// Number of cells of the neighbourhood, the middle cell included
+    pub fn size(&self) -> u32 {
+        let r = self.range;
+        match self.neighbourhood {
+            Neighbourhood::Moore => (2 * r + 1) * (2 * r + 1),
+            Neighbourhood::VonNeumann => 2 * r * (r + 1) + 1,
+            Neighbourhood::Hexagonal => 3 * r * (r + 1) + 1,
+        }
+    }
+
+    /// Columns of the neighbourhood in the row `dy` (between -range and range), relative to the cell
+    pub fn row_span(&self, dy: i64) -> (i64, i64) {
+        let r = self.range as i64;
+        match self.neighbourhood {
+            Neighbourhood::Moore => (-r, r),
+            Neighbourhood::VonNeumann => (-(r - dy.abs()), r - dy.abs()),
+            // Same skewed rows as the hexagonal neighbourhood of range 1
+            Neighbourhood::Hexagonal => ((dy - r).max(-r), (dy + r).min(r)),
+        }
+    }
+
+    /// Compute the next state of a cell from its state and the number of live cells of its neighbourhood,
+    /// the cell itself included
+    pub fn next_state(&self, alive: bool, count: u32) -> bool {
+        let count = if alive && !self.middle { count - 1 } else { count };
+        let (min, max) = if alive { self.survival } else { self.birth };
+        (min..=max).contains(&count)
+    }
+}
+
+impl fmt::Display for LargerThanLife {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let neighbourhood = match self.neighbourhood {
+            Neighbourhood::Moore => 'M',
+            Neighbourhood::VonNeumann => 'N',
+            Neighbourhood::Hexagonal => 'H',
+        };
+        write!(f, "R{},C0,M{},S{}..{},B{}..{},N{}", self.range, self.middle as u8,
+               self.survival.0, self.survival.1, self.birth.0, self.birth.1, neighbourhood)
+    }
+}
+
+impl FromStr for LargerThanLife {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        LargerThanLife::parse(s)
+    }
+}
+
+
+// --------
+// Tests
+// --------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Test LargerThanLife::parse
+
+    #[test]
+    fn test_parse_1() {
+        // Bosco's rule
+        let rule = LargerThanLife::parse("R5,C0,M1,S34..58,B34..45,NM").unwrap();
+        assert_eq!(rule.range(), 5);
+        assert!(rule.middle());
+        assert_eq!(rule.neighbourhood(), Neighbourhood::Moore);
+        assert_eq!(rule.to_string(), "R5,C0,M1,S34..58,B34..45,NM");
+        assert_eq!(LargerThanLife::parse("5,34,45,34,58").unwrap(), rule);
+        assert_eq!(LargerThanLife::parse("r5,c2,m1,s34..58,b34..45").unwrap(), rule);
+    }
+
+    #[test]
+    fn test_parse_2() {
+        let rule = LargerThanLife::parse("R2,M0,S3,B3..4,NN").unwrap();
+        assert_eq!(rule.to_string(), "R2,C0,M0,S3..3,B3..4,NN");
+        assert_eq!(rule.size(), 13);
+        assert_eq!(LargerThanLife::parse("R2,S1,B1,NH").unwrap().size(), 19);
+    }
+
+    #[test]
+    fn test_parse_3() {
+        assert!(LargerThanLife::parse("R0,S1,B1").is_err());
+        assert!(LargerThanLife::parse("R501,S1,B1").is_err());
+        assert!(LargerThanLife::parse("R1,C3,S1,B1").is_err());
+        assert!(LargerThanLife::parse("R1,S5..4,B1").is_err());
+        assert!(LargerThanLife::parse("R1,S10,B1").is_err());
+        assert!(LargerThanLife::parse("R1,S1,B1,NX").is_err());
+        assert!(LargerThanLife::parse("R1,R2,S1,B1").is_err());
+        assert!(LargerThanLife::parse("R1,S1").is_err());
+        assert!(LargerThanLife::parse("R1,S1,B1,X").is_err());
+        assert!(LargerThanLife::parse("R1,S1,B0..2").is_err());
+    }
+
+    // Test LargerThanLife.next_state
+
+    #[test]
+    fn test_next_state() {
+        let rule = LargerThanLife::parse("R1,M0,S2..3,B3,NM").unwrap();
+        assert!(rule.next_state(false, 3));
+        assert!(!rule.next_state(false, 4));
+        // Without M1, the live cell is not counted
+        assert!(rule.next_state(true, 4));
+        assert!(!rule.next_state(true, 2));
+
+        let rule = LargerThanLife::parse("R1,M1,S2..3,B3,NM").unwrap();
+        assert!(rule.next_state(true, 2));
+        assert!(!rule.next_state(true, 4));
+    }
+}
@@ -1,82 +1,451 @@
-use std::error::Error;
+//! Simulation of the Conway's Game of Life and other life-like cellular automata.
+//!
+//! Patterns are read from RLE, plaintext, Life 1.05/1.06 or macrocell files, evolved by a [`Simulation`] and exported as RLE files,
+//! images, GIF or videos.
+//!
+//! ```
+//! use alife::{Algorithm, Simulation, RLE};
+//!
+//! let rle = RLE::parse(String::from("x = 3, y = 3, rule = B3/S23\nbo$2bo$3o!"))?;
+//! let mut simulation = Simulation::new(rle.to_cell_map()?, Algorithm::Hashlife)?;
+//! simulation.run(1000)?;
+//!
+//! assert_eq!(simulation.population(), 5);
+//! println!("{}", RLE::cell_map_to_file(&simulation.cell_map()?, None));
+//! # Ok::<(), alife::Error>(())
+//! ```
+
 use std::fs;
 use std::fs::File;
 use std::io::Write;
-use clap::{arg, Parser};
+use clap::{ArgGroup, Parser, Subcommand};
 use chrono::Local;
-use crate::img_cell::ImgCell;
-use crate::rle::RLE;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+mod error;
 mod cell_map;
+mod state_map;
+mod bit_cell_map;
+mod hash_life;
+mod rule;
+mod generations;
+mod larger_than_life;
+mod margolus;
+mod noise;
+mod rule_table;
+mod topology;
+mod wolfram;
 mod rle;
+mod cells;
+mod format;
+mod life_105;
+mod life_106;
+mod macrocell;
 mod img_cell;
 mod video_cell;
+mod gif_cell;
+mod analysis;
+mod stop_condition;
+mod simulation;
+
+pub use crate::analysis::{analyze, Analysis, Classification};
+pub use crate::cell_map::CellMap;
+pub use crate::cells::Cells;
+pub use crate::error::{Error, Location, SourceError};
+pub use crate::format::Format;
+pub use crate::generations::Generations;
+pub use crate::gif_cell::{GifCell, GifOptions, GifWriter};
+pub use crate::img_cell::{ImgCell, Renderable, Viewport};
+pub use crate::larger_than_life::LargerThanLife;
+pub use crate::life_105::Life105;
+pub use crate::life_106::Life106;
+pub use crate::macrocell::Macrocell;
+pub use crate::margolus::Margolus;
+pub use crate::noise::Noise;
+pub use crate::rle::RLE;
+pub use crate::rule::{Neighbourhood, Rule};
+pub use crate::rule_table::RuleTable;
+pub use crate::simulation::{Algorithm, Simulation};
+pub use crate::state_map::{StateMap, StateRule};
+pub use crate::stop_condition::{Stop, StopCondition, StopConditions};
+pub use crate::topology::Topology;
+pub use crate::video_cell::{VideoCell, VideoOptions, VideoWriter};
+pub use crate::wolfram::Wolfram;
+
+/// Commands other than the simulation
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Tell whether a pattern is a still life, an oscillator or a spaceship
+    Analyze {
+        /// Pattern file (RLE, plaintext, Life 1.05/1.06 or macrocell) to analyze
+        #[arg(short, long)]
+        file: PathBuf,
+        /// Maximum number of generations to simulate before giving up
+        #[arg(short, long, default_value_t = 10000)]
+        max_gen: u64,
+    },
+}
 
 /// Alife is a program that simulates the Conway's Game of Life.
-/// It can read RLE files to generate images, GIF and RLE files.
+/// It can read RLE, plaintext, Life 1.05/1.06 and macrocell files to generate images, GIF and pattern files.
 #[derive(Parser, Debug)]
-#[command(version, about)]
+#[command(version, about, args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+#[command(group(ArgGroup::new("noise").multiple(true)))]
 pub struct Args {
-    /// RLE file to load for initial configuration
-    #[arg(short, long)]
-    file: std::path::PathBuf,
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Pattern file (RLE, plaintext, Life 1.05/1.06 or macrocell) to load for initial configuration
+    #[arg(short, long, required = true)]
+    file: Option<PathBuf>,
     /// Number of generations to simulate
-    #[arg(short, long)]
-    gen: u32,
+    #[arg(short, long, required = true)]
+    gen: Option<u64>,
+    /// Go back the number of generations instead, for the reversible Margolus rules, generations before 0 being negative
+    #[arg(long, action = clap::ArgAction::SetTrue, conflicts_with_all = [
+        "video", "gif", "stop_extinct", "stop_periodic", "max_population", "min_population", "max_size", "timeout",
+    ])]
+    backwards: bool,
     /// Display the result on standard output instead of writing to a file
     #[arg(short, long, action = clap::ArgAction::SetTrue)]
     output: bool,
+    /// Simulation engine
+    #[arg(short, long, value_enum, default_value_t)]
+    algo: Algorithm,
+    /// Also render every generation into a video (MP4, WebM...), requires ffmpeg
+    #[arg(long, value_name = "FILE")]
+    video: Option<PathBuf>,
+    /// Number of generations per second in the video
+    #[arg(long, default_value_t = 10, requires = "video")]
+    framerate: u32,
+    /// Size of the video in pixels, both even
+    #[arg(long, value_name = "WxH", default_value = "1920x1080", value_parser = parse_video_size, requires = "video")]
+    video_size: (u32, u32),
+    /// Minimum margin in pixels between the pattern and the borders of the video
+    #[arg(long, value_name = "PIXELS", default_value_t = 40, requires = "video")]
+    video_padding: u32,
+    /// ffmpeg video codec (libx264, libvpx-vp9...), chosen from the extension of the video if not given
+    #[arg(long, requires = "video")]
+    codec: Option<String>,
+    /// Also render the generations into an animated GIF
+    #[arg(long, value_name = "FILE")]
+    gif: Option<PathBuf>,
+    /// Number of generations between two frames of the GIF
+    #[arg(long, default_value_t = 1, requires = "gif")]
+    step: u64,
+    /// Display time of each frame of the GIF in milliseconds
+    #[arg(long, default_value_t = 100, requires = "gif")]
+    delay: u32,
+    /// Number of times the GIF is played, forever if not given
+    #[arg(long, requires = "gif")]
+    loops: Option<u16>,
+    /// Stop when all the cells are dead
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    stop_extinct: bool,
+    /// Stop when the pattern is stable or periodic, with a period up to 1024 generations
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    stop_periodic: bool,
+    /// Stop when the population exceeds this number of cells
+    #[arg(long, value_name = "CELLS")]
+    max_population: Option<u64>,
+    /// Stop when the population drops below this number of cells
+    #[arg(long, value_name = "CELLS")]
+    min_population: Option<u64>,
+    /// Stop when the width or height of the pattern exceeds this number of cells
+    #[arg(long, value_name = "CELLS")]
+    max_size: Option<u32>,
+    /// Stop after this number of seconds
+    #[arg(long, value_name = "SECONDS")]
+    timeout: Option<f64>,
+    /// Probability that a cell born by the rule is actually born
+    #[arg(long, value_name = "P", group = "noise")]
+    birth_probability: Option<f64>,
+    /// Probability that a cell killed by the rule actually dies
+    #[arg(long, value_name = "P", group = "noise")]
+    death_probability: Option<f64>,
+    /// Probability that each cell flips its state after every generation. On the infinite plane only the cells around the pattern flip
+    #[arg(long, value_name = "P", group = "noise")]
+    flip_probability: Option<f64>,
+    /// Seed of the random numbers of the probabilities, taken from the clock if not given
+    #[arg(long, requires = "noise")]
+    seed: Option<u64>,
+    /// Directory of the Golly rule files (`WireWorld.rule`...) of the rules named in RLE headers, relative to the current directory
+    #[arg(long, value_name = "DIR", default_value = "rules")]
+    rules: PathBuf,
 }
 
-pub fn run(args: Args) -> Result<(), Box<dyn Error>> {
-    if !args.file.is_file() {
-        return Err(Box::<dyn Error>::from("[File reader] No such file"))
+/// Parse a video size written `1280x720`
+fn parse_video_size(value: &str) -> Result<(u32, u32), String> {
+    let error = || format!("`{}` is not a size written WIDTHxHEIGHT", value);
+    let (width, height) = value.split_once(['x', 'X']).ok_or_else(error)?;
+    Ok((width.trim().parse().map_err(|_| error())?, height.trim().parse().map_err(|_| error())?))
+}
+
+impl Args {
+    fn stop_conditions(&self) -> Result<StopConditions, Error> {
+        let mut conditions = Vec::new();
+        if self.stop_extinct { conditions.push(StopCondition::Extinction) }
+        if self.stop_periodic { conditions.push(StopCondition::Periodic) }
+        if let Some(n) = self.max_population { conditions.push(StopCondition::MaxPopulation(n)) }
+        if let Some(n) = self.min_population { conditions.push(StopCondition::MinPopulation(n)) }
+        if let Some(n) = self.max_size { conditions.push(StopCondition::MaxSize(n)) }
+        if let Some(seconds) = self.timeout {
+            let duration = Duration::try_from_secs_f64(seconds)
+                .map_err(|_| Error::Arguments("[Arguments] The timeout must be a positive number of seconds"))?;
+            conditions.push(StopCondition::Timeout(duration));
+        }
+        Ok(StopConditions::new(conditions))
     }
-    if let Some(ext) = args.file.extension() {
-        if ext != "rle" {
-            return Err(Box::<dyn Error>::from("[File reader] The file supplied must be a file with the .rle extension"))
+
+    fn noise(&self) -> Result<Option<Noise>, Error> {
+        if self.birth_probability.is_none() && self.death_probability.is_none() && self.flip_probability.is_none() {
+            return Ok(None)
         }
-    } else {
-        return Err(Box::<dyn Error>::from("[File reader] The file extension must be provided"))
+        let seed = self.seed.unwrap_or_else(|| {
+            SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or_default()
+        });
+        let noise = Noise {
+            birth: self.birth_probability.unwrap_or(1.0),
+            death: self.death_probability.unwrap_or(1.0),
+            flip: self.flip_probability.unwrap_or(0.0),
+            seed,
+        };
+        if !noise.is_valid() {
+            return Err(Error::Arguments("[Arguments] The probabilities must be between 0 and 1"))
+        }
+        Ok(Some(noise))
+    }
+}
+
+/// Read a pattern file and find its format, given by its extension or else recognised from its content
+fn read_source(file: &Path) -> Result<(Format, String), Error> {
+    let content = fs::read_to_string(file)
+        .map_err(|source| Error::Io { path: file.to_path_buf(), source })?;
+    let format = Format::from_extension(file)
+        .or_else(|| Format::detect(&content))
+        .ok_or(Error::Arguments("[File reader] Unknown pattern format, the file must be a RLE (.rle), plaintext (.cells), Life 1.05/1.06 (.lif) or macrocell (.mc) file"))?;
+    Ok((format, content))
+}
+
+/// Read and decode a pattern file
+fn read_pattern(file: &Path) -> Result<(Format, CellMap, Vec<String>), Error> {
+    let (format, content) = read_source(file)?;
+    let (cell_map, _, comments) = format.parse(content).map_err(|e| e.with_path(file))?;
+    Ok((format, cell_map, comments))
+}
+
+/// Read a pattern file and start a simulation from it, a macrocell file being given directly to HashLife
+/// and a RLE file of a Generations rule or of a rule file of the `rules` directory keeping the states of its cells
+fn read_simulation(file: &Path, algorithm: Algorithm, rules: &Path) -> Result<(Format, Simulation, Vec<String>), Error> {
+    let (format, content) = read_source(file)?;
+    if format == Format::Macrocell {
+        let macrocell = Macrocell::parse(content).map_err(|e| e.with_path(file))?;
+        return Ok((format, Simulation::from_macrocell(&macrocell, algorithm)?, macrocell.comments().to_vec()))
+    }
+    if format == Format::Rle {
+        let rle = RLE::parse(content).map_err(|e| e.with_path(file))?;
+        let mut state_map = rle.to_state_map()?;
+        state_map.set_rule(state_map.rule().resolve(rules)?);
+        let mut simulation = match state_map.rule() {
+            StateRule::Table(_) => Simulation::from_state_map(state_map, algorithm)?,
+            rule if rule.states().is_some_and(|states| states > 2) => Simulation::from_state_map(state_map, algorithm)?,
+            _ => Simulation::new(rle.to_cell_map()?, algorithm)?,
+        };
+        simulation.set_generation(rle.generation());
+        return Ok((format, simulation, rle.comments().to_vec()))
+    }
+    let (cell_map, generation, comments) = format.parse(content).map_err(|e| e.with_path(file))?;
+    let mut simulation = Simulation::new(cell_map, algorithm)?;
+    simulation.set_generation(generation);
+    Ok((format, simulation, comments))
+}
+
+/// Read the pattern file of the arguments into a simulation with the given noise
+fn start_simulation(args: &Args, file: &Path, noise: Option<Noise>) -> Result<(Format, Simulation, Vec<String>), Error> {
+    let (format, mut simulation, comments) = read_simulation(file, args.algo, &args.rules)?;
+    if let Some(noise) = noise {
+        simulation.set_noise(noise)?;
     }
-    // Read file
-    let content = fs::read_to_string(&args.file).unwrap_or_else(|e| {
-        return "[File reader] ".to_string() + &e.to_string()
+    Ok((format, simulation, comments))
+}
+
+/// Print the classification of the pattern of a file
+fn run_analysis(file: &Path, max_gen: u64) -> Result<(), Error> {
+    let (_, cell_map, _) = read_pattern(file)?;
+
+    eprintln!("Analyzing pattern...");
+    let analysis = analyze(&cell_map, max_gen);
+
+    println!("{}", analysis.classification);
+    eprintln!("Determined at generation {}", analysis.generation);
+    Ok(())
+}
+
+/// Generation of a simulation drawn in the frames of the animations, multi-state patterns keeping their states
+fn frame(s: &Simulation) -> Result<Box<dyn Renderable>, Error> {
+    Ok(if s.is_multi_state() { Box::new(s.state_map()?) } else { Box::new(s.cell_map()?) })
+}
+
+/// Run a simulation again for `generations` generations to write the video and the GIF requested by the arguments,
+/// each frame being sent to the encoders as soon as it is computed. The GIF only keeps one frame every `step` generations
+fn write_animations(args: &Args, s: &mut Simulation, generations: u64, video_viewport: Option<Viewport>, gif_viewport: Option<Viewport>) -> Result<(), Error> {
+    let video_cell = VideoCell::new(VideoOptions {
+        framerate: args.framerate,
+        width: args.video_size.0,
+        height: args.video_size.1,
+        padding: args.video_padding,
+        codec: args.codec.clone(),
+        ..VideoOptions::default()
     });
+    let gif_cell = GifCell::new(GifOptions { delay: args.delay, loops: args.loops, ..GifOptions::default() });
+    let mut video = match (&args.video, video_viewport) {
+        (Some(path), Some(viewport)) => Some(video_cell.start(viewport, path)?),
+        _ => None,
+    };
+    let mut gif = match (&args.gif, gif_viewport) {
+        (Some(path), Some(viewport)) => Some(gif_cell.start(viewport, path)?),
+        _ => None,
+    };
+
+    eprintln!("Rendering animations...");
+    let start = s.generation();
+    s.run_observed(generations, &mut StopConditions::new(Vec::new()), |s| {
+        let gif_frame = gif.is_some() && s.generation().abs_diff(start).is_multiple_of(args.step);
+        if video.is_none() && !gif_frame {
+            return Ok(())
+        }
+        let frame = frame(s)?;
+        if let Some(video) = &mut video {
+            video.write_frame(frame.as_ref())?;
+        }
+        if let (Some(gif), true) = (&mut gif, gif_frame) {
+            gif.write_frame(frame.as_ref())?;
+        }
+        Ok(())
+    })?;
+
+    if let (Some(video), Some(path)) = (video, &args.video) {
+        video.finish()?;
+        eprintln!("Successfully created {}", path.display());
+    }
+    if let (Some(gif), Some(path)) = (gif, &args.gif) {
+        gif.finish()?;
+        eprintln!("Successfully created {}", path.display());
+    }
+    Ok(())
+}
 
-    let rle = RLE::parse(content)?;
-    let mut cell_map = rle.to_cell_map()?;
+pub fn run(args: Args) -> Result<(), Error> {
+    if let Some(Command::Analyze { file, max_gen }) = &args.command {
+        return run_analysis(file, *max_gen)
+    }
+    let (Some(file), Some(gen)) = (&args.file, args.gen) else {
+        return Err(Error::Arguments("[Arguments] The file and the number of generations are required"))
+    };
+
+    let noise = args.noise()?;
+    let (format, mut simulation, mut comments) = start_simulation(&args, file, noise)?;
+    if let Some(noise) = noise {
+        eprintln!("{}", noise);
+        comments.push(format.comment(&noise.to_string()));
+    }
+
+    let animated = args.video.is_some() || args.gif.is_some();
+    if args.gif.is_some() && args.step == 0 {
+        return Err(Error::Arguments("[Arguments] The step between two frames of the GIF must be positive"))
+    }
 
     eprintln!("Running simulation...");
 
-    for _ in 0..args.gen {
-        cell_map.generate_next();
+    let mut stop_conditions = args.stop_conditions()?;
+    let start = simulation.generation();
+    let stop = if args.backwards {
+        simulation.run_backwards(gen)?;
+        None
+    } else if animated {
+        // Every frame of an animation draws the same viewport: a first run finds it and a second one
+        // renders the generations as they are computed, so that they are never all kept in memory
+        let (mut video_viewport, mut gif_viewport): (Option<Viewport>, Option<Viewport>) = (None, None);
+        let stop = simulation.run_observed(gen, &mut stop_conditions, |s| {
+            let gif_frame = args.gif.is_some() && s.generation().abs_diff(start).is_multiple_of(args.step);
+            if args.video.is_none() && !gif_frame {
+                return Ok(())
+            }
+            let viewport = frame(s)?.viewport();
+            let union = |v: Option<Viewport>| Some(v.map_or(viewport, |v| v.union(&viewport)));
+            video_viewport = union(video_viewport);
+            if gif_frame {
+                gif_viewport = union(gif_viewport);
+            }
+            Ok(())
+        })?;
+        // The noise has the same seed, the second run computes the same generations
+        let (_, mut replay, _) = start_simulation(&args, file, noise)?;
+        let generations = simulation.generation().abs_diff(start);
+        write_animations(&args, &mut replay, generations, video_viewport, gif_viewport)?;
+        stop
+    } else {
+        simulation.run_until(gen, &mut stop_conditions)?
+    };
+
+    let generation = simulation.generation();
+    if args.algo == Algorithm::Hashlife {
+        eprintln!("Population after {} generations: {}", generation, simulation.population());
     }
 
-    let exported_content = RLE::cell_map_to_file(&cell_map, Some(&rle.comments));
+    if let Some(s) = &stop {
+        eprintln!("{}", s);
+        comments.push(format.comment(&s.to_string()));
+    }
+    let (format, exported_content) = match format {
+        Format::Macrocell => (format, simulation.macrocell(&comments)?.export()),
+        Format::Rle => match simulation.rle(&comments) {
+            Ok(rle) => (format, rle.export()),
+            // The quadtree of HashLife can always be written, whatever the size of the pattern
+            Err(e) if args.algo == Algorithm::Hashlife => {
+                eprintln!("Warning: {}, the result is written in the macrocell format instead", e);
+                (Format::Macrocell, simulation.macrocell(&comments)?.export())
+            }
+            Err(e) => return Err(e),
+        },
+        _ => (format, format.export(&simulation.cell_map()?, generation, &comments)?),
+    };
 
     if !args.output {
         let date = Local::now().format("%Y-%m-%d_%H-%M").to_string();
         let mut exported_file_name = String::new();
-        if let Some(file_stem) = args.file.file_stem() {
+        if let Some(file_stem) = file.file_stem() {
             if let Some(file_stem_str) = file_stem.to_str() {
-                exported_file_name = format!("Alife-{}-{}-{}", args.gen, file_stem_str, &date);
+                exported_file_name = format!("Alife-{}-{}-{}", generation, file_stem_str, &date);
             }
         }
         if exported_file_name.is_empty() {
-            exported_file_name = format!("Alife-{}-export-{}", args.gen, &date);
+            exported_file_name = format!("Alife-{}-export-{}", generation, &date);
         }
 
-        let mut file = File::create(format!("{}.rle", &exported_file_name))?;
-        let img_cell = ImgCell::from_cell_map(&cell_map, None, Some(true));
-
-        file.write_all(exported_content.as_bytes())?;
-        img_cell.img.save(format!("{}.png", &exported_file_name))?;
-
+        let pattern_path = PathBuf::from(format!("{}.{}", &exported_file_name, format.extension()));
+        let io_error = |source| Error::Io { path: pattern_path.clone(), source };
+        let mut file = File::create(&pattern_path).map_err(io_error)?;
+        file.write_all(exported_content.as_bytes()).map_err(io_error)?;
 
-        eprintln!("Successfully created {} and {}", format!("{}.rle", &exported_file_name), format!("{}.png", &exported_file_name));
+        // A macrocell pattern can be too large to be rendered
+        let image = if simulation.is_multi_state() {
+            simulation.state_map().map(|s| ImgCell::from_state_map(&s, None))
+        } else {
+            simulation.cell_map().map(|c| ImgCell::from_cell_map(&c, None, Some(true)))
+        };
+        match image {
+            Ok(img_cell) => {
+                img_cell.save(Path::new(&format!("{}.png", &exported_file_name)))?;
+                eprintln!("Successfully created {} and {}.png", pattern_path.display(), &exported_file_name);
+            }
+            Err(e) => {
+                eprintln!("Successfully created {}", pattern_path.display());
+                eprintln!("No image created: {}", e);
+            }
+        }
     } else {
-        eprintln!("Result of the simulation after {} generations:\n", args.gen);
+        eprintln!("Result of the simulation after {} generations:\n", generation);
         println!("{}", exported_content);
     }
 
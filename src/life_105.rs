@@ -0,0 +1,270 @@
+use crate::cell_map::CellMap;
+use crate::error::{Error, SourceError};
+use crate::rule::Rule;
+
+/// Rectangle of cells whose top left cell is at (x, y)
+#[derive(Debug, Clone, PartialEq)]
+struct Block {
+    x: i64,
+    y: i64,
+    rows: Vec<Vec<bool>>,
+}
+
+/// Pattern in the Life 1.05 format: `#P x y` blocks of `.` and `*` rows placed around the origin
+#[derive(Debug)]
+pub struct Life105 {
+    comments: Vec<String>,
+    rule: Option<Rule>,
+    blocks: Vec<Block>,
+}
+
+impl Life105 {
+    const DECODER: &'static str = "Life 1.05 decoder";
+    const HEADER: &'static str = "#Life 1.05";
+
+    /// Parse the coordinates of a `#P x y` line
+    fn parse_position(text: &str, number: usize) -> Result<(i64, i64), Error> {
+        let error = |message, token: &str, column| {
+            Error::Body(SourceError::new(Self::DECODER, message, token).at(number, column, text))
+        };
+        let mut coordinates = Vec::with_capacity(2);
+        let mut offset = 2;
+        for token in text[2..].split_whitespace() {
+            let column = offset + text[offset..].find(token).unwrap_or_default();
+            offset = column + token.len();
+            let value = token.parse::<i64>().map_err(|_| error("Invalid coordinate", token, column))?;
+            coordinates.push(value);
+        }
+        match coordinates[..] {
+            [x, y] => Ok((x, y)),
+            _ => Err(error("Expected `#P x y`", text.trim(), 0)),
+        }
+    }
+
+    // ---------
+
+    pub fn parse(file_content: String) -> Result<Life105, Error> {
+        let mut lines = file_content.lines().enumerate().map(|(i, text)| (i + 1, text));
+        match lines.next() {
+            Some((_, text)) if text.trim_end() == Self::HEADER => {}
+            Some((number, text)) => {
+                let e = SourceError::new(Self::DECODER, "Expected the `#Life 1.05` header", text.trim()).at(number, 0, text);
+                return Err(Error::Header(e))
+            }
+            None => return Err(Error::Header(SourceError::new(Self::DECODER, "Header line not found", ""))),
+        }
+
+        let (mut comments, mut rule, mut blocks) = (Vec::new(), None, Vec::new());
+        for (number, text) in lines {
+            if text.starts_with("#P") {
+                let (x, y) = Self::parse_position(text, number)?;
+                blocks.push(Block { x, y, rows: Vec::new() });
+            } else if text.starts_with("#N") {
+                rule = Some(Rule::default());
+            } else if let Some(r) = text.strip_prefix("#R") {
+                let r = Rule::parse(r).map_err(|_| {
+                    Error::Header(SourceError::new(Self::DECODER, "Invalid rule", r.trim()).at(number, 2, text))
+                })?;
+                rule = Some(r);
+            } else if text.starts_with('#') {
+                comments.push(text.to_string());
+            } else {
+                let mut row = Vec::with_capacity(text.len());
+                for (column, c) in text.trim_end().char_indices() {
+                    match c {
+                        '.' => row.push(false),
+                        '*' => row.push(true),
+                        _ => {
+                            let e = SourceError::new(Self::DECODER, "Unknown character", &c.to_string()).at(number, column, text);
+                            return Err(Error::Body(e))
+                        }
+                    }
+                }
+                // Rows before the first `#P` line are placed at the origin
+                if blocks.is_empty() {
+                    blocks.push(Block { x: 0, y: 0, rows: Vec::new() });
+                }
+                if let Some(block) = blocks.last_mut() {
+                    block.rows.push(row);
+                }
+            }
+        }
+
+        Ok(Life105 { comments, rule, blocks })
+    }
+
+    pub fn from_cell_map(c: &CellMap, comments: Option<&Vec<String>>) -> Life105 {
+        let c = c.auto_crop();
+        let (x, y) = c.origin();
+        // Dead cells at the end of the rows are omitted
+        let rows = c.cells().iter()
+            .map(|row| row[..row.iter().rposition(|v| *v).map_or(0, |i| i + 1)].to_vec())
+            .collect();
+
+        Life105 {
+            comments: if let Some(c) = comments { c.clone() } else { Vec::<String>::new() },
+            rule: Some(*c.rule()),
+            blocks: vec![Block { x, y, rows }],
+        }
+    }
+
+    /// Build a cell map covering all the blocks, its origin being the top left cell of the blocks
+    pub fn to_cell_map(&self) -> Result<CellMap, Error> {
+        let blocks = self.blocks.iter().filter(|b| !b.rows.is_empty());
+        let min_x = blocks.clone().map(|b| b.x).min().unwrap_or_default();
+        let min_y = blocks.clone().map(|b| b.y).min().unwrap_or_default();
+        // Computed on 128 bits as the blocks may sit at the extremes of i64
+        let max_x = blocks.clone().map(|b| b.x as i128 + b.rows.iter().map(|r| r.len()).max().unwrap_or_default() as i128).max();
+        let max_y = blocks.clone().map(|b| b.y as i128 + b.rows.len() as i128).max();
+        let (w, h) = ((max_x.unwrap_or_default() - min_x as i128).max(1), (max_y.unwrap_or_default() - min_y as i128).max(1));
+        if w.saturating_mul(h) > CellMap::MAX_CELLS as i128 {
+            return Err(Error::CellMap("[CellMap creation] The pattern is too large to be converted to a cell map"))
+        }
+
+        let mut map = vec![vec![false; w as usize]; h as usize];
+        for block in blocks {
+            for (i, row) in block.rows.iter().enumerate() {
+                for (j, &v) in row.iter().enumerate() {
+                    if v {
+                        map[(block.y - min_y) as usize + i][(block.x - min_x) as usize + j] = true;
+                    }
+                }
+            }
+        }
+
+        let mut cell_map = CellMap::new(map)?;
+        cell_map.set_origin((min_x, min_y));
+        cell_map.set_rule(self.rule.unwrap_or_default());
+        Ok(cell_map)
+    }
+
+    /// Comment lines (`#D ...`) of the file
+    pub fn comments(&self) -> &[String] {
+        &self.comments
+    }
+
+    pub fn export(&self) -> String {
+        let mut content = format!("{}\n#D Generated by ALife\n", Self::HEADER);
+        for comment in self.comments.iter() {
+            content += comment;
+            content += "\n";
+        }
+        match self.rule {
+            Some(rule) if rule != Rule::default() => content += &format!("#R {}\n", rule.to_legacy_string()),
+            _ => content += "#N\n",
+        }
+        for block in self.blocks.iter() {
+            content += &format!("#P {} {}\n", block.x, block.y);
+            for row in block.rows.iter() {
+                // An empty line could be mistaken for the end of the file
+                if row.is_empty() {
+                    content += ".";
+                }
+                content.extend(row.iter().map(|&alive| if alive { '*' } else { '.' }));
+                content += "\n";
+            }
+        }
+        content
+    }
+
+    pub fn file_to_cell_map(file_content: String) -> Result<CellMap, Error> {
+        Self::parse(file_content)?.to_cell_map()
+    }
+
+    pub fn cell_map_to_file(c: &CellMap, comments: Option<&Vec<String>>) -> String {
+        Self::from_cell_map(c, comments).export()
+    }
+}
+
+
+// --------
+// Tests
+// --------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Test Life105::parse
+
+    #[test]
+    fn test_parse_1() {
+        let content = String::from("#Life 1.05
+#D Glider and blinker
+#R 23/36
+#P -1 -1
+.*
+..*
+***
+#P 4 0
+***
+");
+
+        let result = Life105::parse(content).unwrap();
+
+        assert_eq!(result.comments, vec!["#D Glider and blinker"]);
+        assert_eq!(result.rule.map(|r| r.to_string()), Some(String::from("B36/S23")));
+        assert_eq!(result.blocks.len(), 2);
+        assert_eq!((result.blocks[0].x, result.blocks[0].y), (-1, -1));
+        assert_eq!(result.blocks[1].rows, vec![vec![true, true, true]]);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(matches!(Life105::parse(String::from("#Life 1.06\n0 0")), Err(Error::Header(_))));
+
+        match Life105::parse(String::from("#Life 1.05\n#P 0 a\n*")) {
+            Err(Error::Body(e)) => {
+                assert_eq!(e.token, "a");
+                assert_eq!(e.location.map(|l| (l.line, l.column)), Some((2, 6)));
+            }
+            _ => panic!("A body error was expected"),
+        }
+        assert!(Life105::parse(String::from("#Life 1.05\n#P 0 0\n*O")).is_err());
+    }
+
+    // Test Life105.to_cell_map
+
+    #[test]
+    fn test_to_cell_map() {
+        let life = Life105::parse(String::from("#Life 1.05\n#P -1 -1\n.*\n..*\n***\n#P 4 0\n***\n")).unwrap();
+
+        let c = life.to_cell_map().unwrap();
+
+        assert_eq!(c.origin(), (-1, -1));
+        assert_eq!((c.width(), c.height()), (8, 3));
+        assert!(c.is_alive(0, -1));
+        assert!(c.is_alive(1, 0));
+        assert!(c.is_alive(6, 0));
+        assert!(!c.is_alive(3, 0));
+        assert_eq!(c.population(), 8);
+    }
+
+    #[test]
+    fn test_to_cell_map_extreme_coordinates() {
+        let life = Life105::parse(String::from("#Life 1.05\n#P -9223372036854775808 0\n*\n#P 9223372036854775807 0\n*\n")).unwrap();
+
+        assert!(matches!(life.to_cell_map(), Err(Error::CellMap(message)) if message.contains("too large")));
+    }
+
+    // Test Life105::cell_map_to_file
+
+    #[test]
+    fn test_cell_map_to_file() {
+        let mut c = CellMap::new(vec![
+            vec![false, true, false],
+            vec![false, false, false],
+            vec![true, true, true],
+        ]).unwrap();
+        c.set_origin((-5, 2));
+        c.set_rule(Rule::parse("B36/S23").unwrap());
+
+        let content = Life105::cell_map_to_file(&c, Some(&vec![String::from("#D Test")]));
+
+        assert_eq!(content, "#Life 1.05\n#D Generated by ALife\n#D Test\n#R 23/36\n#P -5 2\n.*\n.\n***\n");
+        let back = Life105::file_to_cell_map(content).unwrap();
+        assert_eq!(back.origin(), (-5, 2));
+        assert_eq!(back.cells(), c.cells());
+        assert_eq!(back.rule(), c.rule());
+    }
+}
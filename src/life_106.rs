@@ -0,0 +1,235 @@
+use crate::cell_map::CellMap;
+use crate::error::{Error, SourceError};
+use crate::rule::Rule;
+
+/// Pattern in the Life 1.06 format: the absolute `x y` coordinates of a live cell on each line
+#[derive(Debug)]
+pub struct Life106 {
+    comments: Vec<String>,
+    rule: Rule,
+    cells: Vec<(i64, i64)>,
+}
+
+impl Life106 {
+    const DECODER: &'static str = "Life 1.06 decoder";
+    const HEADER: &'static str = "#Life 1.06";
+
+    // ---------
+
+    pub fn parse(file_content: String) -> Result<Life106, Error> {
+        let mut lines = file_content.lines().enumerate().map(|(i, text)| (i + 1, text));
+        match lines.next() {
+            Some((_, text)) if text.trim_end() == Self::HEADER => {}
+            Some((number, text)) => {
+                let e = SourceError::new(Self::DECODER, "Expected the `#Life 1.06` header", text.trim()).at(number, 0, text);
+                return Err(Error::Header(e))
+            }
+            None => return Err(Error::Header(SourceError::new(Self::DECODER, "Header line not found", ""))),
+        }
+
+        let (mut comments, mut rule, mut cells) = (Vec::new(), Rule::default(), Vec::new());
+        for (number, text) in lines {
+            // The rule line of Life 1.05, Conway's Game of Life if there is none
+            if let Some(r) = text.strip_prefix("#R") {
+                rule = Rule::parse(r).map_err(|_| {
+                    Error::Header(SourceError::new(Self::DECODER, "Invalid rule", r.trim()).at(number, 2, text))
+                })?;
+                continue
+            }
+            if text.starts_with('#') {
+                comments.push(text.to_string());
+                continue
+            }
+            if text.trim().is_empty() {
+                continue
+            }
+            let mut coordinates = Vec::with_capacity(2);
+            let mut offset = 0;
+            for token in text.split_whitespace() {
+                let column = offset + text[offset..].find(token).unwrap_or_default();
+                offset = column + token.len();
+                let value = token.parse::<i64>().map_err(|_| {
+                    Error::Body(SourceError::new(Self::DECODER, "Invalid coordinate", token).at(number, column, text))
+                })?;
+                coordinates.push(value);
+            }
+            match coordinates[..] {
+                [x, y] => cells.push((x, y)),
+                _ => return Err(Error::Body(SourceError::new(Self::DECODER, "Expected `x y`", text.trim()).at(number, 0, text))),
+            }
+        }
+
+        Ok(Life106 { comments, rule, cells })
+    }
+
+    pub fn from_cell_map(c: &CellMap, comments: Option<&Vec<String>>) -> Life106 {
+        let (x, y) = c.origin();
+        let cells = c.cells().iter().enumerate()
+            .flat_map(|(i, row)| {
+                row.iter().enumerate().filter(|(_, &v)| v).map(move |(j, _)| (x + j as i64, y + i as i64))
+            })
+            .collect();
+
+        Life106 {
+            comments: if let Some(c) = comments { c.clone() } else { Vec::<String>::new() },
+            rule: *c.rule(),
+            cells,
+        }
+    }
+
+    /// Build the smallest cell map holding all the cells, its origin being their top left corner
+    pub fn to_cell_map(&self) -> Result<CellMap, Error> {
+        let min_x = self.cells.iter().map(|c| c.0).min().unwrap_or_default();
+        let min_y = self.cells.iter().map(|c| c.1).min().unwrap_or_default();
+        let max_x = self.cells.iter().map(|c| c.0).max().unwrap_or_default();
+        let max_y = self.cells.iter().map(|c| c.1).max().unwrap_or_default();
+        // Computed on 128 bits as the extremes of i64 do not fit in a single dimension
+        let (w, h) = (max_x as i128 - min_x as i128 + 1, max_y as i128 - min_y as i128 + 1);
+        if w.saturating_mul(h) > CellMap::MAX_CELLS as i128 {
+            return Err(Error::CellMap("[CellMap creation] The pattern is too large to be converted to a cell map"))
+        }
+
+        let mut map = vec![vec![false; w as usize]; h as usize];
+        for &(x, y) in self.cells.iter() {
+            map[(y - min_y) as usize][(x - min_x) as usize] = true;
+        }
+
+        let mut cell_map = CellMap::new(map)?;
+        cell_map.set_origin((min_x, min_y));
+        cell_map.set_rule(self.rule);
+        Ok(cell_map)
+    }
+
+    /// Comment lines (`#D ...`) of the file
+    pub fn comments(&self) -> &[String] {
+        &self.comments
+    }
+
+    pub fn export(&self) -> String {
+        let mut content = format!("{}\n#D Generated by ALife\n", Self::HEADER);
+        for comment in self.comments.iter() {
+            content += comment;
+            content += "\n";
+        }
+        if self.rule != Rule::default() {
+            content += &format!("#R {}\n", self.rule.to_legacy_string());
+        }
+        for (x, y) in self.cells.iter() {
+            content += &format!("{} {}\n", x, y);
+        }
+        content
+    }
+
+    pub fn file_to_cell_map(file_content: String) -> Result<CellMap, Error> {
+        Self::parse(file_content)?.to_cell_map()
+    }
+
+    pub fn cell_map_to_file(c: &CellMap, comments: Option<&Vec<String>>) -> String {
+        Self::from_cell_map(c, comments).export()
+    }
+}
+
+
+// --------
+// Tests
+// --------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Test Life106::parse
+
+    #[test]
+    fn test_parse_1() {
+        let content = String::from("#Life 1.06\n0 -1\n1 0\n-1 1\n0 1\n1 1\n");
+
+        let result = Life106::parse(content).unwrap();
+
+        assert_eq!(result.comments, Vec::<String>::new());
+        assert_eq!(result.cells, vec![(0, -1), (1, 0), (-1, 1), (0, 1), (1, 1)]);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(matches!(Life106::parse(String::from("0 0")), Err(Error::Header(_))));
+
+        match Life106::parse(String::from("#Life 1.06\n0 0\n 12 4x\n")) {
+            Err(Error::Body(e)) => {
+                assert_eq!(e.token, "4x");
+                assert_eq!(e.location.map(|l| (l.line, l.column)), Some((3, 5)));
+            }
+            _ => panic!("A body error was expected"),
+        }
+        assert!(Life106::parse(String::from("#Life 1.06\n1 2 3\n")).is_err());
+    }
+
+    // Test Life106.to_cell_map
+
+    #[test]
+    fn test_to_cell_map() {
+        let c = Life106::file_to_cell_map(String::from("#Life 1.06\n0 -1\n1 0\n-1 1\n0 1\n1 1\n")).unwrap();
+
+        assert_eq!(c.origin(), (-1, -1));
+        assert_eq!(c.cells(), vec![
+            vec![false, true, false],
+            vec![false, false, true],
+            vec![true, true, true],
+        ]);
+    }
+
+    #[test]
+    fn test_to_cell_map_empty() {
+        let c = Life106::file_to_cell_map(String::from("#Life 1.06\n")).unwrap();
+
+        assert_eq!(c.population(), 0);
+    }
+
+    #[test]
+    fn test_to_cell_map_extreme_coordinates() {
+        let result = Life106::file_to_cell_map(String::from("#Life 1.06\n-9223372036854775808 0\n9223372036854775807 0\n"));
+
+        assert!(matches!(result, Err(Error::CellMap(message)) if message.contains("too large")));
+    }
+
+    // Test Life106::cell_map_to_file
+
+    #[test]
+    fn test_cell_map_to_file() {
+        let mut c = CellMap::new(vec![
+            vec![false, false, false],
+            vec![true, true, true],
+        ]).unwrap();
+        c.set_origin((-10, 20));
+
+        let content = Life106::cell_map_to_file(&c, None);
+
+        assert_eq!(content, "#Life 1.06\n#D Generated by ALife\n-10 21\n-9 21\n-8 21\n");
+    }
+
+    #[test]
+    fn test_cell_map_to_file_rule() {
+        let mut c = CellMap::new(vec![vec![true, true, true]]).unwrap();
+        c.set_rule(Rule::parse("B36/S23").unwrap());
+
+        let content = Life106::cell_map_to_file(&c, None);
+
+        assert_eq!(content, "#Life 1.06\n#D Generated by ALife\n#R 23/36\n0 0\n1 0\n2 0\n");
+        assert_eq!(Life106::file_to_cell_map(content).unwrap().rule(), c.rule());
+        assert!(matches!(Life106::parse(String::from("#Life 1.06\n#R 23/3x\n")), Err(Error::Header(_))));
+    }
+
+    #[test]
+    fn test_moving_pattern() {
+        // The glider keeps its absolute position through an export and an import
+        let mut c = Life106::file_to_cell_map(String::from("#Life 1.06\n0 -1\n1 0\n-1 1\n0 1\n1 1\n")).unwrap();
+        for _ in 0..4 {
+            c.generate_next();
+        }
+
+        let back = Life106::file_to_cell_map(Life106::cell_map_to_file(&c, None)).unwrap();
+
+        assert_eq!(back.origin(), (0, 0));
+        assert_eq!(back.population(), 5);
+    }
+}
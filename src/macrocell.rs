@@ -0,0 +1,372 @@
+use std::collections::HashMap;
+use crate::cell_map::CellMap;
+use crate::error::{Error, SourceError};
+use crate::hash_life::HashLife;
+use crate::rule::Rule;
+
+/// Line of a macrocell file, numbered from 1 in the order of the file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum MacroNode {
+    /// Square of 8x8 cells, bit x of row y being the cell (x, y)
+    Leaf([u8; 8]),
+    /// Square of 2^level cells per side made of four nodes of the level below, 0 being an empty node
+    Node { level: u8, children: [usize; 4] },
+}
+
+/// Pattern in Golly's macrocell format: the quadtree of the pattern, identical squares being written once
+#[derive(Debug)]
+pub struct Macrocell {
+    comments: Vec<String>,
+    rule: Rule,
+    generation: i64,
+    nodes: Vec<MacroNode>,
+}
+
+impl Macrocell {
+    const DECODER: &'static str = "Macrocell decoder";
+    const HEADER: &'static str = "[M2]";
+    /// Level of the leaves, squares of 8x8 cells
+    pub(crate) const LEAF_LEVEL: u8 = 3;
+
+    /// Parse a leaf line: `.` and `*` rows ended by `$`, dead cells at the end being omitted
+    fn parse_leaf(text: &str, number: usize) -> Result<MacroNode, Error> {
+        let mut rows = [0u8; 8];
+        let (mut x, mut y) = (0, 0);
+        for (column, c) in text.trim_end().char_indices() {
+            let error = |message| Error::Body(SourceError::new(Self::DECODER, message, &c.to_string()).at(number, column, text));
+            match c {
+                '.' | '*' if x >= 8 || y >= 8 => return Err(error("The leaf is larger than 8x8 cells")),
+                '.' => x += 1,
+                '*' => {
+                    rows[y] |= 1 << x;
+                    x += 1;
+                }
+                '$' => (x, y) = (0, y + 1),
+                _ => return Err(error("Unknown character")),
+            }
+        }
+        Ok(MacroNode::Leaf(rows))
+    }
+
+    /// Parse a node line `level nw ne sw se`, the children being nodes of the previous lines
+    fn parse_node(text: &str, number: usize, nodes: &[MacroNode]) -> Result<MacroNode, Error> {
+        let error = |message, token: &str, column| {
+            Error::Body(SourceError::new(Self::DECODER, message, token).at(number, column, text))
+        };
+        let mut values = Vec::with_capacity(5);
+        let mut offset = 0;
+        for token in text.split_whitespace() {
+            let column = offset + text[offset..].find(token).unwrap_or_default();
+            offset = column + token.len();
+            let value = token.parse::<usize>().map_err(|_| error("Invalid number", token, column))?;
+            let valid = match values.len() {
+                0 => value > Self::LEAF_LEVEL as usize && value < 64,
+                _ => value == 0 || nodes.get(value - 1).is_some_and(|n| Self::level(n) as usize + 1 == values[0]),
+            };
+            if !valid {
+                let message = if values.is_empty() { "Invalid level" } else { "Invalid child node" };
+                return Err(error(message, token, column))
+            }
+            values.push(value);
+        }
+        match values[..] {
+            [level, nw, ne, sw, se] => Ok(MacroNode::Node { level: level as u8, children: [nw, ne, sw, se] }),
+            _ => Err(error("Expected `level nw ne sw se`", text.trim(), 0)),
+        }
+    }
+
+    fn level(node: &MacroNode) -> u8 {
+        match node {
+            MacroNode::Leaf(_) => Self::LEAF_LEVEL,
+            MacroNode::Node { level, .. } => *level,
+        }
+    }
+
+    /// Number of the node of the given level whose top left cell is at (x, y), 0 if it is empty
+    fn build(c: &CellMap, level: u8, x: i64, y: i64, numbers: &mut HashMap<MacroNode, usize>, nodes: &mut Vec<MacroNode>) -> usize {
+        let size = 1i64 << level;
+        let (x0, y0) = c.origin();
+        if x + size <= x0 || y + size <= y0 || x >= x0 + c.width() as i64 || y >= y0 + c.height() as i64 {
+            return 0
+        }
+        let node = if level == Self::LEAF_LEVEL {
+            let mut rows = [0u8; 8];
+            for (dy, row) in rows.iter_mut().enumerate() {
+                for dx in 0..8 {
+                    // The cells in the opposite state of the background, the `#G` line giving the phase of a B0 rule
+                    if c.is_alive(x + dx, y + dy as i64) != c.background() {
+                        *row |= 1 << dx;
+                    }
+                }
+            }
+            if rows == [0; 8] {
+                return 0
+            }
+            MacroNode::Leaf(rows)
+        } else {
+            let half = size / 2;
+            let children = [(0, 0), (half, 0), (0, half), (half, half)]
+                .map(|(dx, dy)| Self::build(c, level - 1, x + dx, y + dy, numbers, nodes));
+            if children == [0; 4] {
+                return 0
+            }
+            MacroNode::Node { level, children }
+        };
+        *numbers.entry(node).or_insert_with(|| {
+            nodes.push(node);
+            nodes.len()
+        })
+    }
+
+    // ---------
+
+    pub fn parse(file_content: String) -> Result<Macrocell, Error> {
+        let mut lines = file_content.lines().enumerate().map(|(i, text)| (i + 1, text));
+        match lines.next() {
+            Some((_, text)) if text.starts_with(Self::HEADER) => {}
+            Some((number, text)) => {
+                let e = SourceError::new(Self::DECODER, "Expected the `[M2]` header", text.trim()).at(number, 0, text);
+                return Err(Error::Header(e))
+            }
+            None => return Err(Error::Header(SourceError::new(Self::DECODER, "Header line not found", ""))),
+        }
+
+        let (mut comments, mut rule, mut generation, mut nodes) = (Vec::new(), Rule::default(), 0, Vec::new());
+        for (number, text) in lines {
+            if let Some(r) = text.strip_prefix("#R") {
+                rule = Rule::parse(r.trim()).map_err(|_| {
+                    Error::Header(SourceError::new(Self::DECODER, "Invalid rule", r.trim()).at(number, 2, text))
+                })?;
+            } else if let Some(g) = text.strip_prefix("#G") {
+                generation = g.trim().parse().map_err(|_| {
+                    Error::Header(SourceError::new(Self::DECODER, "Invalid generation", g.trim()).at(number, 2, text))
+                })?;
+            } else if text.starts_with('#') {
+                comments.push(text.to_string());
+            } else if text.starts_with(|c: char| c.is_ascii_digit()) {
+                nodes.push(Self::parse_node(text, number, &nodes)?);
+            } else if !text.trim().is_empty() {
+                nodes.push(Self::parse_leaf(text, number)?);
+            }
+        }
+
+        Ok(Macrocell { comments, rule, generation, nodes })
+    }
+
+    /// Build the quadtree of a cell map, centered on the coordinates (0, 0) like in Golly
+    pub fn from_cell_map(c: &CellMap, comments: Option<&Vec<String>>) -> Macrocell {
+        let (mut numbers, mut nodes) = (HashMap::new(), Vec::new());
+        if let Some((min_x, min_y, max_x, max_y)) = c.bounding_box() {
+            let mut level = Self::LEAF_LEVEL;
+            while level < 62 && [min_x, min_y, max_x + 1, max_y + 1].iter().any(|v| v.abs() > 1 << (level - 1)) {
+                level += 1;
+            }
+            let half = 1i64 << (level - 1);
+            Self::build(c, level, -half, -half, &mut numbers, &mut nodes);
+        }
+
+        Macrocell {
+            comments: if let Some(c) = comments { c.clone() } else { Vec::<String>::new() },
+            rule: *c.rule(),
+            generation: 0,
+            nodes,
+        }
+    }
+
+    pub(crate) fn from_nodes(nodes: Vec<MacroNode>, rule: Rule, generation: i64, comments: &[String]) -> Macrocell {
+        Macrocell { comments: comments.to_vec(), rule, generation, nodes }
+    }
+
+    /// Build a cell map of the live cells, fails when the pattern is too large for a cell map.
+    /// The cells of a B0 rule are the ones in the opposite state of the background at the generation of the file
+    pub fn to_cell_map(&self) -> Result<CellMap, Error> {
+        // HashLife only reads the quadtree, the rule may be one that it cannot simulate
+        let quadtree = HashLife::read_macrocell(self, Rule::default())?.to_cell_map()?;
+        // The phase keeps the state of the cells, those of the file are inverted first when the background is alive
+        let background = self.rule.background_at(self.generation);
+        let cells = quadtree.cells().iter().map(|row| row.iter().map(|&v| v != background).collect()).collect();
+        let mut c = CellMap::new(cells)?;
+        c.set_origin(quadtree.origin());
+        c.set_rule(self.rule);
+        c.set_phase(self.generation);
+        Ok(c)
+    }
+
+    /// Nodes of the quadtree, the last one being the root
+    pub(crate) fn nodes(&self) -> &[MacroNode] {
+        &self.nodes
+    }
+
+    pub fn rule(&self) -> &Rule {
+        &self.rule
+    }
+
+    /// Generation of the pattern given by the `#G` line
+    pub fn generation(&self) -> i64 {
+        self.generation
+    }
+
+    /// Change the generation written in the `#G` line
+    pub fn set_generation(&mut self, generation: i64) {
+        self.generation = generation;
+    }
+
+    /// Comment lines (`#C ...`, `#N ...`) of the file
+    pub fn comments(&self) -> &[String] {
+        &self.comments
+    }
+
+    pub fn export(&self) -> String {
+        let mut content = format!("{} (ALife)\n#R {}\n", Self::HEADER, self.rule);
+        if self.generation != 0 {
+            content += &format!("#G {}\n", self.generation);
+        }
+        content += "#C Generated by ALife\n";
+        for comment in self.comments.iter() {
+            content += comment;
+            content += "\n";
+        }
+        for node in self.nodes.iter() {
+            match node {
+                MacroNode::Leaf(rows) => {
+                    // Dead cells at the end of the rows and empty rows at the end of the leaf are omitted
+                    let last = rows.iter().rposition(|&r| r != 0).map_or(0, |i| i + 1);
+                    for &row in rows[..last].iter() {
+                        let width = 8 - row.leading_zeros() as usize;
+                        content.extend((0..width).map(|x| if row >> x & 1 == 1 { '*' } else { '.' }));
+                        content += "$";
+                    }
+                }
+                MacroNode::Node { level, children } => {
+                    content += &format!("{} {} {} {} {}", level, children[0], children[1], children[2], children[3]);
+                }
+            }
+            content += "\n";
+        }
+        content
+    }
+
+    pub fn file_to_cell_map(file_content: String) -> Result<CellMap, Error> {
+        Self::parse(file_content)?.to_cell_map()
+    }
+
+    pub fn cell_map_to_file(c: &CellMap, comments: Option<&Vec<String>>) -> String {
+        Self::from_cell_map(c, comments).export()
+    }
+}
+
+
+// --------
+// Tests
+// --------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn glider() -> CellMap {
+        CellMap::new(vec![
+            vec![false, true, false],
+            vec![false, false, true],
+            vec![true, true, true],
+        ]).unwrap()
+    }
+
+    // Test Macrocell::parse
+
+    #[test]
+    fn test_parse_1() {
+        let content = String::from("[M2] (golly 4.2)
+#R B36/S23
+#G 120
+#C Two gliders
+.*$..*$***$
+4 1 0 0 1
+");
+
+        let result = Macrocell::parse(content).unwrap();
+
+        assert_eq!(result.rule().to_string(), "B36/S23");
+        assert_eq!(result.generation(), 120);
+        assert_eq!(result.comments(), ["#C Two gliders"]);
+        assert_eq!(result.nodes, vec![
+            MacroNode::Leaf([0b010, 0b100, 0b111, 0, 0, 0, 0, 0]),
+            MacroNode::Node { level: 4, children: [1, 0, 0, 1] },
+        ]);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(matches!(Macrocell::parse(String::from("x = 3, y = 1\n3o!")), Err(Error::Header(_))));
+        assert!(matches!(Macrocell::parse(String::from("[M2]\n#G many\n")), Err(Error::Header(_))));
+
+        match Macrocell::parse(String::from("[M2]\n.*$\n4 1 0 2 0\n")) {
+            Err(Error::Body(e)) => {
+                assert_eq!(e.message, "Invalid child node");
+                assert_eq!(e.location.map(|l| (l.line, l.column)), Some((3, 7)));
+            }
+            _ => panic!("A body error was expected"),
+        }
+        assert!(Macrocell::parse(String::from("[M2]\n.*$\n5 1 0 0 0\n")).is_err());
+        assert!(Macrocell::parse(String::from("[M2]\n.........*$\n")).is_err());
+        assert!(Macrocell::parse(String::from("[M2]\n.*$\n4 1 0 0\n")).is_err());
+    }
+
+    // Test Macrocell.to_cell_map
+
+    #[test]
+    fn test_to_cell_map() {
+        let c = Macrocell::file_to_cell_map(String::from("[M2]\n.*$..*$***$\n4 1 0 0 1\n")).unwrap();
+
+        // The root of level 4 is centered on (0, 0)
+        assert_eq!(c.origin(), (-8, -8));
+        assert_eq!((c.width(), c.height()), (11, 11));
+        assert!(c.is_alive(-7, -8));
+        assert!(c.is_alive(2, 2));
+        assert_eq!(c.population(), 10);
+    }
+
+    // Test Macrocell::cell_map_to_file
+
+    #[test]
+    fn test_cell_map_to_file() {
+        let mut c = glider();
+        c.set_origin((-1, -1));
+
+        let content = Macrocell::cell_map_to_file(&c, None);
+
+        // The pattern fits in a single leaf whose top left cell is at (-4, -4)
+        assert_eq!(content, "[M2] (ALife)\n#R B3/S23\n#C Generated by ALife\n$$$....*$.....*$...***$\n");
+        let back = Macrocell::file_to_cell_map(content).unwrap();
+        assert_eq!(back.origin(), (-1, -1));
+        assert_eq!(back.cells(), glider().cells());
+    }
+
+    #[test]
+    fn test_cell_map_to_file_b0() {
+        // At an odd generation the background is alive, the `#G` line gives the phase back
+        let mut c = CellMap::new(vec![vec![true, true]]).unwrap();
+        c.set_rule(Rule::parse("B0/S").unwrap());
+        c.generate_next();
+        let mut m = Macrocell::from_cell_map(&c, None);
+        m.set_generation(1);
+
+        let back = Macrocell::parse(m.export()).unwrap().to_cell_map().unwrap();
+
+        assert!(back.background());
+        assert_eq!(back.bounding_box(), c.bounding_box());
+        assert!((-3..5).all(|x| (-2..3).all(|y| back.is_alive(x, y) == c.is_alive(x, y))));
+    }
+
+    #[test]
+    fn test_cell_map_to_file_shared_nodes() {
+        // Identical leaves are written once
+        let mut c = CellMap::new(vec![vec![true; 64]; 64]).unwrap();
+        c.set_origin((-32, -32));
+
+        let m = Macrocell::from_cell_map(&c, None);
+
+        assert_eq!(m.nodes.len(), 4);
+        assert_eq!(m.to_cell_map().unwrap().population(), 64 * 64);
+    }
+}
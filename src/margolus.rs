@@ -0,0 +1,146 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Named Margolus rules and their tables
+const PRESETS: [(&str, [u8; 16]); 3] = [
+    ("Critters", [15, 14, 13, 3, 11, 5, 6, 1, 7, 9, 10, 2, 12, 4, 8, 0]),
+    ("BBM", [0, 8, 4, 3, 2, 5, 9, 7, 1, 6, 10, 11, 12, 13, 14, 15]),
+    ("Tron", [15, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 0]),
+];
+
+/// Block cellular automaton of the Margolus neighbourhood: the plane is cut into 2x2 blocks, shifted by one
+/// cell in both directions every other generation, and each block is replaced following a table of 16 blocks.
+/// Bit 0 of a block is its top left cell, bit 1 the top right one, bit 2 the bottom left one and bit 3 the bottom right one.
+/// Written as in MCell `M0,8,4,3,2,5,9,7,1,6,10,11,12,13,14,15` or with the name of a preset: `Critters`, `BBM` (billiard-ball machine) or `Tron`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Margolus {
+    table: [u8; 16],
+}
+
+impl Margolus {
+    /// Block whose cells are all alive
+    pub const FULL: u8 = 15;
+
+    // ---------
+
+    /// Parse a preset name or the 16 blocks of the table written `M0,8,4,...`
+    pub fn parse(input: &str) -> Result<Margolus, &'static str> {
+        let input = input.trim();
+        if let Some((_, table)) = PRESETS.iter().find(|(name, _)| name.eq_ignore_ascii_case(input)) {
+            return Ok(Margolus { table: *table })
+        }
+        let Some(list) = input.strip_prefix(['M', 'm']) else {
+            return Err("[Rule parser] A Margolus rule starts with M or is Critters, BBM or Tron")
+        };
+        let blocks = list.split(',')
+            .map(|block| match block.trim().parse::<u8>() {
+                Ok(block) if block <= Self::FULL => Ok(block),
+                _ => Err("[Rule parser] The blocks of a Margolus rule are numbers between 0 and 15"),
+            })
+            .collect::<Result<Vec<u8>, _>>()?;
+        let table: [u8; 16] = blocks.try_into().map_err(|_| "[Rule parser] A Margolus rule needs the next state of the 16 blocks")?;
+
+        // The infinite background must stay uniform
+        let uniform = |block: u8| block == 0 || block == Self::FULL;
+        if !uniform(table[0]) || (table[0] == Self::FULL && !uniform(table[Self::FULL as usize])) {
+            return Err("[Rule parser] A Margolus rule must turn the empty and full blocks into empty or full blocks")
+        }
+        Ok(Margolus { table })
+    }
+
+    /// Next state of each of the 16 blocks
+    pub fn table(&self) -> &[u8; 16] {
+        &self.table
+    }
+
+    /// Compute the next state of a block
+    pub fn next_block(&self, block: u8) -> u8 {
+        self.table[block as usize]
+    }
+
+    /// Whether every block comes from a single block, so that the generations can be computed backwards
+    pub fn is_reversible(&self) -> bool {
+        (0..16).all(|block| self.table.contains(&block))
+    }
+
+    /// Rule giving back the previous blocks, None if the rule is not reversible
+    pub fn inverse(&self) -> Option<Margolus> {
+        if !self.is_reversible() {
+            return None
+        }
+        let mut table = [0; 16];
+        for (block, &next) in self.table.iter().enumerate() {
+            table[next as usize] = block as u8;
+        }
+        Some(Margolus { table })
+    }
+}
+
+impl fmt::Display for Margolus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some((name, _)) = PRESETS.iter().find(|(_, table)| *table == self.table) {
+            return write!(f, "{}", name)
+        }
+        let blocks: Vec<String> = self.table.iter().map(|block| block.to_string()).collect();
+        write!(f, "M{}", blocks.join(","))
+    }
+}
+
+impl FromStr for Margolus {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Margolus::parse(s)
+    }
+}
+
+
+// --------
+// Tests
+// --------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Test Margolus::parse
+
+    #[test]
+    fn test_parse_1() {
+        let rule = Margolus::parse("bbm").unwrap();
+        assert_eq!(rule.to_string(), "BBM");
+        assert_eq!(Margolus::parse("M0,8,4,3,2,5,9,7,1,6,10,11,12,13,14,15").unwrap(), rule);
+
+        let rule = Margolus::parse("M0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15").unwrap();
+        assert_eq!(rule.to_string(), "M0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15");
+        assert_eq!(Margolus::parse("Critters").unwrap().next_block(1), 14);
+    }
+
+    #[test]
+    fn test_parse_2() {
+        assert!(Margolus::parse("M0,1,2").is_err());
+        assert!(Margolus::parse("M0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,16").is_err());
+        assert!(Margolus::parse("M0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,0").is_err());
+        assert!(Margolus::parse("Life").is_err());
+        // An empty block can't turn into a single cell
+        assert!(Margolus::parse("M1,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15").is_err());
+        assert!(Margolus::parse("M15,1,2,3,4,5,6,7,8,9,10,11,12,13,14,1").is_err());
+    }
+
+    // Test Margolus.inverse
+
+    #[test]
+    fn test_inverse() {
+        for name in ["Critters", "BBM", "Tron"] {
+            let rule = Margolus::parse(name).unwrap();
+            let inverse = rule.inverse().unwrap();
+            assert!((0..16).all(|block| inverse.next_block(rule.next_block(block)) == block), "{}", name);
+        }
+        // The billiard-ball machine is its own inverse
+        assert_eq!(Margolus::parse("BBM").unwrap().inverse(), Margolus::parse("BBM").ok());
+
+        let rule = Margolus::parse("M0,0,2,3,4,5,6,7,8,9,10,11,12,13,14,15").unwrap();
+        assert!(!rule.is_reversible());
+        assert_eq!(rule.inverse(), None);
+    }
+}
@@ -0,0 +1,119 @@
+use std::fmt;
+
+/// Probabilities of the transitions of a stochastic simulation, drawn from a generator seeded with `seed`
+/// so that a run can be reproduced
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Noise {
+    /// Probability that a cell born by the rule is actually born
+    pub birth: f64,
+    /// Probability that a cell killed by the rule actually dies
+    pub death: f64,
+    /// Probability that a cell of the map flips its state after each generation, the map following the pattern on the infinite plane
+    pub flip: f64,
+    /// Seed of the random number generator
+    pub seed: u64,
+}
+
+impl Default for Noise {
+    fn default() -> Self {
+        Noise {
+            birth: 1.0,
+            death: 1.0,
+            flip: 0.0,
+            seed: 0,
+        }
+    }
+}
+
+impl Noise {
+    /// Whether all the probabilities are between 0 and 1
+    pub fn is_valid(&self) -> bool {
+        [self.birth, self.death, self.flip].iter().all(|p| (0.0..=1.0).contains(p))
+    }
+}
+
+impl fmt::Display for Noise {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Random seed {}, births {}, deaths {}, flips {}", self.seed, self.birth, self.death, self.flip)
+    }
+}
+
+/// Deterministic random number generator (SplitMix64), giving the same numbers on every platform
+#[derive(Debug, Clone, PartialEq)]
+pub struct Random {
+    state: u64,
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Random {
+        Random { state: seed }
+    }
+
+    /// Next random 64 bits number
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Next random number in [0, 1)
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Whether an event of probability `p` happens. No number is drawn when the outcome is certain
+    pub fn chance(&mut self, p: f64) -> bool {
+        p >= 1.0 || (p > 0.0 && self.next_f64() < p)
+    }
+}
+
+
+// --------
+// Tests
+// --------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Test Random.next_u64
+
+    #[test]
+    fn test_next_u64() {
+        // Reference values of SplitMix64
+        let mut random = Random::new(0);
+        assert_eq!(random.next_u64(), 0xe220_a839_7b1d_cdaf);
+        assert_eq!(random.next_u64(), 0x6e78_9e6a_a1b9_65f4);
+
+        let numbers = |seed| { let mut r = Random::new(seed); (0..10).map(|_| r.next_u64()).collect::<Vec<_>>() };
+        assert_eq!(numbers(42), numbers(42));
+        assert_ne!(numbers(42), numbers(43));
+    }
+
+    // Test Random.chance
+
+    #[test]
+    fn test_chance() {
+        let mut random = Random::new(7);
+        let hits = (0..10_000).filter(|_| random.chance(0.25)).count();
+        assert!((2300..2700).contains(&hits), "{}", hits);
+
+        // Certain outcomes leave the generator untouched
+        let state = random.clone();
+        assert!(random.chance(1.0) && !random.chance(0.0));
+        assert_eq!(random, state);
+    }
+
+    // Test Noise.is_valid
+
+    #[test]
+    fn test_is_valid() {
+        assert!(Noise::default().is_valid());
+        assert!(Noise { birth: 0.95, flip: 1e-5, ..Noise::default() }.is_valid());
+        assert!(!Noise { flip: 1.5, ..Noise::default() }.is_valid());
+        assert!(!Noise { death: -0.1, ..Noise::default() }.is_valid());
+        assert!(!Noise { birth: f64::NAN, ..Noise::default() }.is_valid());
+    }
+}
@@ -1,16 +1,31 @@
 use crate::cell_map::CellMap;
-use regex::Regex;
+use crate::error::{Error, SourceError};
+use crate::hash_life::HashLife;
+use crate::rule::Rule;
+use crate::state_map::{StateMap, StateRule};
+use crate::topology::Topology;
 
+/// Pattern in the Run Length Encoded format, with two states (`b`, `o`) or up to 256 states (`.`, `A`..`X`, `pA`..`yO`)
 #[derive(Debug)]
+#[allow(clippy::upper_case_acronyms)]
 pub struct RLE {
-    pub comments: Vec<String>,
+    comments: Vec<String>,
     x: u32,
     y: u32,
     rule: Option<String>,
+    /// Coordinates of the top left cell, given by a `#CXRLE Pos=x,y` line
+    position: Option<(i64, i64)>,
+    /// Generation of the pattern, given by a `#CXRLE Gen=n` line
+    generation: i64,
     data: String,
 }
 
 impl RLE {
+    const DECODER: &'static str = "RLE decoder";
+    const EXTENSION: &'static str = "#CXRLE";
+    /// Longest data encoded from a HashLife universe, whose patterns can be far larger than a cell map
+    const MAX_LENGTH: usize = 1 << 26;
+
     fn remove_and_collect_comments(content: &str) -> (String, Vec<String>) {
         let mut cleaned_lines = Vec::new();
         let mut removed_lines = Vec::new();
@@ -26,22 +41,112 @@ impl RLE {
         (cleaned_lines.join("\n"), removed_lines)
     }
 
-    fn parse_config_string(input: &str) -> Result<(u32, u32, Option<String>), &'static str> {
-        let re = Regex::new(r"^x\s*=\s*([0-9]+)\s*,\s*y\s*=\s*([0-9]+)\s*(,\s*rule\s*=\s*(\S+))?$").unwrap();
-
-        if let Some(captures) = re.captures(input) {
-            // Extract values
-            let x: u32 = captures[1].parse().unwrap();
-            let y: u32 = captures[2].parse().unwrap();
-            let rule: Option<String> = match captures.get(4) {
-                Some(v) => Some(v.as_str().to_string()),
-                None => None,
+    /// Parse the header line `x = m, y = n, rule = abc`, `number` being its line number in the file
+    fn parse_config_string(text: &str, number: usize) -> Result<(u32, u32, Option<String>), Error> {
+        let error = |message, token: &str, column| {
+            Error::Header(SourceError::new(Self::DECODER, message, token).at(number, column, text))
+        };
+        let (mut x, mut y, mut rule) = (None, None, None);
+        let mut start = 0;
+
+        while start <= text.len() {
+            // The rule is the last field, it can contain commas (`B3/S23:T8,6`)
+            let rest = &text[start..];
+            let field = match rest.split_once(',') {
+                Some((field, _)) if !field.trim_start().starts_with("rule") => field,
+                _ => rest,
             };
+            let field_start = start;
+            start += field.len() + 1;
+            let Some((key, value)) = field.split_once('=') else {
+                return Err(error("Expected `key = value`", field.trim(), field_start + field.len() - field.trim_start().len()))
+            };
+            let key_column = field_start + key.len() - key.trim_start().len();
+            let value_column = field_start + key.len() + 1 + value.len() - value.trim_start().len();
+            let value = value.trim();
+
+            match key.trim() {
+                "x" => x = Some(value.parse::<u32>().map_err(|_| error("Invalid width", value, value_column))?),
+                "y" => y = Some(value.parse::<u32>().map_err(|_| error("Invalid height", value, value_column))?),
+                "rule" if !value.is_empty() && !value.contains(char::is_whitespace) => rule = Some(value.to_string()),
+                "rule" => return Err(error("Invalid rule", value, value_column)),
+                key => return Err(error("Unknown key", key, key_column)),
+            }
+        }
 
-            Ok((x, y, rule))
-        } else {
-            Err("[RLE decoder] Header line not found or incorrect")
+        match (x, y) {
+            (Some(x), Some(y)) => Ok((x, y, rule)),
+            (None, _) => Err(error("The width `x` is missing", "", text.len())),
+            (_, None) => Err(error("The height `y` is missing", "", text.len())),
+        }
+    }
+
+    /// Parse Golly's `#CXRLE Pos=x,y Gen=n` line, unknown keys being ignored
+    fn parse_extension(text: &str, number: usize) -> Result<(Option<(i64, i64)>, i64), Error> {
+        let (mut position, mut generation) = (None, 0);
+        let mut offset = Self::EXTENSION.len();
+        for token in text[offset..].split_whitespace() {
+            let column = offset + text[offset..].find(token).unwrap_or_default();
+            offset = column + token.len();
+            let error = |message| Error::Header(SourceError::new(Self::DECODER, message, token).at(number, column, text));
+            if let Some(value) = token.strip_prefix("Pos=") {
+                let coordinates = value.split_once(',').and_then(|(x, y)| Some((x.parse().ok()?, y.parse().ok()?)));
+                position = Some(coordinates.ok_or_else(|| error("Invalid position"))?);
+            } else if let Some(value) = token.strip_prefix("Gen=") {
+                generation = value.parse().map_err(|_| error("Invalid generation"))?;
+            }
+        }
+        Ok((position, generation))
+    }
+
+    /// Check the encoded cells and join their lines, up to the final `!`
+    fn parse_data<'a>(lines: impl Iterator<Item = (usize, &'a str)>, header: (usize, &'a str)) -> Result<String, Error> {
+        let mut data = String::new();
+        let mut last = header;
+        // Digits of the current run count and the place of the first one
+        let mut count = String::new();
+        let mut count_start = (header, 0);
+
+        for (number, text) in lines {
+            last = (number, text);
+            for (i, c) in text.char_indices() {
+                let error = |message, token: &str| Error::Body(SourceError::new(Self::DECODER, message, token).at(number, i, text));
+                match c {
+                    '0'..='9' => {
+                        if count.is_empty() {
+                            count_start = ((number, text), i);
+                        }
+                        count.push(c);
+                    }
+                    // A multi-state prefix is always followed by a state letter
+                    'p'..='y' if !text[i + 1..].starts_with(|c: char| c.is_ascii_uppercase() && c <= 'X') => {
+                        return Err(error("Invalid state", &text[i..].chars().take(2).collect::<String>()))
+                    }
+                    'b' | 'o' | '.' | 'A'..='X' | 'p'..='y' | '$' | '!' => {
+                        if !count.is_empty() {
+                            let ((line, line_text), i) = count_start;
+                            count.parse::<u32>().map_err(|_| {
+                                Error::Body(SourceError::new(Self::DECODER, "Invalid run count", &count).at(line, i, line_text))
+                            })?;
+                        }
+                        // The run count applies to the state letter following the prefix
+                        if !matches!(c, 'p'..='y') {
+                            count.clear();
+                        }
+                        if c == '!' {
+                            // Anything after the end of the pattern is ignored
+                            data.push(c);
+                            return Ok(data)
+                        }
+                    }
+                    ' ' | '\t' => {}
+                    _ => return Err(error("Unknown character", &c.to_string())),
+                }
+                data.push(c);
+            }
         }
+
+        Err(Error::Body(SourceError::new(Self::DECODER, "The pattern must end with `!`", "").at(last.0, last.1.len(), last.1)))
     }
 
     fn line_part_str(count: usize, cell: bool) -> String {
@@ -79,6 +184,37 @@ impl RLE {
         rle_line
     }
 
+    /// Letters of a state of a multi-state pattern: `.`, `A` for 1 to `X` for 24, `pA` for 25 to `yO` for 255
+    fn state_str(state: u8) -> String {
+        match state {
+            0 => String::from("."),
+            1..=24 => char::from(b'A' + state - 1).to_string(),
+            _ => {
+                let (prefix, letter) = ((state - 25) / 24, (state - 25) % 24);
+                format!("{}{}", char::from(b'p' + prefix), char::from(b'A' + letter))
+            }
+        }
+    }
+
+    fn encode_state_line(line: &[u8]) -> String {
+        let mut rle_line = String::new();
+        let mut i = 0;
+        while i < line.len() {
+            let state = line[i];
+            let count = line[i..].iter().take_while(|&&s| s == state).count();
+            i += count;
+            // Dead cells at the end of the line are omitted
+            if state == 0 && i == line.len() {
+                break
+            }
+            if count > 1 {
+                rle_line += &count.to_string();
+            }
+            rle_line += &Self::state_str(state);
+        }
+        rle_line
+    }
+
     fn process_empty_lines(all_lines: &mut Vec<String>) {
         let mut count_empty_lines = 0;
         let mut i = 0;
@@ -97,17 +233,17 @@ impl RLE {
         }
     }
 
-    fn lines_to_map(&self, all_lines: &mut Vec<Vec<(u32, bool)>>) -> Vec<Vec<bool>> {
-        let mut map = vec![vec![false; self.x as usize]; self.y as usize];
+    fn lines_to_map(&self, all_lines: &mut [Vec<(u32, u8)>]) -> Vec<Vec<u8>> {
+        let mut map = vec![vec![0; self.x as usize]; self.y as usize];
 
-        for i in 0..map.len() {
-            for j in 0..map[i].len() {
-                map[i][j] = all_lines[i][0].1;
-                if all_lines[i][0].0 > 1 {
-                    all_lines[i][0].0 -= 1;
+        for (row, line) in map.iter_mut().zip(all_lines.iter_mut()) {
+            for cell in row.iter_mut() {
+                let Some(run) = line.first_mut() else { break };
+                *cell = run.1;
+                if run.0 > 1 {
+                    run.0 -= 1;
                 } else {
-                    all_lines[i].remove(0);
-                    if all_lines[i].is_empty() { break }
+                    line.remove(0);
                 }
             }
         }
@@ -115,28 +251,40 @@ impl RLE {
         map
     }
 
-    fn process_file_lines(&self, all_lines: &Vec<&str>) -> Result<Vec<Vec<(u32, bool)>>, &'static str> {
-        let mut all_lines_parsed: Vec<Vec<(u32, bool)>> = Vec::new();
+    fn process_file_lines(&self, all_lines: &[&str]) -> Result<Vec<Vec<(u32, u8)>>, Error> {
+        let mut all_lines_parsed: Vec<Vec<(u32, u8)>> = Vec::new();
 
         for line in all_lines {
-            let mut line_parsed: Vec<(u32, bool)> = Vec::new();
+            let mut line_parsed: Vec<(u32, u8)> = Vec::new();
             let mut number_construct = String::new();
+            // Value of the multi-state prefix letter (`p` is 1, `y` is 10)
+            let mut prefix = 0u32;
 
             for c in line.chars() {
                 if c.is_ascii_digit() {
                     number_construct.push(c);
-                } else if c == 'b' || c == 'o' {
+                } else if ('p'..='y').contains(&c) {
+                    prefix = c as u32 - 'p' as u32 + 1;
+                } else if matches!(c, 'b' | 'o' | '.' | 'A'..='X') {
                     let factor: u32 = if !number_construct.is_empty() {
                         number_construct.parse()
-                            .map_err(|_| "[RLE decoder] Error while parsing RLE file: Number parsing")?
+                            .map_err(|_| Error::Body(SourceError::new(Self::DECODER, "Invalid run count", &number_construct)))?
                     } else {
                         1
                     };
+                    let state = match c {
+                        'b' | '.' => 0,
+                        'o' => 1,
+                        _ => prefix * 24 + c as u32 - 'A' as u32 + 1,
+                    };
+                    let state = u8::try_from(state)
+                        .map_err(|_| Error::Body(SourceError::new(Self::DECODER, "Invalid state", &c.to_string())))?;
 
-                    line_parsed.push((factor, if c == 'o' { true } else { false }));
+                    line_parsed.push((factor, state));
                     number_construct = String::new();
+                    prefix = 0;
                 } else {
-                    return Err("[RLE decoder] Error while parsing RLE file: Unknown character")
+                    return Err(Error::Body(SourceError::new(Self::DECODER, "Unknown character", &c.to_string())))
                 }
             }
 
@@ -147,10 +295,10 @@ impl RLE {
 
             if !number_construct.is_empty() {
                 let n = number_construct.parse::<u32>()
-                    .map_err(|_| "[RLE decoder] Error while parsing RLE file: Number parsing")?;
+                    .map_err(|_| Error::Body(SourceError::new(Self::DECODER, "Invalid run count", &number_construct)))?;
 
                 let count_empty_lines = if is_last_line_empty { n } else { n - 1 };
-                all_lines_parsed.extend(std::iter::repeat(vec![(self.x, false)]).take(count_empty_lines as usize));
+                all_lines_parsed.extend(std::iter::repeat_n(vec![(self.x, 0)], count_empty_lines as usize));
             }
         }
 
@@ -159,48 +307,101 @@ impl RLE {
 
     // ---------
 
-    pub fn parse(mut file_content: String) -> Result<RLE, &'static str> {
-        // Store comments lines
-        let (cleaned_content, comments) = Self::remove_and_collect_comments(&file_content);
-        file_content = cleaned_content;
+    pub fn parse(file_content: String) -> Result<RLE, Error> {
+        // Store comments lines, except the position and the generation
+        let (_, mut comments) = Self::remove_and_collect_comments(&file_content);
+        comments.retain(|text| !text.trim_start().starts_with(Self::EXTENSION));
+        let (mut position, mut generation) = (None, 0);
+        for (i, text) in file_content.lines().enumerate() {
+            if text.starts_with(Self::EXTENSION) {
+                (position, generation) = Self::parse_extension(text, i + 1)?;
+            }
+        }
+        // Other lines, numbered from 1 for the error messages
+        let mut lines = file_content.lines()
+            .enumerate()
+            .map(|(i, text)| (i + 1, text))
+            .filter(|(_, text)| !text.trim_start().starts_with('#'));
         // Extract information
-        let (x, y, rule): (u32, u32, Option<String>) =  match file_content.lines().nth(0) {
-            Some(desc) => Self::parse_config_string(desc.trim()),
-            None => return Err("[RLE decoder] Header line not found"),
-        }?;
-        // Group data
-        let mut group: Vec<&str> = file_content.lines().collect();
-        group.remove(0);
-        file_content = group.join("");
+        let header = lines.next()
+            .ok_or_else(|| Error::Header(SourceError::new(Self::DECODER, "Header line not found", "")))?;
+        let (x, y, rule) = Self::parse_config_string(header.1, header.0)?;
         // Verify and extract data
-        let re = Regex::new(r"^((\s?[0-9]*[bo]\s?)*\s?[0-9]*\s?[$]?)+\s?!$").unwrap();
-        let data = if re.is_match(&file_content) {
-            file_content
-        } else {
-            return Err("[RLE decoder] Content not found or incorrect.")
-        };
+        let data = Self::parse_data(lines, header)?;
 
-        Ok(RLE { comments, x, y, rule, data })
+        Ok(RLE { comments, x, y, rule, position, generation, data })
     }
 
     pub fn from_cell_map(c: &CellMap, comments: Option<&Vec<String>>) -> RLE {
+        let topology = *c.topology();
+        let grid = c.origin();
         let c = c.auto_crop();
-        let mut all_lines: Vec<String> = c.actual_generation.iter()
-            .map(|line| Self::encode_rle_line(line))
+        // The cells are written in their state, the background of a B0 rule being given by the generation
+        let mut all_lines: Vec<String> = c.cells().iter()
+            .map(|line| Self::encode_rle_line(&line.iter().map(|&v| v != c.background()).collect::<Vec<bool>>()))
             .collect();
 
         Self::process_empty_lines(&mut all_lines);
 
         RLE {
             comments: if let Some(c) = comments { c.clone() } else { Vec::<String>::new() },
-            x: c.w,
-            y: c.h,
-            rule: Some(String::from("B3/S23")),
+            x: c.width(),
+            y: c.height(),
+            rule: Some(format!("{}{}", c.rule(), topology)),
+            position: Some(match topology.size() {
+                Some((w, h)) => Self::grid_position(c.origin(), grid, w, h),
+                None => c.origin(),
+            }),
+            generation: 0,
             data: all_lines.join("$") + "!",
         }
     }
 
-    pub fn to_cell_map(&self) -> Result<CellMap, &'static str> {
+    /// Encode the pattern of a HashLife universe row by row from its quadtree, without building a cell map.
+    /// Fails when the encoded pattern is too long
+    pub fn from_hash_life(h: &HashLife, comments: Option<&Vec<String>>) -> Result<RLE, Error> {
+        let too_large = || Error::Export(String::from("[RLE encoder] The pattern is too large to be written in RLE"));
+        let (min_x, min_y, max_x, max_y) = h.bounding_box().unwrap_or_default();
+        let (Ok(x), Ok(y)) = (u32::try_from(max_x.abs_diff(min_x) + 1), u32::try_from(max_y.abs_diff(min_y) + 1)) else {
+            return Err(too_large())
+        };
+        // Every row holding live cells takes at least two characters, such as `o$`
+        if h.min_rows().saturating_mul(2) > Self::MAX_LENGTH as u64 {
+            return Err(too_large())
+        }
+
+        let mut data = String::new();
+        let mut last_row = min_y;
+        h.visit_rows(|row, runs| {
+            // Rows without live cells are counted in the end of line of the previous row
+            match row.abs_diff(last_row) {
+                0 => (),
+                1 => data.push('$'),
+                n => data += &format!("{}$", n),
+            }
+            let mut next_x = min_x;
+            for &(first, last) in runs {
+                data += &Self::line_part_str(first.abs_diff(next_x) as usize, false);
+                data += &Self::line_part_str((last.abs_diff(first) + 1) as usize, true);
+                next_x = last + 1;
+            }
+            last_row = row;
+            if data.len() > Self::MAX_LENGTH { Err(too_large()) } else { Ok(()) }
+        })?;
+
+        Ok(RLE {
+            comments: if let Some(c) = comments { c.clone() } else { Vec::<String>::new() },
+            x,
+            y,
+            rule: Some(h.rule().to_string()),
+            position: Some((min_x, min_y)),
+            generation: h.generation(),
+            data: data + "!",
+        })
+    }
+
+    /// Build a multi-state map, the rule being kept as it is written in the header
+    pub fn to_state_map(&self) -> Result<StateMap, Error> {
         let mut cleaned_data = self.data.replace(" ", "");
         // Remove "!"
         cleaned_data.pop();
@@ -209,12 +410,102 @@ impl RLE {
 
         let mut all_lines_parsed = self.process_file_lines(&all_lines)?;
 
-        Ok(CellMap::new(self.lines_to_map(&mut all_lines_parsed))?)
+        let mut state_map = StateMap::new(self.lines_to_map(&mut all_lines_parsed))?;
+        state_map.set_origin(self.position.unwrap_or_default());
+        state_map.set_rule(self.rule.as_deref().map(StateRule::parse).unwrap_or_default());
+        Ok(state_map)
+    }
+
+    /// Encode a multi-state map with the `.` and `A`..`X` letters
+    pub fn from_state_map(s: &StateMap, comments: Option<&Vec<String>>) -> RLE {
+        let s = s.auto_crop();
+        let mut all_lines: Vec<String> = s.cells().iter()
+            .map(|line| Self::encode_state_line(line))
+            .collect();
+
+        Self::process_empty_lines(&mut all_lines);
+
+        RLE {
+            comments: if let Some(c) = comments { c.clone() } else { Vec::<String>::new() },
+            x: s.width(),
+            y: s.height(),
+            rule: Some(s.rule().to_string()),
+            position: Some(s.origin()),
+            generation: 0,
+            data: all_lines.join("$") + "!",
+        }
+    }
+
+    /// Build a two-state map, fails if the pattern has more than two states
+    pub fn to_cell_map(&self) -> Result<CellMap, Error> {
+        let mut cell_map = self.to_state_map()?.to_cell_map()?;
+        let rule = self.rule()?;
+        cell_map.set_rule(rule);
+        let topology = self.topology()?;
+        cell_map.set_topology(topology)?;
+        if let (Some((w, h)), Some(_)) = (topology.size(), self.position) {
+            cell_map.set_grid_origin(Self::grid_position((0, 0), (0, 0), w, h))?;
+        }
+        cell_map.set_phase(self.generation);
+        Ok(cell_map)
+    }
+
+    /// Coordinates of a cell in a bounded grid whose top left cell is at `grid`, moved like in Golly
+    /// so that the top left cell of the grid is at (-w/2, -h/2)
+    fn grid_position(cell: (i64, i64), grid: (i64, i64), w: u32, h: u32) -> (i64, i64) {
+        (cell.0 - grid.0 - (w / 2) as i64, cell.1 - grid.1 - (h / 2) as i64)
+    }
+
+    /// Comment lines (`#N`, `#C`...) of the file
+    pub fn comments(&self) -> &[String] {
+        &self.comments
+    }
+
+    /// Coordinates of the top left cell of the pattern, (0, 0) if none are given
+    pub fn position(&self) -> (i64, i64) {
+        self.position.unwrap_or_default()
+    }
+
+    /// Generation of the pattern, 0 if none is given. It is negative for a pattern run backwards from generation 0
+    pub fn generation(&self) -> i64 {
+        self.generation
+    }
+
+    /// Change the generation written in the `#CXRLE` line
+    pub fn set_generation(&mut self, generation: i64) {
+        self.generation = generation;
+    }
+
+    /// Rule declared in the header, Conway's Game of Life if none is given
+    pub fn rule(&self) -> Result<Rule, Error> {
+        match &self.rule {
+            Some(r) => Rule::parse(r.split(':').next().unwrap_or_default()).map_err(Error::Rule),
+            None => Ok(Rule::default()),
+        }
+    }
+
+    /// Topology given after the `:` of the rule, the infinite plane if none is given
+    pub fn topology(&self) -> Result<Topology, Error> {
+        match self.rule.as_ref().and_then(|r| r.split_once(':')) {
+            Some((_, suffix)) => Topology::parse(suffix).map_err(Error::Rule),
+            None => Ok(Topology::Plane),
+        }
     }
 
     pub fn export(&self) -> String {
+        // Add position and generation, only when they are not the default ones
+        let mut content = String::new();
+        let position = self.position.filter(|&p| p != (0, 0));
+        if position.is_some() || self.generation != 0 {
+            let (x, y) = self.position.unwrap_or_default();
+            content += &format!("{} Pos={},{}", Self::EXTENSION, x, y);
+            if self.generation != 0 {
+                content += &format!(" Gen={}", self.generation);
+            }
+            content += "\n";
+        }
         // Add comments
-        let mut content = String::from("#C Generated by ALife");
+        content += "#C Generated by ALife";
         if !&self.comments.is_empty() {
             content += "\n";
         }
@@ -230,9 +521,9 @@ impl RLE {
         content
     }
 
-    pub fn file_to_cell_map(file_content: String) -> Result<CellMap, &'static str> {
+    pub fn file_to_cell_map(file_content: String) -> Result<CellMap, Error> {
         let rle = Self::parse(file_content)?;
-        Ok(rle.to_cell_map()?)
+        rle.to_cell_map()
     }
 
     pub fn cell_map_to_file(c: &CellMap, comments: Option<&Vec<String>>) -> String {
@@ -265,7 +556,7 @@ mod tests {
 
     // Test RLE::parse
     #[test]
-    fn test_parse_1() -> Result<(), &'static str> {
+    fn test_parse_1() -> Result<(), Error> {
         let content = String::from("#N Gosper glider gun
 #C This was the first gun discovered.
 #C As its name suggests, it was discovered by Bill Gosper.
@@ -288,7 +579,7 @@ bo7bo$11bo3bo$12b2o!");
     }
 
     #[test]
-    fn test_parse_2() -> Result<(), &'static str> {
+    fn test_parse_2() -> Result<(), Error> {
         let content = String::from("x = 36, y = 9
 24bo$22bobo$12b2o6b2o12b2o$11bo3bo4b2o12b2o$2o8bo5bo3b2o$2o8bo3bob2o4bobo$ 10b o 5b o 7b o $11bo3bo$12b2o !");
 
@@ -306,19 +597,55 @@ bo7bo$11bo3bo$12b2o!");
     }
 
     #[test]
-    fn test_parse_3() -> Result<(), &'static str> {
+    fn test_parse_3() -> Result<(), Error> {
         let content = String::from("x = 36, y = 9
 24bo$22bobo$12b2o6b2o12b2o$11bo3ao4b2o12b2o$2o8bo5bo3b2o$2o8bo3bob2o4bobo$10bo5bo7bo$11bo3bo$12b2o!");
 
         match RLE::parse(content) {
             Ok(_) => panic!("The result should not be Ok"),
-            Err(error) => assert_eq!(error, "[RLE decoder] Content not found or incorrect."),
+            Err(Error::Body(e)) => {
+                assert_eq!(e.token, "a");
+                assert_eq!(e.location.map(|l| (l.line, l.column)), Some((2, 33)));
+            }
+            Err(error) => panic!("Unexpected error {}", error),
         }
         Ok(())
     }
 
     #[test]
-    fn test_parse_4() -> Result<(), &'static str> {
+    fn test_parse_header_errors() {
+        let error = |content: &str| match RLE::parse(content.to_string()) {
+            Err(Error::Header(e)) => (e.message, e.token, e.location.map(|l| l.column)),
+            _ => panic!("A header error was expected"),
+        };
+
+        assert_eq!(error("#C Empty"), ("Header line not found", String::new(), None));
+        assert_eq!(error("x = 3, y = 3a\n3o!"), ("Invalid height", String::from("3a"), Some(12)));
+        assert_eq!(error("x = 3, z = 3\n3o!"), ("Unknown key", String::from("z"), Some(8)));
+        assert_eq!(error("x = 3\n3o!"), ("The height `y` is missing", String::new(), Some(6)));
+    }
+
+    #[test]
+    fn test_parse_body_errors() {
+        let error = |content: &str| match RLE::parse(content.to_string()) {
+            Err(Error::Body(e)) => (e.message, e.location.map(|l| (l.line, l.column))),
+            _ => panic!("A body error was expected"),
+        };
+
+        assert_eq!(error("x = 3, y = 1\n3o"), ("The pattern must end with `!`", Some((2, 3))));
+        assert_eq!(error("x = 3, y = 1\n99999999999o!"), ("Invalid run count", Some((2, 1))));
+        assert_eq!(error("x = 3, y = 1\n#C Comment\nbo$\n2c!"), ("Unknown character", Some((4, 2))));
+    }
+
+    #[test]
+    fn test_parse_ignores_trailing_text() {
+        let rle = RLE::parse(String::from("x = 3, y = 1\n3o! Blinker")).unwrap();
+
+        assert_eq!(rle.data, "3o!");
+    }
+
+    #[test]
+    fn test_parse_4() -> Result<(), Error> {
         let content = String::from("#N 20cellquadraticgrowth.rle
 #O dani, 2022
 #C https://conwaylife.com/wiki/20-cell_quadratic_growth
@@ -339,6 +666,26 @@ x = 97, y = 33, rule = B3/S23
         Ok(())
     }
 
+    #[test]
+    fn test_parse_extension() {
+        let rle = RLE::parse(String::from("#CXRLE Pos=-12,7 Gen=1000\n#C Glider\nx = 3, y = 3\nbo$2bo$3o!")).unwrap();
+
+        assert_eq!(rle.comments, vec!["#C Glider"]);
+        assert_eq!(rle.position(), (-12, 7));
+        assert_eq!(rle.generation(), 1000);
+        assert_eq!(rle.to_cell_map().unwrap().origin(), (-12, 7));
+        // A pattern run backwards from generation 0
+        assert_eq!(RLE::parse(String::from("#CXRLE Pos=0,0 Gen=-3\nx = 1, y = 1\no!")).unwrap().generation(), -3);
+
+        match RLE::parse(String::from("#CXRLE Pos=3 Gen=1\nx = 3, y = 1\n3o!")) {
+            Err(Error::Header(e)) => {
+                assert_eq!((e.message, e.token.as_str()), ("Invalid position", "Pos=3"));
+                assert_eq!(e.location.map(|l| (l.line, l.column)), Some((1, 8)));
+            }
+            _ => panic!("A header error was expected"),
+        }
+    }
+
     // Test RLE.export
 
     #[test]
@@ -348,6 +695,8 @@ x = 97, y = 33, rule = B3/S23
             x: 36,
             y: 9,
             rule: Some("B3/S23".to_string()),
+            position: None,
+            generation: 0,
             data: "24bo$22bobo$12b2o6b2o12b2o$11bo3bo4b2o12b2o$2o8bo5bo3b2o$2o8bo3bob2o4bobo$10bo5bo7bo$11bo3bo$12b2o!".to_string()
         };
 
@@ -370,6 +719,8 @@ x = 97, y = 33, rule = B3/S23
             x: 36,
             y: 9,
             rule: Some("B3/S23".to_string()),
+            position: None,
+            generation: 0,
             data: "24bo$22bobo$12b2o6b2o12b2o$11bo3bo4b2o12b2o$2o8bo5bo3b2o$2o8bo3bob2o4bobo$10bo5bo7bo$11bo3bo$12b2o!".to_string()
         };
 
@@ -443,6 +794,46 @@ x = 97, y = 33, rule = B3/S23
         assert_eq!(rle.data, String::from("bo3$2bo2$3o!"));
     }
 
+    // Test RLE::from_hash_life
+
+    #[test]
+    fn test_from_hash_life() {
+        // A full block of 8x8 cells, empty rows and a glider placed at negative coordinates
+        let mut cells = vec![vec![false; 20]; 20];
+        for row in cells.iter_mut().skip(2).take(8) {
+            row[1..9].fill(true);
+        }
+        cells[15][18] = true;
+        cells[16][19] = true;
+        cells[17][17..20].fill(true);
+        let mut c = CellMap::new(cells).unwrap();
+        c.set_origin((-7, -3));
+        let h = HashLife::from_cell_map(&c).unwrap();
+
+        let rle = RLE::from_hash_life(&h, Some(&vec!["#C Block and glider".to_string()])).unwrap();
+
+        let expected = RLE::from_cell_map(&c, Some(&vec!["#C Block and glider".to_string()]));
+        assert_eq!((rle.x, rle.y, rle.position), (expected.x, expected.y, expected.position));
+        assert_eq!(rle.data, expected.data);
+        assert_eq!(rle.export(), expected.export());
+    }
+
+    #[test]
+    fn test_from_hash_life_too_large() {
+        // The gliders of a Gosper glider gun spread over a billion rows after four billion generations
+        let gun = RLE::file_to_cell_map(String::from(
+            "x = 36, y = 9\n24bo$22bobo$12b2o6b2o12b2o$11bo3bo4b2o12b2o$2o8bo5bo3b2o$2o8bo3bob2o4bobo$10bo5bo7bo$11bo3bo$12b2o!"
+        )).unwrap();
+        let mut h = HashLife::from_cell_map(&gun).unwrap();
+        h.run(1 << 32).unwrap();
+
+        assert!(h.to_cell_map().is_err());
+        match RLE::from_hash_life(&h, None) {
+            Ok(_) => panic!("The result should not be Ok"),
+            Err(e) => assert!(e.to_string().contains("too large to be written in RLE")),
+        }
+    }
+
     // Test RLE::cell_map_to_file
 
     #[test]
@@ -460,11 +851,49 @@ x = 97, y = 33, rule = B3/S23
             vec![false, false, false, false, false],
         ]).unwrap();
 
-        let file_content = String::from("#C Generated by ALife\n#C Stretched glider\nx = 3, y = 6, rule = B3/S23\nbo3$2bo2$3o!");
+        // The glider does not start at the top left cell of the map
+        let file_content = String::from("#CXRLE Pos=1,2\n#C Generated by ALife\n#C Stretched glider\nx = 3, y = 6, rule = B3/S23\nbo3$2bo2$3o!");
 
         assert_eq!(RLE::cell_map_to_file(&c, Some(&vec![String::from("#C Stretched glider")])), file_content);
     }
 
+    #[test]
+    fn test_cell_map_to_file_moving() {
+        // A glider exported after 100 generations is read back at the same place
+        let mut c = RLE::file_to_cell_map(String::from("x = 3, y = 3\nbo$2bo$3o!")).unwrap();
+        for _ in 0..100 {
+            c.generate_next();
+        }
+        let mut rle = RLE::from_cell_map(&c, None);
+        rle.set_generation(100);
+
+        let content = rle.export();
+        let back = RLE::parse(content.clone()).unwrap();
+
+        assert!(content.starts_with("#CXRLE Pos=25,25 Gen=100\n"));
+        assert_eq!(back.generation(), 100);
+        assert_eq!(back.to_cell_map().unwrap().origin(), (25, 25));
+    }
+
+    #[test]
+    fn test_from_cell_map_b0() {
+        // With a live background, the cells are written in their state and the generation gives the phase
+        let mut c = RLE::file_to_cell_map(String::from("x = 2, y = 1, rule = B0/S
+2o!")).unwrap();
+        c.generate_next();
+        let mut rle = RLE::from_cell_map(&c, None);
+        rle.set_generation(1);
+
+        let back = RLE::parse(rle.export()).unwrap().to_cell_map().unwrap();
+
+        // The 4x3 dead cells around the two cells which died
+        assert_eq!((rle.x, rle.y, rle.data.as_str()), (4, 3, "!"));
+        assert!(back.background());
+        assert!(!back.is_alive(0, 0) && back.is_alive(-2, 0));
+        assert_eq!(back.cells(), c.cells());
+        assert_eq!(back.bounding_box(), c.bounding_box());
+    }
+
     // Test RLE.to_cell_map
 
     #[test]
@@ -474,6 +903,8 @@ x = 97, y = 33, rule = B3/S23
             x: 3,
             y: 6,
             rule: None,
+            position: None,
+            generation: 0,
             data: String::from("b o 2 $ 2b o 3 $3o!")
         };
 
@@ -488,9 +919,9 @@ x = 97, y = 33, rule = B3/S23
 
         let result = rle.to_cell_map().unwrap();
 
-        assert_eq!(result.w, cell_map.w);
-        assert_eq!(result.h, cell_map.h);
-        assert_eq!(result.actual_generation, cell_map.actual_generation);
+        assert_eq!(result.width(), cell_map.width());
+        assert_eq!(result.height(), cell_map.height());
+        assert_eq!(result.cells(), cell_map.cells());
     }
 
     #[test]
@@ -500,6 +931,8 @@ x = 97, y = 33, rule = B3/S23
             x: 11,
             y: 14,
             rule: None,
+            position: None,
+            generation: 0,
             data: String::from("b o 2 $10bo$10$3o!")
         };
 
@@ -514,9 +947,192 @@ x = 97, y = 33, rule = B3/S23
 
         let result = rle.to_cell_map().unwrap();
 
-        assert_eq!(result.w, cell_map.w);
-        assert_eq!(result.h, cell_map.h);
-        assert_eq!(result.actual_generation, cell_map.actual_generation);
+        assert_eq!(result.width(), cell_map.width());
+        assert_eq!(result.height(), cell_map.height());
+        assert_eq!(result.cells(), cell_map.cells());
+    }
+
+    #[test]
+    fn test_to_cell_map_rule() {
+        let rle = RLE::parse(String::from("x = 3, y = 1, rule = 23/36\n3o!")).unwrap();
+
+        let result = rle.to_cell_map().unwrap();
+
+        assert_eq!(result.rule().to_string(), "B36/S23");
+        assert_eq!(RLE::from_cell_map(&result, None).rule, Some(String::from("B36/S23")));
+    }
+
+    #[test]
+    fn test_to_cell_map_isotropic_rule() {
+        let rle = RLE::parse(String::from("x = 3, y = 1, rule = b2-a/s12\n3o!")).unwrap();
+
+        let result = rle.to_cell_map().unwrap();
+
+        assert_eq!(result.rule().to_string(), "B2-a/S12");
+        assert_eq!(RLE::from_cell_map(&result, None).export(), "#C Generated by ALife\nx = 3, y = 1, rule = B2-a/S12\n3o!");
+    }
+
+    #[test]
+    fn test_to_cell_map_larger_than_life_rule() {
+        // The commas of the rule are not separators of the header
+        let rle = RLE::parse(String::from("x = 3, y = 1, rule = R5,C0,M1,S34..58,B34..45,NM:T40,40\n3o!")).unwrap();
+
+        let result = rle.to_cell_map().unwrap();
+
+        assert_eq!(result.rule().larger_than_life().map(|r| r.range()), Some(5));
+        assert_eq!(RLE::from_cell_map(&result, None).rule, Some(String::from("R5,C0,M1,S34..58,B34..45,NM:T40,40")));
+    }
+
+    #[test]
+    fn test_to_cell_map_map_rule() {
+        // The base64 characters of a MAP rule keep their case
+        let map = Rule::parse("B2-a/S12").unwrap().to_map_string().unwrap();
+        let rle = RLE::parse(format!("x = 3, y = 1, rule = {}\n3o!", map)).unwrap();
+
+        let result = rle.to_cell_map().unwrap();
+
+        assert!(result.rule().is_isotropic());
+        assert_eq!(RLE::from_cell_map(&result, None).rule, Some(map));
+    }
+
+    #[test]
+    fn test_to_cell_map_margolus_rule() {
+        // The generation gives the position of the blocks
+        let mut c = RLE::file_to_cell_map(String::from("x = 2, y = 2, rule = Critters\n2o$bo!")).unwrap();
+        c.generate_next();
+        let mut rle = RLE::from_cell_map(&c, None);
+        rle.set_generation(1);
+        assert_eq!(rle.rule.as_deref(), Some("Critters"));
+
+        let mut back = RLE::parse(rle.export()).unwrap().to_cell_map().unwrap();
+        back.generate_next();
+        c.generate_next();
+
+        assert_eq!(back.cells(), c.cells());
+        assert_eq!(back.bounding_box(), c.bounding_box());
+    }
+
+    #[test]
+    fn test_to_cell_map_invalid_rule() {
+        let rle = RLE::parse(String::from("x = 3, y = 1, rule = B3/S29\n3o!")).unwrap();
+
+        assert!(rle.to_cell_map().is_err());
+    }
+
+    #[test]
+    fn test_to_cell_map_topology() {
+        let rle = RLE::parse(String::from("x = 3, y = 3, rule = B3/S23:T8,6\nbo$2bo$3o!")).unwrap();
+
+        let result = rle.to_cell_map().unwrap();
+
+        assert_eq!(*result.topology(), Topology::Torus { w: 8, h: 6 });
+        assert_eq!((result.width(), result.height()), (8, 6));
+        // The glider is centered in the torus
+        assert!(result.cells()[1][3]);
+        assert!(result.cells()[3][2]);
+        // The top left cell of the grid is at (-4, -3)
+        assert_eq!(RLE::cell_map_to_file(&result, None), "#CXRLE Pos=-2,-2\n#C Generated by ALife\nx = 3, y = 3, rule = B3/S23:T8,6\nbo$2bo$3o!");
+    }
+
+    #[test]
+    fn test_to_cell_map_topology_position() {
+        // The glider crosses the border of the torus and keeps its place in the grid
+        let mut c = RLE::file_to_cell_map(String::from("x = 3, y = 3, rule = B3/S23:T8,6\nbo$2bo$3o!")).unwrap();
+        for _ in 0..10 {
+            c.generate_next();
+        }
+
+        let back = RLE::parse(RLE::cell_map_to_file(&c, None)).unwrap().to_cell_map().unwrap();
+
+        assert_eq!(back.cells(), c.cells());
+        assert_eq!(RLE::cell_map_to_file(&back, None), RLE::cell_map_to_file(&c, None));
+        let outside = RLE::parse(String::from("#CXRLE Pos=3,0\nx = 3, y = 3, rule = B3/S23:P8,6\nbo$2bo$3o!")).unwrap();
+        assert!(outside.to_cell_map().is_err());
+    }
+
+    #[test]
+    fn test_to_cell_map_topology_too_small() {
+        let rle = RLE::parse(String::from("x = 3, y = 3, rule = B3/S23:P2,2\nbo$2bo$3o!")).unwrap();
+
+        assert!(rle.to_cell_map().is_err());
+    }
+
+    // Test RLE.to_state_map
+
+    #[test]
+    fn test_to_state_map() {
+        // WireWorld diode
+        let rle = RLE::parse(String::from("x = 7, y = 3, rule = WireWorld\n2.2C$CBA.3C$2.2C!")).unwrap();
+
+        let result = rle.to_state_map().unwrap();
+
+        assert_eq!(result.rule(), &StateRule::Other(String::from("WireWorld")));
+        assert_eq!(result.cells(), vec![
+            vec![0, 0, 3, 3, 0, 0, 0],
+            vec![3, 2, 1, 0, 3, 3, 3],
+            vec![0, 0, 3, 3, 0, 0, 0],
+        ]);
+        assert!(rle.to_cell_map().is_err());
+    }
+
+    #[test]
+    fn test_to_state_map_rule_file() {
+        // The rule is loaded from the rule file of the same name
+        let rle = RLE::parse(String::from("x = 7, y = 3, rule = WireWorld\n2.2C$CBA.3C$2.2C!")).unwrap();
+        let mut s = rle.to_state_map().unwrap();
+        s.set_rule(s.rule().resolve(std::path::Path::new("rules")).unwrap());
+
+        for _ in 0..4 {
+            s.generate_next().unwrap();
+        }
+
+        // Cells of WireWorld never die
+        assert_eq!(s.population(), 10);
+        assert_eq!(s.auto_crop().cells(), vec![
+            vec![0, 0, 1, 1, 0, 0, 0],
+            vec![2, 3, 2, 0, 3, 2, 1],
+            vec![0, 0, 1, 1, 0, 0, 0],
+        ]);
+        assert!(RLE::from_state_map(&s, None).export().contains("rule = WireWorld\n"));
+    }
+
+    #[test]
+    fn test_to_state_map_prefix() {
+        let rle = RLE::parse(String::from("x = 4, y = 2, rule = B2/S/C256\n2pAyO$o.X!")).unwrap();
+
+        let result = rle.to_state_map().unwrap();
+
+        assert_eq!(result.cells(), vec![vec![25, 25, 255, 0], vec![1, 0, 24, 0]]);
+    }
+
+    #[test]
+    fn test_to_state_map_errors() {
+        let error = |content: &str| match RLE::parse(content.to_string()).and_then(|rle| rle.to_state_map()) {
+            Err(Error::Body(e)) => (e.message, e.token),
+            _ => panic!("A body error was expected"),
+        };
+
+        assert_eq!(error("x = 3, y = 1\n2pb!"), ("Invalid state", String::from("pb")));
+        assert_eq!(error("x = 3, y = 1\nyP!"), ("Invalid state", String::from("P")));
+        assert_eq!(error("x = 3, y = 1\nAZ!"), ("Unknown character", String::from("Z")));
+    }
+
+    // Test RLE::from_state_map
+
+    #[test]
+    fn test_from_state_map() {
+        let mut s = StateMap::new(vec![
+            vec![0, 0, 0, 0],
+            vec![3, 3, 0, 0],
+            vec![0, 0, 0, 0],
+            vec![1, 0, 26, 0],
+        ]).unwrap();
+        s.set_rule(StateRule::parse("LifeHistory"));
+
+        let rle = RLE::from_state_map(&s, None);
+
+        assert_eq!(rle.export(), "#CXRLE Pos=0,1\n#C Generated by ALife\nx = 3, y = 3, rule = LifeHistory\n2C2$A.pB!");
+        assert_eq!(RLE::parse(rle.export()).unwrap().to_state_map().unwrap(), s.auto_crop());
     }
 
     // Test RLE::file_to_cell_map
@@ -540,8 +1156,8 @@ x = 12, y = 8, rule = B3/S23
 
         let result = RLE::file_to_cell_map(file_content).unwrap();
 
-        assert_eq!(result.w, 12);
-        assert_eq!(result.h, 8);
-        assert_eq!(result.actual_generation, map);
+        assert_eq!(result.width(), 12);
+        assert_eq!(result.height(), 8);
+        assert_eq!(result.cells(), map);
     }
 }
\ No newline at end of file
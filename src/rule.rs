@@ -0,0 +1,789 @@
+use std::fmt;
+use std::str::FromStr;
+use crate::larger_than_life::LargerThanLife;
+use crate::margolus::Margolus;
+use crate::wolfram::Wolfram;
+
+/// Hensel letters of the isotropic configurations, per number of neighbours
+const LETTERS: [&str; 9] = ["", "ce", "cekain", "cekainyqjr", "cekainyqjrtwz", "cekainyqjr", "cekain", "ce", ""];
+
+/// One neighbourhood of each configuration of 1 to 4 neighbours, in the order of `LETTERS`.
+/// Bit `3 * y + x` is the cell at (x, y) of the 3x3 block, the center being bit 4
+const REPRESENTATIVES: [&[u16]; 5] = [
+    &[0],
+    &[1, 2],
+    &[5, 10, 33, 3, 40, 68],
+    &[69, 42, 98, 11, 7, 13, 97, 70, 14, 41],
+    &[325, 170, 99, 15, 45, 71, 101, 102, 106, 43, 105, 78, 108],
+];
+
+/// Bits of the eight neighbours of a neighbourhood
+const NEIGHBOURS: u16 = 0x1ef;
+
+/// Index of the 3x3 neighbourhood whose cells are all alive
+const NEIGHBOURHOOD: usize = 0x1ff;
+
+/// Characters of the base64 encoding of the MAP rules
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Cells counted as the neighbours of a cell, selected by the suffix of the rule
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Neighbourhood {
+    /// The eight surrounding cells
+    #[default]
+    Moore,
+    /// Six cells of a hexagonal grid whose rows are skewed by half a cell: all the surrounding cells
+    /// but the top right and bottom left ones, suffix `H`
+    Hexagonal,
+    /// The four orthogonally adjacent cells, suffix `V`
+    VonNeumann,
+}
+
+impl Neighbourhood {
+    /// Bits of the neighbours in a 3x3 neighbourhood, bit `3 * y + x` being the cell at (x, y)
+    pub fn mask(&self) -> u16 {
+        match self {
+            Neighbourhood::Moore => NEIGHBOURS,
+            Neighbourhood::Hexagonal => NEIGHBOURS & !(1 << 2) & !(1 << 6),
+            Neighbourhood::VonNeumann => 1 << 1 | 1 << 3 | 1 << 5 | 1 << 7,
+        }
+    }
+
+    /// Number of neighbours of a cell
+    pub fn size(&self) -> usize {
+        self.mask().count_ones() as usize
+    }
+
+    fn suffix(&self) -> &'static str {
+        match self {
+            Neighbourhood::Moore => "",
+            Neighbourhood::Hexagonal => "H",
+            Neighbourhood::VonNeumann => "V",
+        }
+    }
+}
+
+/// Isotropic non-totalistic rule for two-state automata, written in B/S notation
+/// with optional Hensel letters (`B2-a/S12`), outer-totalistic rules being the ones without letters.
+/// A `H` or `V` suffix selects the hexagonal or von Neumann neighbourhood (`B2/S34H`).
+/// It can also be a Larger than Life rule, whose neighbourhood is larger than the 3x3 block,
+/// a Wolfram rule drawing the generations of a line of cells one row below the other,
+/// or a Margolus rule replacing the 2x2 blocks of the grid.
+/// Any rule of the 3x3 neighbourhood can be written as a `MAP` string, the base64 encoding of its 512 transitions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rule {
+    /// Configurations giving a birth, a bit per Hensel letter for each number of neighbours
+    birth: [u16; 9],
+    /// Configurations in which a live cell survives
+    survival: [u16; 9],
+    /// Next state of the center cell for each of the 512 neighbourhoods, as bits
+    table: [u64; 8],
+    neighbourhood: Neighbourhood,
+    larger_than_life: Option<LargerThanLife>,
+    wolfram: Option<Wolfram>,
+    margolus: Option<Margolus>,
+    /// Whether the rule was given as a MAP string, which is kept when it is written
+    map: bool,
+}
+
+impl Default for Rule {
+    /// Conway's Game of Life (B3/S23)
+    fn default() -> Self {
+        let mut birth = [0; 9];
+        let mut survival = [0; 9];
+        birth[3] = Self::all_letters(3);
+        survival[2] = Self::all_letters(2);
+        survival[3] = Self::all_letters(3);
+        Rule::from_conditions(birth, survival, Neighbourhood::Moore)
+    }
+}
+
+impl Rule {
+    /// Mask of all the configurations of a number of neighbours
+    fn all_letters(neighbours: usize) -> u16 {
+        (1 << LETTERS[neighbours].len().max(1)) - 1
+    }
+
+    /// Neighbourhood of the configuration of a letter, the configurations of 5 to 8 neighbours
+    /// being the complements of the ones of 3 to 0 neighbours
+    fn representative(neighbours: usize, letter: usize) -> u16 {
+        if neighbours <= 4 {
+            REPRESENTATIVES[neighbours][letter]
+        } else {
+            !REPRESENTATIVES[8 - neighbours][letter] & NEIGHBOURS
+        }
+    }
+
+    /// The eight rotations and reflections of a neighbourhood
+    fn symmetries(neighbourhood: u16) -> [u16; 8] {
+        let transform = |f: &dyn Fn(u16, u16) -> (u16, u16)| {
+            (0..9).filter(|bit| neighbourhood >> bit & 1 == 1)
+                .map(|bit| { let (x, y) = f(bit % 3, bit / 3); 1 << (3 * y + x) })
+                .fold(0, |acc, bit| acc | bit)
+        };
+        [
+            neighbourhood,
+            transform(&|x, y| (2 - y, x)),
+            transform(&|x, y| (2 - x, 2 - y)),
+            transform(&|x, y| (y, 2 - x)),
+            transform(&|x, y| (2 - x, y)),
+            transform(&|x, y| (x, 2 - y)),
+            transform(&|x, y| (y, x)),
+            transform(&|x, y| (2 - y, 2 - x)),
+        ]
+    }
+
+    fn from_conditions(birth: [u16; 9], survival: [u16; 9], neighbourhood: Neighbourhood) -> Rule {
+        let mut table = [0u64; 8];
+        let mut set = |index: usize| table[index / 64] |= 1 << (index % 64);
+        if neighbourhood == Neighbourhood::Moore {
+            for (conditions, center) in [(&birth, 0), (&survival, 1 << 4)] {
+                for (neighbours, &letters) in conditions.iter().enumerate() {
+                    for letter in (0..16).filter(|letter| letters >> letter & 1 == 1) {
+                        for n in Self::symmetries(Self::representative(neighbours, letter)) {
+                            set((n | center) as usize);
+                        }
+                    }
+                }
+            }
+        } else {
+            // The cells outside of the neighbourhood are ignored
+            for index in 0..512 {
+                let neighbours = (index as u16 & neighbourhood.mask()).count_ones() as usize;
+                let conditions = if index & 1 << 4 == 0 { &birth } else { &survival };
+                if conditions[neighbours] != 0 { set(index) }
+            }
+        }
+        Rule { birth, survival, table, neighbourhood, larger_than_life: None, wolfram: None, margolus: None, map: false }
+    }
+
+    /// Check that the conditions can be met in a hexagonal or von Neumann neighbourhood
+    fn check_conditions(conditions: &[u16; 9], neighbourhood: Neighbourhood) -> Result<(), &'static str> {
+        if neighbourhood == Neighbourhood::Moore {
+            return Ok(())
+        }
+        for (n, &letters) in conditions.iter().enumerate().filter(|(_, &letters)| letters != 0) {
+            if n > neighbourhood.size() {
+                return Err("[Rule parser] Neighbour counts can't be larger than the size of the neighbourhood")
+            }
+            if letters != Self::all_letters(n) {
+                return Err("[Rule parser] Hensel letters are only supported in the Moore neighbourhood")
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_digits(input: &str) -> Result<[u16; 9], &'static str> {
+        let mut counts = [0; 9];
+        let mut chars = input.chars().peekable();
+        while let Some(c) = chars.next() {
+            let d = match c.to_digit(10) {
+                Some(d) if d <= 8 => d as usize,
+                _ => return Err("[Rule parser] Neighbour counts must be digits between 0 and 8"),
+            };
+            // Hensel letters following the digit, all of them being excluded after a minus
+            let negated = chars.next_if_eq(&'-').is_some();
+            let mut letters = 0;
+            while let Some(letter) = chars.next_if(char::is_ascii_alphabetic) {
+                match LETTERS[d].find(letter) {
+                    Some(i) => letters |= 1 << i,
+                    None => return Err("[Rule parser] Unknown Hensel letter for this number of neighbours"),
+                }
+            }
+            if negated && letters == 0 {
+                return Err("[Rule parser] Expected Hensel letters after a minus")
+            }
+            counts[d] |= match (negated, letters) {
+                (true, _) => Self::all_letters(d) & !letters,
+                (false, 0) => Self::all_letters(d),
+                (false, _) => letters,
+            };
+        }
+        Ok(counts)
+    }
+
+    fn digits_to_string(counts: &[u16; 9]) -> String {
+        let mut result = String::new();
+        for (d, &letters) in counts.iter().enumerate().filter(|(_, &letters)| letters != 0) {
+            result += &d.to_string();
+            if letters == Self::all_letters(d) {
+                continue
+            }
+            // The shorter of the letters and of the excluded letters
+            let total = LETTERS[d].len();
+            let (negated, letters) = if 2 * letters.count_ones() as usize > total {
+                (true, !letters & Self::all_letters(d))
+            } else {
+                (false, letters)
+            };
+            if negated { result.push('-') }
+            result.extend(LETTERS[d].chars().enumerate().filter(|(i, _)| letters >> i & 1 == 1).map(|(_, c)| c));
+        }
+        result
+    }
+
+    /// Position of a neighbourhood in a MAP string, whose cells are read from the top left one,
+    /// the most significant bit, to the bottom right one
+    fn map_index(neighbourhood: usize) -> usize {
+        (0..9).filter(|bit| neighbourhood >> bit & 1 == 1).fold(0, |index, bit| index | 1 << (8 - bit))
+    }
+
+    /// Decode the base64 part of a MAP rule, the `==` padding being optional
+    fn parse_map(input: &str) -> Result<Rule, &'static str> {
+        let data = input.trim_end_matches('=');
+        if data.len() != 86 {
+            return Err("[Rule parser] A MAP rule needs 86 base64 characters")
+        }
+        let mut bits = Vec::with_capacity(data.len() * 6);
+        for c in data.bytes() {
+            let Some(value) = BASE64.iter().position(|&b| b == c) else {
+                return Err("[Rule parser] Invalid base64 character in the MAP rule")
+            };
+            bits.extend((0..6).rev().map(|i| value >> i & 1 == 1));
+        }
+
+        let mut table = [0u64; 8];
+        for neighbourhood in 0..512 {
+            if bits[Self::map_index(neighbourhood)] {
+                table[neighbourhood / 64] |= 1 << (neighbourhood % 64);
+            }
+        }
+        // The letters whose configurations all give a birth or a survival
+        let (mut birth, mut survival) = ([0; 9], [0; 9]);
+        for (conditions, center) in [(&mut birth, 0), (&mut survival, 1 << 4)] {
+            for (neighbours, letters) in conditions.iter_mut().enumerate() {
+                for letter in 0..LETTERS[neighbours].len().max(1) {
+                    let configurations = Self::symmetries(Self::representative(neighbours, letter));
+                    if configurations.iter().all(|&n| bits[Self::map_index((n | center) as usize)]) {
+                        *letters |= 1 << letter;
+                    }
+                }
+            }
+        }
+        Ok(Rule { table, map: true, ..Rule::from_conditions(birth, survival, Neighbourhood::Moore) })
+    }
+
+    fn with_neighbourhood(birth: [u16; 9], survival: [u16; 9], neighbourhood: Neighbourhood) -> Result<Rule, &'static str> {
+        Self::check_conditions(&birth, neighbourhood)?;
+        Self::check_conditions(&survival, neighbourhood)?;
+        Ok(Rule::from_conditions(birth, survival, neighbourhood))
+    }
+
+    // ---------
+
+    /// Parse a rule written as `B36/S23` or in the legacy `23/36` (survival/birth) form,
+    /// each digit being optionally followed by Hensel letters (`B2-a/S12`).
+    /// Larger than Life rules are recognised by their commas (`R5,C0,M1,S34..58,B34..45,NM`)
+    /// and Wolfram rules by their `W` (`W30`). MAP rules are written `MAP` followed by 86 base64 characters
+    pub fn parse(input: &str) -> Result<Rule, &'static str> {
+        // Base64 is case sensitive
+        if let Some(data) = input.trim().strip_prefix("MAP") {
+            return Self::parse_map(data)
+        }
+        let input = input.trim().to_ascii_lowercase();
+        if input.starts_with('m') || Margolus::parse(&input).is_ok() {
+            return Ok(Rule::from(Margolus::parse(&input)?))
+        }
+        if input.contains(',') {
+            return Ok(Rule::from(LargerThanLife::parse(&input)?))
+        }
+        if input.starts_with('w') {
+            return Ok(Rule::from(Wolfram::parse(&input)?))
+        }
+        let (lower, neighbourhood) = if let Some(rule) = input.strip_suffix('h') {
+            (rule, Neighbourhood::Hexagonal)
+        } else if let Some(rule) = input.strip_suffix('v') {
+            (rule, Neighbourhood::VonNeumann)
+        } else {
+            (input.as_str(), Neighbourhood::Moore)
+        };
+
+        if lower.starts_with('b') || lower.starts_with('s') {
+            let (mut birth, mut survival) = (None, None);
+            let parts: Vec<&str> = if lower.contains('/') {
+                lower.split('/').collect()
+            } else if let Some(index) = lower[1..].find(['b', 's']) {
+                // Slash-less form such as `B3S23`
+                vec![&lower[..index + 1], &lower[index + 1..]]
+            } else {
+                vec![lower]
+            };
+            if parts.len() > 2 {
+                return Err("[Rule parser] Too many parts in the rule string")
+            }
+            for part in parts {
+                if let Some(digits) = part.strip_prefix('b') {
+                    if birth.is_some() { return Err("[Rule parser] Birth conditions given twice") }
+                    birth = Some(Self::parse_digits(digits)?);
+                } else if let Some(digits) = part.strip_prefix('s') {
+                    if survival.is_some() { return Err("[Rule parser] Survival conditions given twice") }
+                    survival = Some(Self::parse_digits(digits)?);
+                } else {
+                    return Err("[Rule parser] Each part must start with B or S")
+                }
+            }
+            Self::with_neighbourhood(birth.unwrap_or([0; 9]), survival.unwrap_or([0; 9]), neighbourhood)
+        } else if let Some((survival, birth)) = lower.split_once('/') {
+            Self::with_neighbourhood(Self::parse_digits(birth)?, Self::parse_digits(survival)?, neighbourhood)
+        } else {
+            Err("[Rule parser] Unknown rule format, expected B3/S23 or 23/3")
+        }
+    }
+
+    /// Rule in the legacy survival/birth notation, like `23/3`. Larger than Life, Wolfram, Margolus and MAP rules have no such notation
+    pub fn to_legacy_string(&self) -> String {
+        if let Some(rule) = self.larger_than_life {
+            return rule.to_string()
+        }
+        if let Some(rule) = self.margolus {
+            return rule.to_string()
+        }
+        if let Some(rule) = self.wolfram {
+            return rule.to_string()
+        }
+        if self.map {
+            return self.to_string()
+        }
+        format!("{}/{}{}", Self::digits_to_string(&self.survival), Self::digits_to_string(&self.birth), self.neighbourhood.suffix())
+    }
+
+    /// Cells counted as neighbours
+    pub fn neighbourhood(&self) -> Neighbourhood {
+        self.neighbourhood
+    }
+
+    /// Larger than Life rule, None when the neighbours are in the 3x3 block
+    pub fn larger_than_life(&self) -> Option<&LargerThanLife> {
+        self.larger_than_life.as_ref()
+    }
+
+    /// Wolfram rule, None for the two-dimensional rules
+    pub fn wolfram(&self) -> Option<&Wolfram> {
+        self.wolfram.as_ref()
+    }
+
+    /// Block rule of the Margolus neighbourhood
+    pub fn margolus(&self) -> Option<&Margolus> {
+        self.margolus.as_ref()
+    }
+
+    /// Whether the rule is the same in every rotation and reflection of the neighbourhoods,
+    /// so that it can be written in Hensel notation
+    pub fn is_isotropic(&self) -> bool {
+        self.larger_than_life.is_none() && self.margolus.is_none() && Rule::from_conditions(self.birth, self.survival, self.neighbourhood).table == self.table
+    }
+
+    /// Canonical MAP string of the rule: `MAP` followed by the 86 base64 characters of its 512 transitions.
+    /// None for the Larger than Life and Margolus rules, whose neighbourhoods are not the 3x3 block
+    pub fn to_map_string(&self) -> Option<String> {
+        if self.larger_than_life.is_some() || self.margolus.is_some() {
+            return None
+        }
+        let bits: Vec<bool> = (0..512).map(|index| self.next_state_of(Self::map_index(index))).collect();
+        let data: String = bits.chunks(6)
+            .map(|chunk| {
+                let value = chunk.iter().enumerate().fold(0, |acc, (i, &bit)| acc | (bit as usize) << (5 - i));
+                BASE64[value] as char
+            })
+            .collect();
+        Some(format!("MAP{}", data))
+    }
+
+    /// Whether the rule only depends on the number of live neighbours
+    pub fn is_totalistic(&self) -> bool {
+        self.is_isotropic() && (0..=8).all(|n| [self.birth[n], self.survival[n]].iter().all(|&l| l == 0 || l == Self::all_letters(n)))
+    }
+
+    /// Whether a dead cell with `neighbours` live neighbours becomes alive, whatever their configuration
+    pub fn birth(&self, neighbours: usize) -> bool {
+        self.birth[neighbours] == Self::all_letters(neighbours)
+    }
+
+    /// Whether a live cell with `neighbours` live neighbours stays alive, whatever their configuration
+    pub fn survival(&self, neighbours: usize) -> bool {
+        self.survival[neighbours] == Self::all_letters(neighbours)
+    }
+
+    /// Compute the next state of a cell of an outer-totalistic rule from its state and its number of live neighbours
+    pub fn next_state(&self, alive: bool, neighbours: usize) -> bool {
+        if alive { self.survival(neighbours) } else { self.birth(neighbours) }
+    }
+
+    /// Compute the next state of the center cell of a 3x3 neighbourhood,
+    /// bit `3 * y + x` being the state of the cell at (x, y)
+    pub fn next_state_of(&self, neighbourhood: usize) -> bool {
+        self.table[neighbourhood / 64] >> (neighbourhood % 64) & 1 == 1
+    }
+
+    /// Whether a dead cell without live neighbours is born (B0), turning the empty background alive
+    pub fn has_b0(&self) -> bool {
+        self.larger_than_life.is_none() && self.next_state_of(0)
+    }
+
+    /// State of the infinite background after a generation: a B0 rule turns a dead background alive,
+    /// which only stays alive with S8
+    pub fn next_background(&self, background: bool) -> bool {
+        self.next_state_of(if background { NEIGHBOURHOOD } else { 0 })
+    }
+
+    /// State of the background at a generation, the background being dead at generation 0 and before.
+    /// It strobes with the B0 rules without S8 and stays alive after the first generation with S8
+    pub fn background_at(&self, generation: i64) -> bool {
+        match (self.next_background(false), self.next_background(true)) {
+            (false, _) => false,
+            (true, true) => generation > 0,
+            (true, false) => generation.rem_euclid(2) == 1,
+        }
+    }
+
+    /// Equivalent rule for the cells stored relative to the background, a stored cell being alive when
+    /// it is in the opposite state of the background, so that the stored background always stays dead.
+    /// Without S8, a B0 rule alternates between its complemented outputs and its complemented inputs
+    pub fn relative_to(&self, background: bool) -> Rule {
+        let input = if background { NEIGHBOURHOOD } else { 0 };
+        let output = self.next_background(background);
+        let mut table = [0u64; 8];
+        for index in 0..512 {
+            if self.next_state_of(index ^ input) != output {
+                table[index / 64] |= 1 << (index % 64);
+            }
+        }
+        Rule { table, ..*self }
+    }
+}
+
+impl From<LargerThanLife> for Rule {
+    fn from(rule: LargerThanLife) -> Self {
+        Rule {
+            neighbourhood: rule.neighbourhood(),
+            larger_than_life: Some(rule),
+            ..Rule::from_conditions([0; 9], [0; 9], Neighbourhood::Moore)
+        }
+    }
+}
+
+impl From<Wolfram> for Rule {
+    /// Two-dimensional rule building the spacetime diagram: live cells stay alive
+    /// and a dead cell takes the next state of the cell above it
+    fn from(rule: Wolfram) -> Self {
+        let mut table = [0u64; 8];
+        for index in 0..512 {
+            let above = |bit: usize| index >> bit & 1 == 1;
+            if above(4) || rule.next_state(above(0), above(1), above(2)) {
+                table[index / 64] |= 1 << (index % 64);
+            }
+        }
+        Rule {
+            table,
+            wolfram: Some(rule),
+            ..Rule::from_conditions([0; 9], [0; 9], Neighbourhood::Moore)
+        }
+    }
+}
+
+impl From<Margolus> for Rule {
+    /// The transitions of the 3x3 neighbourhoods only tell how the empty and full backgrounds evolve,
+    /// the blocks being replaced by the cell map
+    fn from(rule: Margolus) -> Self {
+        let mut table = [0u64; 8];
+        table[0] = (rule.next_block(0) == Margolus::FULL) as u64;
+        table[7] = ((rule.next_block(Margolus::FULL) == Margolus::FULL) as u64) << 63;
+        Rule {
+            table,
+            margolus: Some(rule),
+            ..Rule::from_conditions([0; 9], [0; 9], Neighbourhood::Moore)
+        }
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(map) = self.to_map_string().filter(|_| self.map) {
+            return write!(f, "{}", map)
+        }
+        if let Some(rule) = self.larger_than_life {
+            return write!(f, "{}", rule)
+        }
+        if let Some(rule) = self.wolfram {
+            return write!(f, "{}", rule)
+        }
+        if let Some(rule) = self.margolus {
+            return write!(f, "{}", rule)
+        }
+        write!(f, "B{}/S{}{}", Self::digits_to_string(&self.birth), Self::digits_to_string(&self.survival), self.neighbourhood.suffix())
+    }
+}
+
+impl FromStr for Rule {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Rule::parse(s)
+    }
+}
+
+
+// --------
+// Tests
+// --------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Test Rule::parse
+
+    #[test]
+    fn test_parse_1() {
+        let rule = Rule::parse("B3/S23").unwrap();
+        assert_eq!(rule, Rule::default());
+        assert_eq!(rule.to_string(), "B3/S23");
+    }
+
+    #[test]
+    fn test_parse_2() {
+        // HighLife in the legacy survival/birth form
+        let rule = Rule::parse("23/36").unwrap();
+        assert_eq!(rule.to_string(), "B36/S23");
+        assert!(rule.next_state(false, 6));
+        assert!(!rule.next_state(true, 6));
+    }
+
+    #[test]
+    fn test_parse_3() {
+        // Day & Night, lowercase and reversed order
+        let rule = Rule::parse("s34678/b3678").unwrap();
+        assert_eq!(rule.to_string(), "B3678/S34678");
+    }
+
+    #[test]
+    fn test_parse_4() {
+        // Seeds has no survival condition
+        assert_eq!(Rule::parse("B2/S").unwrap().to_string(), "B2/S");
+        assert_eq!(Rule::parse("2/").unwrap().to_string(), "B/S2");
+        assert_eq!(Rule::parse("B3S23").unwrap(), Rule::default());
+    }
+
+    #[test]
+    fn test_parse_5() {
+        assert!(Rule::parse("B9/S23").is_err());
+        assert!(Rule::parse("B3/X23").is_err());
+        assert!(Rule::parse("B3/B4").is_err());
+        assert!(Rule::parse("Life").is_err());
+    }
+
+    #[test]
+    fn test_parse_6() {
+        // Isotropic non-totalistic rules
+        let rule = Rule::parse("B2-a/S12").unwrap();
+        assert_eq!(rule.to_string(), "B2-a/S12");
+        assert!(!rule.is_totalistic());
+        assert_eq!(Rule::parse("b2cekin/s12").unwrap(), rule);
+        assert_eq!(Rule::parse("B3cekainyqjr/S2cekain3").unwrap(), Rule::default());
+        assert!(Rule::default().is_totalistic());
+        assert_eq!(Rule::parse("B3/S2-i34q").unwrap().to_string(), "B3/S2-i34q");
+        assert_eq!(Rule::parse("B2ec3ceaikny/S5y").unwrap().to_string(), "B2ce3-qjr/S5y");
+        assert_eq!(Rule::parse("23-a/3i").unwrap().to_legacy_string(), "23-a/3i");
+    }
+
+    #[test]
+    fn test_parse_7() {
+        assert!(Rule::parse("B2-/S23").is_err());
+        assert!(Rule::parse("B1k/S23").is_err());
+        assert!(Rule::parse("B3/S4x").is_err());
+    }
+
+    #[test]
+    fn test_parse_8() {
+        // Hexagonal and von Neumann neighbourhoods
+        let rule = Rule::parse("B2/S34H").unwrap();
+        assert_eq!(rule.neighbourhood(), Neighbourhood::Hexagonal);
+        assert_eq!(rule.to_string(), "B2/S34H");
+        assert_eq!(rule.to_legacy_string(), "34/2H");
+        assert_eq!(Rule::parse("34/2h").unwrap(), rule);
+        assert_eq!(Rule::parse("b13/s012v").unwrap().to_string(), "B13/S012V");
+        assert_eq!(Rule::parse("B3/S23").unwrap().neighbourhood(), Neighbourhood::Moore);
+
+        assert!(Rule::parse("B7/S23H").is_err());
+        assert!(Rule::parse("B2/S5V").is_err());
+        assert!(Rule::parse("B2a/S34H").is_err());
+    }
+
+    #[test]
+    fn test_parse_9() {
+        // Larger than Life
+        let rule = Rule::parse("R5,C0,M1,S34..58,B34..45,NM").unwrap();
+        assert_eq!(rule.larger_than_life().map(|r| r.range()), Some(5));
+        assert_eq!(rule.to_string(), "R5,C0,M1,S34..58,B34..45,NM");
+        assert_eq!(rule.to_legacy_string(), "R5,C0,M1,S34..58,B34..45,NM");
+        assert_eq!(Rule::parse("R2,S1,B1,NH").unwrap().neighbourhood(), Neighbourhood::Hexagonal);
+        assert!(Rule::parse("B3/S23").unwrap().larger_than_life().is_none());
+        assert!(Rule::parse("R5,S34..58").is_err());
+    }
+
+    #[test]
+    fn test_parse_10() {
+        // Wolfram rules
+        let rule = Rule::parse("w30").unwrap();
+        assert_eq!(rule.wolfram().map(|r| r.number()), Some(30));
+        assert_eq!(rule.to_string(), "W30");
+        assert!(!rule.is_totalistic());
+        assert!(Rule::parse("W31").is_err());
+    }
+
+    #[test]
+    fn test_parse_11() {
+        // Conway's Game of Life as a MAP rule
+        let life = "MAPARYXfhZofugWaH7oaIDogBZofuhogOiAaIDogIAAgAAWaH7oaIDogGiA6ICAAIAAaIDogIAAgACAAIAAAAAAAA";
+        let rule = Rule::parse(life).unwrap();
+        assert_eq!(rule.to_string(), life);
+        assert!(rule.is_totalistic());
+        assert!((0..512).all(|i| rule.next_state_of(i) == Rule::default().next_state_of(i)));
+        assert_eq!(Rule::parse(&format!("{}==", life)).unwrap(), rule);
+
+        assert!(Rule::parse("MAPARYX").is_err());
+        assert!(Rule::parse(&life.replace('R', "!")).is_err());
+        assert!(Rule::parse(&life.to_lowercase()).is_err());
+    }
+
+    #[test]
+    fn test_parse_12() {
+        // Margolus rules
+        let rule = Rule::parse("critters").unwrap();
+        assert_eq!(rule.to_string(), "Critters");
+        assert!(rule.has_b0() && !rule.is_totalistic());
+        assert_eq!(rule.margolus().map(|r| r.next_block(1)), Some(14));
+        let rule = Rule::parse("M0,8,4,3,2,5,9,7,1,6,10,11,12,13,14,15").unwrap();
+        assert_eq!(rule.to_string(), "BBM");
+        assert!(!rule.has_b0());
+
+        assert!(Rule::parse("M0,8,4").is_err());
+        assert!(Rule::parse("Tron").unwrap().to_map_string().is_none());
+    }
+
+    // Test Rule.to_map_string
+
+    #[test]
+    fn test_to_map_string() {
+        assert_eq!(Rule::default().to_map_string().unwrap(), "MAPARYXfhZofugWaH7oaIDogBZofuhogOiAaIDogIAAgAAWaH7oaIDogGiA6ICAAIAAaIDogIAAgACAAIAAAAAAAA");
+        // Any rule of the 3x3 neighbourhood goes through its MAP string
+        for (text, isotropic) in [("B2-a/S12", true), ("B2/S34H", false), ("W110", false), ("B36/S23", true)] {
+            let rule = Rule::parse(text).unwrap();
+            let map = Rule::parse(&rule.to_map_string().unwrap()).unwrap();
+            assert!((0..512).all(|i| rule.next_state_of(i) == map.next_state_of(i)), "{}", text);
+            assert_eq!(map.is_isotropic(), isotropic, "{}", text);
+        }
+        assert!(Rule::parse("R2,S1,B1").unwrap().to_map_string().is_none());
+    }
+
+    // Test Rule::symmetries
+
+    #[test]
+    fn test_symmetries() {
+        // The configurations of the letters split the neighbourhoods of each number of neighbours
+        for (n, letters) in LETTERS.iter().enumerate() {
+            let mut covered = vec![false; 512];
+            for letter in 0..letters.len().max(1) {
+                let mut class: Vec<u16> = Rule::symmetries(Rule::representative(n, letter)).to_vec();
+                class.sort();
+                class.dedup();
+                for neighbourhood in class {
+                    assert!(!covered[neighbourhood as usize], "{}{} overlaps another letter", n, &letters[letter..letter + 1]);
+                    covered[neighbourhood as usize] = true;
+                }
+            }
+            let expected = (0..512u16).filter(|&i| i & 16 == 0 && i.count_ones() == n as u32).count();
+            assert_eq!(covered.iter().filter(|&&c| c).count(), expected);
+        }
+    }
+
+    // Test Rule.next_state_of
+
+    #[test]
+    fn test_next_state_of() {
+        // Outer-totalistic rules only depend on the number of neighbours
+        let rule = Rule::parse("B36/S23").unwrap();
+        for i in 0..512usize {
+            let neighbours = (i & !16).count_ones() as usize;
+            assert_eq!(rule.next_state_of(i), rule.next_state(i & 16 != 0, neighbours));
+        }
+
+        // Two corners on the same side give a birth, a corner and an adjacent edge don't
+        let rule = Rule::parse("B2-a/S12").unwrap();
+        assert!(rule.next_state_of(0b000_000_101));
+        assert!(rule.next_state_of(0b101_000_000));
+        assert!(!rule.next_state_of(0b000_000_011));
+        assert!(!rule.next_state_of(0b001_001_000));
+        assert!(rule.next_state_of(0b100_001_000));
+        assert!(rule.next_state_of(0b000_010_011));
+    }
+
+    // Test Rule.next_state
+
+    #[test]
+    fn test_next_state() {
+        let rule = Rule::parse("B36/S23").unwrap();
+        assert!(rule.next_state(false, 3));
+        assert!(rule.next_state(false, 6));
+        assert!(!rule.next_state(false, 2));
+        assert!(rule.next_state(true, 2));
+        assert!(!rule.next_state(true, 6));
+    }
+
+    #[test]
+    fn test_next_state_of_neighbourhoods() {
+        // The top right cell (bit 2) is not a neighbour in the hexagonal neighbourhood
+        let rule = Rule::parse("B1/SH").unwrap();
+        assert!(rule.next_state_of(0b000_000_001));
+        assert!(!rule.next_state_of(0b000_000_100));
+        assert!(rule.next_state_of(0b000_000_101));
+
+        // Only the orthogonal cells are neighbours in the von Neumann neighbourhood
+        let rule = Rule::parse("B2/SV").unwrap();
+        assert!(rule.next_state_of(0b000_101_000));
+        assert!(!rule.next_state_of(0b000_000_101));
+        assert!(rule.next_state_of(0b101_101_000));
+    }
+
+    // Test Rule.background_at
+
+    #[test]
+    fn test_background_at() {
+        let rule = Rule::parse("B0/S").unwrap();
+        assert!(rule.has_b0());
+        assert_eq!((0..4).map(|g| rule.background_at(g)).collect::<Vec<_>>(), vec![false, true, false, true]);
+
+        // With S8, the background stays alive
+        let rule = Rule::parse("B01/S8").unwrap();
+        assert_eq!((0..4).map(|g| rule.background_at(g)).collect::<Vec<_>>(), vec![false, true, true, true]);
+
+        assert!(!Rule::default().has_b0());
+        assert!(!Rule::default().background_at(1));
+    }
+
+    // Test Rule.relative_to
+
+    #[test]
+    fn test_relative_to() {
+        // Without S8, even generations complement the outputs and odd ones the inputs
+        let rule = Rule::parse("B0123478/S0123467").unwrap();
+        let (even, odd) = (rule.relative_to(false), rule.relative_to(true));
+        for i in 0..512usize {
+            assert_eq!(even.next_state_of(i), !rule.next_state_of(i));
+            assert_eq!(odd.next_state_of(i), rule.next_state_of(i ^ 0x1ff));
+        }
+        assert!(!even.next_state_of(0) && !odd.next_state_of(0));
+
+        // Rules without B0 are unchanged
+        assert_eq!(Rule::default().relative_to(false), Rule::default());
+    }
+
+    // Test Rule.to_legacy_string
+
+    #[test]
+    fn test_to_legacy_string() {
+        assert_eq!(Rule::parse("B36/S23").unwrap().to_legacy_string(), "23/36");
+        assert_eq!(Rule::parse("B2/S").unwrap().to_legacy_string(), "/2");
+        assert_eq!(Rule::parse(&Rule::default().to_legacy_string()).unwrap(), Rule::default());
+    }
+}
@@ -0,0 +1,548 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use crate::error::{Error, SourceError};
+use crate::rule::Neighbourhood;
+
+/// Set of states, bit `s` being set when the state `s` is in the set
+type States = [u64; 4];
+
+/// States allowed for a cell in a transition, a named variable taking the same value wherever it appears
+#[derive(Debug, Clone, PartialEq)]
+struct Input {
+    states: States,
+    variable: Option<usize>,
+}
+
+impl Input {
+    /// Whether the cell can have the state, binding the variable to it when it is not bound yet
+    fn bind(&self, state: u8, bound: &mut [Option<u8>]) -> bool {
+        if self.states[state as usize / 64] >> (state % 64) & 1 == 0 {
+            return false
+        }
+        match self.variable {
+            None => true,
+            Some(v) => *bound[v].get_or_insert(state) == state,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Output {
+    State(u8),
+    Variable(usize),
+}
+
+/// Line of the table: the states of the cell and of its neighbours, then the next state of the cell
+#[derive(Debug, Clone, PartialEq)]
+struct Transition {
+    inputs: Vec<Input>,
+    output: Output,
+}
+
+/// Ways the neighbours of a transition can be reordered
+#[derive(Debug, Clone, PartialEq)]
+enum Symmetries {
+    /// Each permutation gives the neighbour matched by each neighbour of the transition
+    Permutations(Vec<Vec<usize>>),
+    /// The neighbours can be in any order, only their states count
+    Permute,
+}
+
+impl Symmetries {
+    /// Symmetries named `none`, `rotate4`, `rotate8reflect`, `reflect_horizontal`, `permute`...,
+    /// the neighbours being listed clockwise from the north
+    fn parse(input: &str, size: usize) -> Option<Symmetries> {
+        let (rotations, reflect) = match input {
+            "permute" => return Some(Symmetries::Permute),
+            "none" => (1, false),
+            "reflect_horizontal" => (1, true),
+            _ => {
+                let rotations = input.strip_prefix("rotate")?;
+                let (rotations, reflect) = rotations.strip_suffix("reflect").map_or((rotations, false), |r| (r, true));
+                (rotations.parse::<usize>().ok()?, reflect)
+            }
+        };
+        if rotations == 0 || !size.is_multiple_of(rotations) {
+            return None
+        }
+        let step = size / rotations;
+        let mut permutations = Vec::new();
+        for r in 0..rotations {
+            permutations.push((0..size).map(|i| (i + r * step) % size).collect());
+            if reflect {
+                permutations.push((0..size).map(|i| (size - i + r * step) % size).collect());
+            }
+        }
+        permutations.dedup();
+        Some(Symmetries::Permutations(permutations))
+    }
+}
+
+/// Multi-state rule given by the `@TABLE` of a Golly `.rule` file: each transition gives the next state
+/// of a cell from its state and the ones of its neighbours, the first matching transition being applied
+/// and a cell matching none of them keeping its state. Colours of the states come from the `@COLORS` section
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuleTable {
+    name: String,
+    states: u16,
+    neighbourhood: Neighbourhood,
+    symmetries: Symmetries,
+    /// Number of named variables, each transition binding them again
+    variables: usize,
+    transitions: Vec<Transition>,
+    colors: Vec<Option<[u8; 3]>>,
+}
+
+impl RuleTable {
+    const DECODER: &'static str = "Rule table decoder";
+    /// Extension of the rule files
+    pub const EXTENSION: &'static str = "rule";
+
+    /// Error on a token, which is a part of the text of the line
+    fn error(message: &'static str, token: &str, number: usize, text: &str) -> Error {
+        let column = (token.as_ptr() as usize).saturating_sub(text.as_ptr() as usize).min(text.len());
+        Error::Body(SourceError::new(Self::DECODER, message, token).at(number, column, text))
+    }
+
+    /// Split a transition at its commas, outside of the lists in braces.
+    /// Without commas, each character is a state or a variable
+    fn tokens(text: &str) -> Vec<&str> {
+        if !text.contains(',') {
+            return text.char_indices()
+                .filter(|(_, c)| !c.is_whitespace())
+                .map(|(i, c)| &text[i..i + c.len_utf8()])
+                .collect()
+        }
+        let (mut tokens, mut start, mut depth) = (Vec::new(), 0, 0);
+        for (i, c) in text.char_indices().chain([(text.len(), ',')]) {
+            match c {
+                '{' => depth += 1,
+                '}' => depth -= 1,
+                ',' if depth == 0 => {
+                    tokens.push(text[start..i].trim());
+                    start = i + 1;
+                }
+                _ => {}
+            }
+        }
+        tokens
+    }
+
+    /// States of a state, of a variable or of a list `{0,1,a}`, with the variable when it is a named one
+    fn parse_states(token: &str, states: u16, variables: &[(String, States)]) -> Result<(States, Option<usize>), &'static str> {
+        if let Some(list) = token.strip_prefix('{').and_then(|t| t.strip_suffix('}')) {
+            let mut set = [0; 4];
+            for item in list.split(',').map(str::trim) {
+                let (item_set, _) = Self::parse_states(item, states, variables)?;
+                (0..4).for_each(|i| set[i] |= item_set[i]);
+            }
+            return Ok((set, None))
+        }
+        if let Ok(state) = token.parse::<u16>() {
+            if state >= states {
+                return Err("The state is larger than the number of states")
+            }
+            let mut set = [0; 4];
+            set[state as usize / 64] |= 1 << (state % 64);
+            return Ok((set, None))
+        }
+        // A variable defined again replaces the previous one in the next transitions
+        match variables.iter().rposition(|(name, _)| name == token) {
+            Some(v) => Ok((variables[v].1, Some(v))),
+            None => Err("Unknown variable"),
+        }
+    }
+
+    fn parse_transition(
+        number: usize, text: &str, line: &str, states: u16, size: usize, variables: &[(String, States)]
+    ) -> Result<Transition, Error> {
+        let tokens = Self::tokens(line);
+        if tokens.len() != size + 2 {
+            return Err(Self::error("A transition needs the cell, its neighbours and the next state", line, number, text))
+        }
+        let mut inputs = Vec::with_capacity(size + 1);
+        for &token in tokens[..size + 1].iter() {
+            let (states, variable) = Self::parse_states(token, states, variables)
+                .map_err(|message| Self::error(message, token, number, text))?;
+            inputs.push(Input { states, variable });
+        }
+
+        let token = tokens[size + 1];
+        let output = match Self::parse_states(token, states, variables) {
+            Ok((_, Some(v))) if inputs.iter().any(|input| input.variable == Some(v)) => Output::Variable(v),
+            Ok((_, Some(_))) => return Err(Self::error("The variable of the next state must be one of the inputs", token, number, text)),
+            Ok((set, None)) if set.iter().map(|s| s.count_ones()).sum::<u32>() == 1 => {
+                let i = set.iter().position(|&s| s != 0).unwrap_or_default();
+                Output::State((i * 64) as u8 + set[i].trailing_zeros() as u8)
+            }
+            Ok(_) => return Err(Self::error("The next state must be a single state", token, number, text)),
+            Err(message) => return Err(Self::error(message, token, number, text)),
+        };
+        Ok(Transition { inputs, output })
+    }
+
+    /// Parse a `@COLORS` line, `state r g b` or `first last r g b r g b` for a gradient
+    fn parse_colors(number: usize, text: &str, line: &str, colors: &mut [Option<[u8; 3]>]) -> Result<(), Error> {
+        let values = line.split_whitespace()
+            .map(|token| token.parse::<u8>().map_err(|_| Self::error("Colours are numbers between 0 and 255", token, number, text)))
+            .collect::<Result<Vec<u8>, Error>>()?;
+        match values[..] {
+            [state, r, g, b] => colors[state as usize] = Some([r, g, b]),
+            [first, last, r1, g1, b1, r2, g2, b2] if first <= last => {
+                for state in first..=last {
+                    let t = if first == last { 0.0 } else { (state - first) as f64 / (last - first) as f64 };
+                    let mix = |a: u8, b: u8| (a as f64 + (b as f64 - a as f64) * t).round() as u8;
+                    colors[state as usize] = Some([mix(r1, r2), mix(g1, g2), mix(b1, b2)]);
+                }
+            }
+            _ => return Err(Self::error("Expected `state r g b` or `first last r g b r g b`", line, number, text)),
+        }
+        Ok(())
+    }
+
+    /// Whether the transition matches the cell, and the next state of the cell if it does
+    fn apply(&self, transition: &Transition, cells: &[u8], bound: &mut [Option<u8>]) -> Option<u8> {
+        let (centre, neighbours) = transition.inputs.split_first()?;
+        let matched = match &self.symmetries {
+            Symmetries::Permutations(permutations) => permutations.iter().any(|permutation| {
+                bound.fill(None);
+                centre.bind(cells[0], bound)
+                    && neighbours.iter().zip(permutation).all(|(input, &j)| input.bind(cells[1 + j], bound))
+            }),
+            Symmetries::Permute => {
+                bound.fill(None);
+                centre.bind(cells[0], bound) && Self::assign(neighbours, &cells[1..], 0, bound)
+            }
+        };
+        if !matched {
+            return None
+        }
+        match transition.output {
+            Output::State(state) => Some(state),
+            Output::Variable(v) => bound[v],
+        }
+    }
+
+    /// Give a different neighbour to each input, trying the neighbours in turn
+    fn assign(inputs: &[Input], cells: &[u8], used: u32, bound: &mut [Option<u8>]) -> bool {
+        let Some((input, rest)) = inputs.split_first() else {
+            return true
+        };
+        let mut tried = [0u64; 4];
+        for (j, &state) in cells.iter().enumerate() {
+            // Two neighbours in the same state lead to the same result
+            if used >> j & 1 == 1 || tried[state as usize / 64] >> (state % 64) & 1 == 1 {
+                continue
+            }
+            tried[state as usize / 64] |= 1 << (state % 64);
+            let unbound = input.variable.is_some_and(|v| bound[v].is_none());
+            if input.bind(state, bound) {
+                if Self::assign(rest, cells, used | 1 << j, bound) {
+                    return true
+                }
+                if unbound {
+                    bound[input.variable.unwrap_or_default()] = None;
+                }
+            }
+        }
+        false
+    }
+
+    // ---------
+
+    /// Parse the content of a `.rule` file, its `@RULE` name, `@TABLE` and optional `@COLORS` sections
+    pub fn parse(file_content: String) -> Result<RuleTable, Error> {
+        let (mut name, mut section, mut has_table) = (None, "", false);
+        let (mut states, mut neighbourhood, mut symmetries) = (None, None, None);
+        let mut variables: Vec<(String, States)> = Vec::new();
+        let mut transitions = Vec::new();
+        let mut colors = vec![None; 256];
+
+        for (number, text) in file_content.lines().enumerate().map(|(i, text)| (i + 1, text)) {
+            let line = text.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue
+            }
+            if let Some(header) = line.strip_prefix('@') {
+                let (key, value) = header.split_once(char::is_whitespace).unwrap_or((header, ""));
+                section = key;
+                match key {
+                    "RULE" if !value.trim().is_empty() => name = Some(value.trim().to_string()),
+                    "RULE" => return Err(Error::Header(SourceError::new(Self::DECODER, "The rule needs a name", line).at(number, 0, text))),
+                    "TABLE" => has_table = true,
+                    _ => {}
+                }
+                continue
+            }
+
+            match section {
+                "TABLE" => {
+                    if let Some((key, value)) = line.split_once(':') {
+                        let value = value.trim();
+                        match key.trim() {
+                            "n_states" => match value.parse::<u16>() {
+                                Ok(n) if (2..=256).contains(&n) => states = Some(n),
+                                _ => return Err(Self::error("The number of states must be between 2 and 256", value, number, text)),
+                            },
+                            "neighborhood" => neighbourhood = Some(match value.to_ascii_lowercase().as_str() {
+                                "moore" => Neighbourhood::Moore,
+                                "vonneumann" => Neighbourhood::VonNeumann,
+                                "hexagonal" => Neighbourhood::Hexagonal,
+                                _ => return Err(Self::error("Only the Moore, vonNeumann and hexagonal neighbourhoods are supported", value, number, text)),
+                            }),
+                            "symmetries" => symmetries = Some((value, number, text)),
+                            key => return Err(Self::error("Unknown setting", key, number, text)),
+                        }
+                        continue
+                    }
+                    let Some(states) = states else {
+                        return Err(Self::error("The number of states must be given first with `n_states:`", line, number, text))
+                    };
+                    if let Some(definition) = line.strip_prefix("var ") {
+                        let Some((variable, list)) = definition.split_once('=') else {
+                            return Err(Self::error("Expected `var name={...}`", line, number, text))
+                        };
+                        let list = list.trim();
+                        let (set, _) = Self::parse_states(list, states, &variables)
+                            .map_err(|message| Self::error(message, list, number, text))?;
+                        variables.push((variable.trim().to_string(), set));
+                        continue
+                    }
+                    let size = neighbourhood.unwrap_or_default().size();
+                    transitions.push(Self::parse_transition(number, text, line, states, size, &variables)?);
+                }
+                "COLORS" => Self::parse_colors(number, text, line, &mut colors)?,
+                // Other sections (@NAMES, @ICONS...) are not used
+                _ => {}
+            }
+        }
+
+        let Some(name) = name else {
+            return Err(Error::Header(SourceError::new(Self::DECODER, "The file must start with `@RULE name`", "")))
+        };
+        if !has_table {
+            return Err(Error::Header(SourceError::new(Self::DECODER, "Only the rules given by a `@TABLE` section are supported", "")))
+        }
+        let Some(states) = states else {
+            return Err(Error::Body(SourceError::new(Self::DECODER, "The number of states is missing", "")))
+        };
+        let neighbourhood = neighbourhood.unwrap_or_default();
+        let (value, number, text) = symmetries.unwrap_or(("none", 0, ""));
+        let Some(symmetries) = Symmetries::parse(value, neighbourhood.size()) else {
+            return Err(Self::error("Unknown symmetries for this neighbourhood", value, number, text))
+        };
+        colors.truncate(states as usize);
+
+        Ok(RuleTable { name, states, neighbourhood, symmetries, variables: variables.len(), transitions, colors })
+    }
+
+    /// Read a `.rule` file
+    pub fn load(path: &Path) -> Result<RuleTable, Error> {
+        let content = fs::read_to_string(path).map_err(|source| Error::Io { path: path.to_path_buf(), source })?;
+        Self::parse(content).map_err(|e| e.with_path(path))
+    }
+
+    /// Find the rule `name` in a directory of `.rule` files, the case of the file name being ignored like in Golly.
+    /// None when there is no such file
+    pub fn find(name: &str, directory: &Path) -> Result<Option<RuleTable>, Error> {
+        let Ok(entries) = fs::read_dir(directory) else {
+            return Ok(None)
+        };
+        let file = format!("{}.{}", name, Self::EXTENSION);
+        let path = entries.flatten()
+            .map(|entry| entry.path())
+            .find(|path| path.file_name().is_some_and(|f| f.to_string_lossy().eq_ignore_ascii_case(&file)));
+        path.map(|path| Self::load(&path)).transpose()
+    }
+
+    /// Name of the rule, as written in the RLE header of its patterns
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Number of states, the dead state 0 included
+    pub fn states(&self) -> u16 {
+        self.states
+    }
+
+    pub fn neighbourhood(&self) -> Neighbourhood {
+        self.neighbourhood
+    }
+
+    /// Positions of the neighbours relative to the cell, in the order of the transitions:
+    /// clockwise from the north, the hexagonal neighbourhood having no north-east and south-west cells
+    pub fn neighbours(&self) -> &'static [(isize, isize)] {
+        match self.neighbourhood {
+            Neighbourhood::Moore => &[(0, -1), (1, -1), (1, 0), (1, 1), (0, 1), (-1, 1), (-1, 0), (-1, -1)],
+            Neighbourhood::Hexagonal => &[(0, -1), (1, 0), (1, 1), (0, 1), (-1, 0), (-1, -1)],
+            Neighbourhood::VonNeumann => &[(0, -1), (1, 0), (0, 1), (-1, 0)],
+        }
+    }
+
+    /// Colour of a state given by the `@COLORS` section
+    pub fn color(&self, state: u8) -> Option<[u8; 3]> {
+        self.colors.get(state as usize).copied().flatten()
+    }
+
+    /// Compute the next state of a cell from its state followed by the states of its neighbours,
+    /// in the order of [`RuleTable::neighbours`]
+    pub fn next_state(&self, cells: &[u8]) -> u8 {
+        let mut bound = vec![None; self.variables];
+        self.transitions.iter()
+            .find_map(|transition| self.apply(transition, cells, &mut bound))
+            .unwrap_or(cells[0])
+    }
+}
+
+impl fmt::Display for RuleTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+
+// --------
+// Tests
+// --------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIREWORLD: &str = "\
+@RULE WireWorld
+
+@TABLE
+n_states:4
+neighborhood:Moore
+symmetries:permute
+var a={0,1,2,3}
+var b=a
+var c=a
+var d=a
+var e=a
+var f=a
+var g=a
+var h=a
+var i={0,2,3}
+var j={0,2,3}
+var k={0,2,3}
+var l={0,2,3}
+var m={0,2,3}
+var n={0,2,3}
+var o={0,2,3}
+# Electron head to tail, tail to wire
+1,a,b,c,d,e,f,g,h,2
+2,a,b,c,d,e,f,g,h,3
+# Wire to head with one or two heads around
+3,1,i,j,k,l,m,n,o,1
+3,1,1,i,j,k,l,m,n,1
+
+@COLORS
+0 48 48 48
+1 0 128 255
+2 255 255 255
+3 255 128 0
+";
+
+    // Test RuleTable::parse
+
+    #[test]
+    fn test_parse_1() {
+        let content = WIREWORLD.replace("var b=a\nvar c=a\nvar d=a\nvar e=a\nvar f=a\nvar g=a\nvar h=a",
+                                        "var b={a}\nvar c={a}\nvar d={a}\nvar e={a}\nvar f={a}\nvar g={a}\nvar h={a}");
+        let rule = RuleTable::parse(content).unwrap();
+
+        assert_eq!(rule.name(), "WireWorld");
+        assert_eq!(rule.to_string(), "WireWorld");
+        assert_eq!(rule.states(), 4);
+        assert_eq!(rule.neighbourhood(), Neighbourhood::Moore);
+        assert_eq!(rule.transitions.len(), 4);
+        assert_eq!(rule.color(1), Some([0, 128, 255]));
+        assert_eq!(rule.color(4), None);
+    }
+
+    #[test]
+    fn test_parse_2() {
+        // Compact transitions, von Neumann neighbourhood and a colour gradient
+        let rule = RuleTable::parse(String::from(
+            "@RULE Spread\n@TABLE\nn_states:3\nneighborhood:vonNeumann\nsymmetries:rotate4\n010002\n0,{1,2},0,0,0,1\n@COLORS\n0 2 0 0 0 200 100 0\n"
+        )).unwrap();
+
+        assert_eq!(rule.neighbours().len(), 4);
+        assert_eq!(rule.transitions.len(), 2);
+        assert_eq!(rule.color(1), Some([100, 50, 0]));
+        // The first matching transition is used
+        assert_eq!(rule.next_state(&[0, 0, 0, 1, 0]), 2);
+        assert_eq!(rule.next_state(&[0, 0, 2, 0, 0]), 1);
+        assert_eq!(rule.next_state(&[0, 1, 1, 0, 0]), 0);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let parse = |s: &str| RuleTable::parse(String::from(s));
+        assert!(matches!(parse("@TABLE\nn_states:2\n"), Err(Error::Header(_))));
+        assert!(matches!(parse("@RULE Tree\n@TREE\nnum_states=2\n"), Err(Error::Header(_))));
+
+        match parse("@RULE R\n@TABLE\nn_states:2\nneighborhood:vonNeumann\n0,1,0,x,0,1\n") {
+            Err(Error::Body(e)) => {
+                assert_eq!(e.token, "x");
+                assert_eq!(e.location.map(|l| (l.line, l.column)), Some((5, 7)));
+            }
+            _ => panic!("A body error was expected"),
+        }
+        assert!(parse("@RULE R\n@TABLE\nn_states:2\nneighborhood:vonNeumann\n0,1,0,2,0,1\n").is_err());
+        assert!(parse("@RULE R\n@TABLE\nn_states:2\nneighborhood:vonNeumann\n0,1,0,0,1\n").is_err());
+        assert!(parse("@RULE R\n@TABLE\nn_states:2\nneighborhood:Moore\nsymmetries:rotate6\n").is_err());
+        assert!(parse("@RULE R\n@TABLE\nn_states:2\nneighborhood:vonNeumann\nvar a={0,1}\n0,0,0,0,0,a\n").is_err());
+        assert!(parse("@RULE R\n@TABLE\nneighborhood:vonNeumann\n0,0,0,0,0,1\n").is_err());
+        assert!(parse("@RULE R\n@TABLE\nn_states:300\n").is_err());
+    }
+
+    // Test RuleTable.next_state
+
+    #[test]
+    fn test_next_state_wireworld() {
+        let rule = RuleTable::parse(String::from(WIREWORLD)).unwrap();
+
+        assert_eq!(rule.next_state(&[1, 3, 3, 0, 0, 0, 0, 0, 0]), 2);
+        assert_eq!(rule.next_state(&[2, 0, 0, 0, 0, 0, 0, 0, 0]), 3);
+        assert_eq!(rule.next_state(&[3, 0, 0, 1, 0, 2, 0, 3, 0]), 1);
+        assert_eq!(rule.next_state(&[3, 1, 0, 0, 0, 0, 0, 0, 1]), 1);
+        assert_eq!(rule.next_state(&[3, 1, 1, 1, 0, 0, 0, 0, 0]), 3);
+        assert_eq!(rule.next_state(&[0, 1, 1, 0, 0, 0, 0, 0, 0]), 0);
+    }
+
+    #[test]
+    fn test_next_state_symmetries() {
+        // A cell with a live neighbour to the north becomes 2, the variable giving the state of the west neighbour
+        let table = |symmetries: &str| RuleTable::parse(format!(
+            "@RULE R\n@TABLE\nn_states:3\nneighborhood:vonNeumann\nsymmetries:{}\nvar a={{0,1,2}}\n0,1,0,0,a,a\n", symmetries
+        )).unwrap();
+
+        let rule = table("none");
+        assert_eq!(rule.next_state(&[0, 1, 0, 0, 2]), 2);
+        assert_eq!(rule.next_state(&[0, 0, 1, 0, 0]), 0);
+
+        let rule = table("rotate4");
+        assert_eq!(rule.next_state(&[0, 0, 0, 2, 1]), 2);
+        assert_eq!(rule.next_state(&[0, 2, 0, 0, 1]), 0);
+
+        let rule = table("reflect_horizontal");
+        assert_eq!(rule.next_state(&[0, 1, 2, 0, 0]), 2);
+        assert_eq!(rule.next_state(&[0, 2, 1, 0, 0]), 0);
+    }
+
+    // Test RuleTable::find
+
+    #[test]
+    fn test_find() {
+        let rule = RuleTable::find("wireworld", Path::new("rules")).unwrap().unwrap();
+        assert_eq!(rule.name(), "WireWorld");
+        assert_eq!(rule.states(), 4);
+
+        assert!(RuleTable::find("Unknown", Path::new("rules")).unwrap().is_none());
+        assert!(RuleTable::find("WireWorld", Path::new("missing")).unwrap().is_none());
+    }
+}
@@ -0,0 +1,428 @@
+use clap::ValueEnum;
+use crate::bit_cell_map::BitCellMap;
+use crate::cell_map::CellMap;
+use crate::error::Error;
+use crate::hash_life::HashLife;
+use crate::macrocell::Macrocell;
+use crate::noise::Noise;
+use crate::rle::RLE;
+use crate::state_map::{StateMap, StateRule};
+use crate::stop_condition::{Stop, StopConditions};
+
+/// Simulation engine used to compute the generations
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum Algorithm {
+    /// Cell by cell, one boolean per cell
+    #[default]
+    Naive,
+    /// Rows packed into 64 bits words, neighbours counted with bitwise adders
+    Bitpacked,
+    /// Memoised quadtree, jumps by powers of two generations
+    Hashlife,
+}
+
+enum Engine {
+    Naive(CellMap),
+    Bitpacked(BitCellMap),
+    Hashlife(HashLife),
+    /// Multi-state pattern of a Generations rule or of a rule table
+    MultiState(StateMap),
+}
+
+/// Pattern evolving with one of the simulation engines
+pub struct Simulation {
+    engine: Engine,
+    /// Generations computed by the engines which do not count them, negative before generation 0
+    generation: i64,
+}
+
+impl Simulation {
+    pub fn new(c: CellMap, algorithm: Algorithm) -> Result<Simulation, Error> {
+        let engine = match algorithm {
+            Algorithm::Naive => Engine::Naive(c),
+            Algorithm::Bitpacked => Engine::Bitpacked(BitCellMap::from_cell_map(&c)?),
+            Algorithm::Hashlife => Engine::Hashlife(HashLife::from_cell_map(&c)?),
+        };
+        Ok(Simulation { engine, generation: 0 })
+    }
+
+    /// Simulate a multi-state pattern, only the naive engine supports Generations rules and rule tables
+    pub fn from_state_map(s: StateMap, algorithm: Algorithm) -> Result<Simulation, Error> {
+        if matches!(s.rule(), StateRule::Other(_)) {
+            return Err(Error::Rule("[Simulation] Only Generations rules and rule tables can be simulated"))
+        }
+        if algorithm != Algorithm::Naive {
+            return Err(Error::Engine("[Simulation] Multi-state patterns can only be simulated by the naive engine"))
+        }
+        Ok(Simulation { engine: Engine::MultiState(s), generation: 0 })
+    }
+
+    /// Start from the generation of a macrocell file, HashLife being fed directly with its quadtree
+    /// so that the pattern does not need to fit in a cell map
+    pub fn from_macrocell(m: &Macrocell, algorithm: Algorithm) -> Result<Simulation, Error> {
+        match algorithm {
+            Algorithm::Hashlife => Ok(Simulation { engine: Engine::Hashlife(HashLife::from_macrocell(m)?), generation: 0 }),
+            _ => {
+                let mut simulation = Simulation::new(m.to_cell_map()?, algorithm)?;
+                simulation.set_generation(m.generation());
+                Ok(simulation)
+            }
+        }
+    }
+
+    /// Number of generations computed since the creation of the simulation
+    pub fn generation(&self) -> i64 {
+        match &self.engine {
+            Engine::Hashlife(h) => h.generation(),
+            _ => self.generation,
+        }
+    }
+
+    /// Change the number of the current generation, for a pattern read at a later generation
+    pub fn set_generation(&mut self, generation: i64) {
+        match &mut self.engine {
+            Engine::Hashlife(h) => h.set_generation(generation),
+            _ => self.generation = generation,
+        }
+    }
+
+    /// Make the simulation stochastic, only the naive engine of two-state patterns supports it
+    pub fn set_noise(&mut self, noise: Noise) -> Result<(), Error> {
+        let Engine::Naive(c) = &mut self.engine else {
+            return Err(Error::Engine("[Simulation] Only the naive engine of two-state patterns can simulate noise"))
+        };
+        c.set_noise(Some(noise))
+    }
+
+    /// Number of live cells
+    pub fn population(&self) -> u64 {
+        match &self.engine {
+            Engine::Naive(c) => c.population(),
+            Engine::Bitpacked(b) => b.population(),
+            Engine::Hashlife(h) => h.population(),
+            Engine::MultiState(s) => s.population(),
+        }
+    }
+
+    /// Whether the pattern has more than two states
+    pub fn is_multi_state(&self) -> bool {
+        matches!(self.engine, Engine::MultiState(_))
+    }
+
+    /// Current generation, HashLife fails when the pattern is too large for a cell map.
+    /// Only the live cells of a multi-state pattern are kept
+    pub fn cell_map(&self) -> Result<CellMap, Error> {
+        match &self.engine {
+            Engine::Naive(c) => Ok(c.clone()),
+            Engine::Bitpacked(b) => b.to_cell_map(),
+            Engine::Hashlife(h) => h.to_cell_map(),
+            Engine::MultiState(s) => s.live_cells(),
+        }
+    }
+
+    /// Current generation with the states of the cells
+    pub fn state_map(&self) -> Result<StateMap, Error> {
+        match &self.engine {
+            Engine::MultiState(s) => Ok(s.clone()),
+            _ => Ok(StateMap::from_cell_map(&self.cell_map()?)),
+        }
+    }
+
+    /// Current generation in the macrocell format, HashLife writing its quadtree without a cell map
+    pub fn macrocell(&mut self, comments: &[String]) -> Result<Macrocell, Error> {
+        match &mut self.engine {
+            Engine::Hashlife(h) => h.macrocell(comments),
+            _ => {
+                let mut m = Macrocell::from_cell_map(&self.cell_map()?, Some(&comments.to_vec()));
+                m.set_generation(self.generation);
+                Ok(m)
+            }
+        }
+    }
+
+    /// Current generation in the RLE format, HashLife encoding its quadtree row by row without a cell map
+    pub fn rle(&self, comments: &[String]) -> Result<RLE, Error> {
+        let comments = comments.to_vec();
+        let mut rle = match &self.engine {
+            Engine::Hashlife(h) => return RLE::from_hash_life(h, Some(&comments)),
+            Engine::MultiState(s) => RLE::from_state_map(s, Some(&comments)),
+            _ => RLE::from_cell_map(&self.cell_map()?, Some(&comments)),
+        };
+        rle.set_generation(self.generation);
+        Ok(rle)
+    }
+
+    /// Compute the next generation
+    pub fn step(&mut self) -> Result<(), Error> {
+        self.run(1)
+    }
+
+    /// Compute the next `generations` generations, HashLife jumping over them by powers of two.
+    /// Fails when HashLife can't reach that far
+    pub fn run(&mut self, generations: u64) -> Result<(), Error> {
+        match &mut self.engine {
+            Engine::Naive(c) => (0..generations).for_each(|_| c.generate_next()),
+            Engine::Bitpacked(b) => (0..generations).for_each(|_| b.generate_next()),
+            Engine::Hashlife(h) => h.run(generations)?,
+            Engine::MultiState(s) => {
+                // The rule is checked when the simulation is created
+                for _ in 0..generations {
+                    s.generate_next()?;
+                }
+            }
+        }
+        self.generation += generations as i64;
+        Ok(())
+    }
+
+    /// Go back `generations` generations, only the naive engine can run the reversible Margolus rules backwards.
+    /// The generation becomes negative when going back before generation 0, the cell map keeping its phase
+    pub fn run_backwards(&mut self, generations: u64) -> Result<(), Error> {
+        let Engine::Naive(c) = &mut self.engine else {
+            return Err(Error::Engine("[Simulation] Only the naive engine can run a pattern backwards"))
+        };
+        for _ in 0..generations {
+            c.generate_previous()?;
+        }
+        self.generation -= generations as i64;
+        Ok(())
+    }
+
+    /// Compute the next `generations` generations unless a stop condition fires.
+    /// The conditions are checked on every generation before the last one, the current one included
+    pub fn run_until(&mut self, generations: u64, stop_conditions: &mut StopConditions) -> Result<Option<Stop>, Error> {
+        if stop_conditions.is_empty() {
+            self.run(generations)?;
+            return Ok(None)
+        }
+        self.run_observed(generations, stop_conditions, |_| Ok(()))
+    }
+
+    /// Compute the next `generations` generations one at a time unless a stop condition fires, `observe`
+    /// being given the simulation on the current generation and then after every generation computed
+    pub fn run_observed(
+        &mut self,
+        generations: u64,
+        stop_conditions: &mut StopConditions,
+        mut observe: impl FnMut(&Simulation) -> Result<(), Error>,
+    ) -> Result<Option<Stop>, Error> {
+        let end = self.generation() + generations as i64;
+        loop {
+            observe(self)?;
+            // Reaching the last generation is not a stop, whatever the conditions
+            if self.generation() == end {
+                return Ok(None)
+            }
+            let stop = match &self.engine {
+                _ if stop_conditions.is_empty() => None,
+                Engine::Naive(c) => stop_conditions.check(c, self.generation),
+                _ => stop_conditions.check(&self.cell_map()?, self.generation()),
+            };
+            if stop.is_some() {
+                return Ok(stop)
+            }
+            self.step()?;
+        }
+    }
+}
+
+
+// --------
+// Tests
+// --------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rle::RLE;
+    use crate::rule::Rule;
+    use crate::stop_condition::StopCondition;
+    use crate::topology::Topology;
+
+    fn glider() -> CellMap {
+        CellMap::new(vec![
+            vec![false, true, false],
+            vec![false, false, true],
+            vec![true, true, true],
+        ]).unwrap()
+    }
+
+    // Test Simulation.run
+
+    #[test]
+    fn test_run_engines() {
+        for algorithm in [Algorithm::Naive, Algorithm::Bitpacked, Algorithm::Hashlife] {
+            let mut simulation = Simulation::new(glider(), algorithm).unwrap();
+
+            simulation.run(8).unwrap();
+
+            let c = simulation.cell_map().unwrap().auto_crop();
+            assert_eq!(simulation.generation(), 8);
+            assert_eq!(simulation.population(), 5, "{:?}", algorithm);
+            assert_eq!(c.origin(), (2, 2), "{:?}", algorithm);
+            assert_eq!(c.cells(), glider().cells(), "{:?}", algorithm);
+        }
+    }
+
+    #[test]
+    fn test_run_from_rle() {
+        let rle = RLE::parse(String::from("x = 3, y = 1, rule = B3/S23\n3o!")).unwrap();
+        let mut simulation = Simulation::new(rle.to_cell_map().unwrap(), Algorithm::Hashlife).unwrap();
+
+        simulation.step().unwrap();
+
+        assert_eq!(simulation.cell_map().unwrap().cells(), vec![vec![true], vec![true], vec![true]]);
+    }
+
+    #[test]
+    fn test_run_from_macrocell() {
+        let m = Macrocell::parse(String::from("[M2]\n#G 100\n.*$..*$***$\n")).unwrap();
+
+        for algorithm in [Algorithm::Naive, Algorithm::Bitpacked, Algorithm::Hashlife] {
+            let mut simulation = Simulation::from_macrocell(&m, algorithm).unwrap();
+
+            simulation.run(4).unwrap();
+
+            assert_eq!(simulation.generation(), 104, "{:?}", algorithm);
+            assert_eq!(simulation.cell_map().unwrap().auto_crop().origin(), (-3, -3), "{:?}", algorithm);
+            assert_eq!(simulation.macrocell(&[]).unwrap().generation(), 104, "{:?}", algorithm);
+        }
+    }
+
+    #[test]
+    fn test_run_generations() {
+        let rle = RLE::parse(String::from("x = 2, y = 2, rule = B2/S/C3\nBA$BA!")).unwrap();
+        let s = rle.to_state_map().unwrap();
+        assert!(Simulation::from_state_map(s.clone(), Algorithm::Hashlife).is_err());
+        let mut simulation = Simulation::from_state_map(s, Algorithm::Naive).unwrap();
+
+        simulation.run(10).unwrap();
+
+        assert!(simulation.is_multi_state());
+        assert_eq!(simulation.population(), 4);
+        assert_eq!(simulation.cell_map().unwrap().population(), 2);
+        assert_eq!(simulation.state_map().unwrap().auto_crop().origin(), (10, 0));
+    }
+
+    // Test Simulation.run_backwards
+
+    #[test]
+    fn test_run_backwards() {
+        let rle = RLE::parse(String::from("x = 1, y = 1, rule = BBM\no!")).unwrap();
+        let mut simulation = Simulation::new(rle.to_cell_map().unwrap(), Algorithm::Naive).unwrap();
+        simulation.run(5).unwrap();
+
+        simulation.run_backwards(3).unwrap();
+
+        assert_eq!(simulation.generation(), 2);
+        assert_eq!(simulation.cell_map().unwrap().bounding_box(), Some((2, 2, 2, 2)));
+
+        // Before generation 0 the generation is negative, the RLE file keeping the position of the blocks
+        simulation.run_backwards(3).unwrap();
+        assert_eq!(simulation.generation(), -1);
+        let mut exported = RLE::from_cell_map(&simulation.cell_map().unwrap(), None);
+        exported.set_generation(simulation.generation());
+        let back = RLE::parse(exported.export()).unwrap();
+        let mut simulation = Simulation::new(back.to_cell_map().unwrap(), Algorithm::Naive).unwrap();
+        simulation.set_generation(back.generation());
+        simulation.run(1).unwrap();
+        assert_eq!(simulation.generation(), 0);
+        assert_eq!(simulation.cell_map().unwrap().bounding_box(), Some((0, 0, 0, 0)));
+
+        // The background of Critters is alive on odd generations, negative ones included
+        let rle = RLE::parse(String::from("x = 2, y = 1, rule = Critters\n2o!")).unwrap();
+        let mut simulation = Simulation::new(rle.to_cell_map().unwrap(), Algorithm::Naive).unwrap();
+        simulation.run_backwards(1).unwrap();
+        assert!(simulation.cell_map().unwrap().background());
+        simulation.run(1).unwrap();
+        assert_eq!(simulation.cell_map().unwrap().auto_crop().cells(), vec![vec![true, true]]);
+
+        // Life is not reversible
+        let mut simulation = Simulation::new(glider(), Algorithm::Naive).unwrap();
+        simulation.run(5).unwrap();
+        assert!(simulation.run_backwards(1).is_err());
+    }
+
+    // Test Simulation.set_noise
+
+    #[test]
+    fn test_set_noise() {
+        let noise = Noise { flip: 0.01, seed: 1, ..Noise::default() };
+        let mut simulation = Simulation::new(glider(), Algorithm::Naive).unwrap();
+        simulation.set_noise(noise).unwrap();
+        simulation.run(10).unwrap();
+        let mut other = Simulation::new(glider(), Algorithm::Naive).unwrap();
+        other.set_noise(noise).unwrap();
+        other.run(10).unwrap();
+
+        assert_eq!(simulation.cell_map().unwrap().cells(), other.cell_map().unwrap().cells());
+        assert_eq!(simulation.cell_map().unwrap().noise(), Some(&noise));
+        assert!(Simulation::new(glider(), Algorithm::Hashlife).unwrap().set_noise(noise).is_err());
+
+        // The background beyond the map would not be reached by the noise
+        let mut c = glider();
+        c.set_rule(Rule::parse("B03/S23").unwrap());
+        assert!(Simulation::new(c.clone(), Algorithm::Naive).unwrap().set_noise(noise).is_err());
+        c.set_topology(Topology::Torus { w: 10, h: 10 }).unwrap();
+        assert!(Simulation::new(c, Algorithm::Naive).unwrap().set_noise(noise).is_ok());
+    }
+
+    // Test Simulation.run_until
+
+    #[test]
+    fn test_run_until() {
+        let blinker = CellMap::new(vec![vec![true, true, true]]).unwrap();
+        for algorithm in [Algorithm::Naive, Algorithm::Bitpacked, Algorithm::Hashlife] {
+            let mut simulation = Simulation::new(blinker.clone(), algorithm).unwrap();
+            let mut stop_conditions = StopConditions::new(vec![StopCondition::Periodic]);
+
+            let stop = simulation.run_until(10, &mut stop_conditions).unwrap().unwrap();
+
+            assert_eq!((stop.generation, stop.period), (2, Some(2)), "{:?}", algorithm);
+            assert_eq!(simulation.generation(), 2);
+        }
+    }
+
+    #[test]
+    fn test_run_until_end() {
+        let mut simulation = Simulation::new(glider(), Algorithm::Naive).unwrap();
+        let mut stop_conditions = StopConditions::new(vec![StopCondition::MaxPopulation(4)]);
+
+        // The glider has 5 cells at generation 0
+        assert_eq!(simulation.run_until(3, &mut stop_conditions).unwrap().map(|s| s.generation), Some(0));
+
+        let mut stop_conditions = StopConditions::new(vec![StopCondition::Extinction]);
+        assert_eq!(simulation.run_until(3, &mut stop_conditions).unwrap(), None);
+        assert_eq!(simulation.generation(), 3);
+
+        // The blinker is periodic on the last generation, the run is not stopped
+        let blinker = CellMap::new(vec![vec![true, true, true]]).unwrap();
+        let mut simulation = Simulation::new(blinker, Algorithm::Naive).unwrap();
+        let mut stop_conditions = StopConditions::new(vec![StopCondition::Periodic]);
+        assert_eq!(simulation.run_until(2, &mut stop_conditions).unwrap(), None);
+        assert_eq!(simulation.generation(), 2);
+    }
+
+    // Test Simulation.run_observed
+
+    #[test]
+    fn test_run_observed() {
+        // The generations of a macrocell file start at 100, the observer sees every one of them
+        let m = Macrocell::parse(String::from("[M2]\n#G 100\n.*$..*$***$\n")).unwrap();
+        for algorithm in [Algorithm::Naive, Algorithm::Bitpacked, Algorithm::Hashlife] {
+            let mut simulation = Simulation::from_macrocell(&m, algorithm).unwrap();
+            let mut stop_conditions = StopConditions::new(vec![StopCondition::MaxSize(3)]);
+            let mut frames = Vec::new();
+
+            let stop = simulation.run_observed(10, &mut stop_conditions, |s| {
+                frames.push((s.generation(), s.cell_map()?.auto_crop()));
+                Ok(())
+            }).unwrap();
+
+            assert_eq!(stop, None, "{:?}", algorithm);
+            assert_eq!(frames.iter().map(|(g, _)| *g).collect::<Vec<_>>(), (100..=110).collect::<Vec<_>>());
+            assert_eq!(frames[4].1.cells(), frames[0].1.cells(), "{:?}", algorithm);
+            assert_eq!(frames[4].1.origin(), (frames[0].1.origin().0 + 1, frames[0].1.origin().1 + 1));
+        }
+    }
+}
@@ -0,0 +1,378 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::mem;
+use std::path::Path;
+use crate::cell_map::CellMap;
+use crate::error::Error;
+use crate::generations::Generations;
+use crate::rule_table::RuleTable;
+
+/// Rule of a multi-state pattern
+#[derive(Debug, Clone, PartialEq)]
+pub enum StateRule {
+    /// Generations rule, the two-state rules being the ones with 2 states
+    Generations(Generations),
+    /// Transition table of a Golly `.rule` file
+    Table(RuleTable),
+    /// Rule which cannot be simulated (`WireWorld`, `LifeHistory`...), kept as it is written in the file
+    Other(String),
+}
+
+impl Default for StateRule {
+    fn default() -> Self {
+        StateRule::Generations(Generations::default())
+    }
+}
+
+impl StateRule {
+    /// Rule written in a file, unknown rules being kept as they are
+    pub fn parse(input: &str) -> StateRule {
+        match Generations::parse(input) {
+            Ok(rule) => StateRule::Generations(rule),
+            Err(_) => StateRule::Other(input.trim().to_string()),
+        }
+    }
+
+    /// Load an unknown rule from the `.rule` file of the same name in a directory, if there is one
+    pub fn resolve(&self, directory: &Path) -> Result<StateRule, Error> {
+        match self {
+            StateRule::Other(name) => Ok(RuleTable::find(name, directory)?.map_or_else(|| self.clone(), StateRule::Table)),
+            rule => Ok(rule.clone()),
+        }
+    }
+
+    /// Number of states of the rule, None if it is unknown
+    pub fn states(&self) -> Option<u16> {
+        match self {
+            StateRule::Generations(rule) => Some(rule.states()),
+            StateRule::Table(table) => Some(table.states()),
+            StateRule::Other(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for StateRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateRule::Generations(rule) => write!(f, "{}", rule),
+            StateRule::Table(table) => write!(f, "{}", table),
+            StateRule::Other(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// Grid of cells having one of up to 256 states, 0 being the dead state, growing on the infinite plane.
+/// It holds the patterns of multi-state rules (Generations, WireWorld, LifeHistory...)
+#[derive(Debug, Clone, PartialEq)]
+pub struct StateMap {
+    w: u32,
+    h: u32,
+    cells: Vec<Vec<u8>>,
+    next_generation: Vec<Vec<u8>>,
+    rule: StateRule,
+    /// Coordinates of the top left cell, which move when the map expands or is cropped
+    origin: (i64, i64),
+}
+
+impl StateMap {
+    /// Add a row or a column of dead cells on the sides where cells are not dead
+    fn expand_if_needed(&mut self) {
+        let not_dead = |s: Option<&u8>| s.is_some_and(|&s| s != 0);
+        if self.cells[0].iter().any(|&s| s != 0) {
+            self.cells.insert(0, vec![0; self.w as usize]);
+            self.h += 1;
+            self.origin.1 -= 1;
+        }
+        if self.cells.last().is_some_and(|row| row.iter().any(|&s| s != 0)) {
+            self.cells.push(vec![0; self.w as usize]);
+            self.h += 1;
+        }
+        if self.cells.iter().any(|row| not_dead(row.first())) {
+            self.cells.iter_mut().for_each(|row| row.insert(0, 0));
+            self.w += 1;
+            self.origin.0 -= 1;
+        }
+        if self.cells.iter().any(|row| not_dead(row.last())) {
+            self.cells.iter_mut().for_each(|row| row.push(0));
+            self.w += 1;
+        }
+        self.next_generation = vec![vec![0; self.w as usize]; self.h as usize];
+    }
+
+    // ---------
+
+    /// Create a map from a two-dimensional vector of states
+    pub fn new(source: Vec<Vec<u8>>) -> Result<StateMap, Error> {
+        let col_size = source.len();
+        if col_size == 0 || source[0].is_empty() {
+            return Err(Error::CellMap("[StateMap creation] The source can't be empty"))
+        }
+        let row_size = source[0].len();
+        if source.iter().any(|r| r.len() != row_size) {
+            return Err(Error::CellMap("[StateMap creation] All rows must have the same size"))
+        }
+        Ok(StateMap {
+            w: row_size as u32,
+            h: col_size as u32,
+            cells: source,
+            next_generation: vec![vec![0; row_size]; col_size],
+            rule: StateRule::default(),
+            origin: (0, 0),
+        })
+    }
+
+    /// Map of a two-state pattern, live cells having the state 1
+    pub fn from_cell_map(c: &CellMap) -> StateMap {
+        StateMap {
+            w: c.width(),
+            h: c.height(),
+            cells: c.cells().iter().map(|row| row.iter().map(|&v| (v != c.background()) as u8).collect()).collect(),
+            next_generation: vec![vec![0; c.width() as usize]; c.height() as usize],
+            rule: StateRule::Generations(Generations::from(*c.rule())),
+            origin: c.origin(),
+        }
+    }
+
+    /// Two-state map of the pattern, fails if a cell has a state other than 0 and 1
+    pub fn to_cell_map(&self) -> Result<CellMap, Error> {
+        if self.cells.iter().flatten().any(|&s| s > 1) {
+            return Err(Error::CellMap("[CellMap creation] The pattern has more than two states"))
+        }
+        self.live_cells()
+    }
+
+    /// Two-state map of the live cells (state 1), the other states being dead
+    pub fn live_cells(&self) -> Result<CellMap, Error> {
+        let mut c = CellMap::new(self.cells.iter().map(|row| row.iter().map(|&s| s == 1).collect()).collect())?;
+        c.set_origin(self.origin);
+        if let StateRule::Generations(rule) = &self.rule {
+            c.set_rule(*rule.rule());
+        }
+        Ok(c)
+    }
+
+    /// Reduce the map to the smallest rectangle holding all the cells which are not dead
+    pub fn auto_crop(&self) -> StateMap {
+        let alive = |row: &Vec<u8>| row.iter().any(|&s| s != 0);
+        let (Some(start_y), Some(end_y)) = (self.cells.iter().position(alive), self.cells.iter().rposition(alive)) else {
+            // Nothing alive, keep a single dead cell
+            return StateMap { origin: self.origin, rule: self.rule.clone(), ..StateMap::new(vec![vec![0]]).unwrap() }
+        };
+        let start_x = self.cells.iter().filter_map(|row| row.iter().position(|&s| s != 0)).min().unwrap_or_default();
+        let end_x = self.cells.iter().filter_map(|row| row.iter().rposition(|&s| s != 0)).max().unwrap_or_default();
+        let (w, h) = (end_x - start_x + 1, end_y - start_y + 1);
+
+        StateMap {
+            w: w as u32,
+            h: h as u32,
+            cells: self.cells[start_y..=end_y].iter().map(|row| row[start_x..=end_x].to_vec()).collect(),
+            next_generation: vec![vec![0; w]; h],
+            rule: self.rule.clone(),
+            origin: (self.origin.0 + start_x as i64, self.origin.1 + start_y as i64),
+        }
+    }
+
+    /// Number of columns of the map
+    pub fn width(&self) -> u32 {
+        self.w
+    }
+
+    /// Number of rows of the map
+    pub fn height(&self) -> u32 {
+        self.h
+    }
+
+    /// States of the cells, row by row
+    pub fn cells(&self) -> &[Vec<u8>] {
+        &self.cells
+    }
+
+    /// State of the cell at the given coordinates, dead outside of the map
+    pub fn state(&self, x: i64, y: i64) -> u8 {
+        let (x, y) = (x - self.origin.0, y - self.origin.1);
+        if x < 0 || y < 0 {
+            return 0
+        }
+        self.cells.get(y as usize).and_then(|row| row.get(x as usize)).copied().unwrap_or_default()
+    }
+
+    /// Number of cells which are not dead
+    pub fn population(&self) -> u64 {
+        self.cells.iter().flatten().filter(|&&s| s != 0).count() as u64
+    }
+
+    /// Coordinates of the top left cell of the map
+    pub fn origin(&self) -> (i64, i64) {
+        self.origin
+    }
+
+    /// Move the map so that its top left cell is at the given coordinates
+    pub fn set_origin(&mut self, origin: (i64, i64)) {
+        self.origin = origin;
+    }
+
+    /// Rule used to compute the next generations
+    pub fn rule(&self) -> &StateRule {
+        &self.rule
+    }
+
+    /// Change the rule used to compute the next generations
+    pub fn set_rule(&mut self, rule: StateRule) {
+        self.rule = rule;
+    }
+
+    /// Generate the next generation following the Generations rule or the rule table of the map
+    pub fn generate_next(&mut self) -> Result<(), Error> {
+        if matches!(self.rule, StateRule::Other(_)) {
+            return Err(Error::Rule("[StateMap] Only Generations rules and rule tables can be simulated"))
+        }
+        self.expand_if_needed();
+
+        let StateMap { cells, next_generation, rule, .. } = self;
+        let cell = |y: usize, x: usize| cells.get(y).and_then(|row| row.get(x)).copied().unwrap_or_default();
+        // Next states of the neighbourhoods already met in this generation
+        let mut known: HashMap<Vec<u8>, u8> = HashMap::new();
+        let mut neighbours = Vec::new();
+
+        for i in 0..cells.len() {
+            for j in 0..cells[i].len() {
+                next_generation[i][j] = match rule {
+                    StateRule::Table(table) => {
+                        neighbours.clear();
+                        neighbours.push(cells[i][j]);
+                        neighbours.extend(table.neighbours().iter()
+                            .map(|&(dx, dy)| cell(i.wrapping_add_signed(dy), j.wrapping_add_signed(dx))));
+                        match known.get(&neighbours) {
+                            Some(&state) => state,
+                            None => *known.entry(neighbours.clone()).or_insert_with(|| table.next_state(&neighbours)),
+                        }
+                    }
+                    StateRule::Generations(rule) => {
+                        // Neighbourhood of live cells, dying cells are not counted
+                        let mut neighbourhood = 0;
+                        for dy in 0..3 {
+                            for dx in 0..3 {
+                                if cell((i + dy).wrapping_sub(1), (j + dx).wrapping_sub(1)) == 1 {
+                                    neighbourhood |= 1 << (3 * dy + dx);
+                                }
+                            }
+                        }
+                        rule.next_state(cells[i][j], neighbourhood)
+                    }
+                    StateRule::Other(_) => cells[i][j],
+                };
+            }
+        }
+        // Swap pointers
+        mem::swap(cells, next_generation);
+        Ok(())
+    }
+}
+
+
+// --------
+// Tests
+// --------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Test StateMap::new
+
+    #[test]
+    fn test_new() {
+        assert!(StateMap::new(vec![vec![0, 1], vec![2]]).is_err());
+        assert!(StateMap::new(vec![]).is_err());
+        assert_eq!(StateMap::new(vec![vec![0, 3, 1]]).unwrap().population(), 2);
+    }
+
+    // Test StateMap.auto_crop
+
+    #[test]
+    fn test_auto_crop() {
+        let s = StateMap::new(vec![
+            vec![0, 0, 0, 0],
+            vec![0, 0, 2, 0],
+            vec![0, 3, 1, 0],
+        ]).unwrap();
+
+        let result = s.auto_crop();
+
+        assert_eq!(result.cells(), vec![vec![0, 2], vec![3, 1]]);
+        assert_eq!(result.origin(), (1, 1));
+        assert_eq!(result.state(2, 1), 2);
+        assert_eq!(result.state(0, 0), 0);
+    }
+
+    // Test StateMap.to_cell_map
+
+    #[test]
+    fn test_to_cell_map() {
+        let mut s = StateMap::new(vec![vec![0, 1, 1]]).unwrap();
+        s.set_origin((-1, 4));
+
+        let c = s.to_cell_map().unwrap();
+
+        assert_eq!(c.cells(), vec![vec![false, true, true]]);
+        assert_eq!(c.origin(), (-1, 4));
+        assert_eq!(StateMap::from_cell_map(&c), s);
+        assert!(StateMap::new(vec![vec![0, 2]]).unwrap().to_cell_map().is_err());
+        assert_eq!(StateMap::new(vec![vec![0, 2, 1]]).unwrap().live_cells().unwrap().population(), 1);
+    }
+
+    // Test StateMap.generate_next
+
+    #[test]
+    fn test_generate_next_brians_brain() {
+        // A period 1 spaceship of Brian's Brain moving to the right
+        let mut s = StateMap::new(vec![
+            vec![2, 1],
+            vec![2, 1],
+        ]).unwrap();
+        s.set_rule(StateRule::parse("B2/S/C3"));
+
+        s.generate_next().unwrap();
+        let result = s.auto_crop();
+
+        assert_eq!(result.cells(), vec![vec![2, 1], vec![2, 1]]);
+        assert_eq!(result.origin(), (1, 0));
+    }
+
+    #[test]
+    fn test_generate_next_conway() {
+        // Blinker, a two-state rule being a Generations rule with 2 states
+        let mut s = StateMap::new(vec![vec![1, 1, 1]]).unwrap();
+
+        s.generate_next().unwrap();
+
+        assert_eq!(s.auto_crop().cells(), vec![vec![1], vec![1], vec![1]]);
+    }
+
+    #[test]
+    fn test_generate_next_rule_table() {
+        // An electron moving along a WireWorld wire
+        let mut s = StateMap::new(vec![vec![2, 1, 3, 3, 3, 3]]).unwrap();
+        s.set_rule(StateRule::parse("WireWorld").resolve(Path::new("rules")).unwrap());
+
+        s.generate_next().unwrap();
+        assert_eq!(s.auto_crop().cells(), vec![vec![3, 2, 1, 3, 3, 3]]);
+        s.generate_next().unwrap();
+        s.generate_next().unwrap();
+
+        assert_eq!(s.auto_crop().cells(), vec![vec![3, 3, 3, 2, 1, 3]]);
+        assert_eq!(s.rule().to_string(), "WireWorld");
+        assert_eq!(s.rule().states(), Some(4));
+    }
+
+    #[test]
+    fn test_generate_next_unknown_rule() {
+        let mut s = StateMap::new(vec![vec![3, 2, 1]]).unwrap();
+        s.set_rule(StateRule::parse("WireWorld"));
+
+        assert_eq!(s.rule(), &StateRule::Other(String::from("WireWorld")));
+        assert!(s.generate_next().is_err());
+        // Without a rule file, the rule stays unknown
+        assert_eq!(s.rule().resolve(Path::new("missing")).unwrap(), StateRule::Other(String::from("WireWorld")));
+    }
+}
@@ -0,0 +1,220 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::{Duration, Instant};
+use crate::analysis::shape_hash;
+use crate::cell_map::CellMap;
+
+/// Reason to end a simulation before the requested number of generations
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StopCondition {
+    /// All the cells are dead
+    Extinction,
+    /// A generation is identical to one of the last `MAX_PERIOD` ones, at the same place
+    Periodic,
+    /// The population is greater than the threshold
+    MaxPopulation(u64),
+    /// The population is lower than the threshold
+    MinPopulation(u64),
+    /// The width or the height of the bounding box is greater than the threshold
+    MaxSize(u32),
+    /// The simulation has been running for longer than the duration
+    Timeout(Duration),
+}
+
+/// Condition which ended a simulation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Stop {
+    pub condition: StopCondition,
+    pub generation: i64,
+    /// Period of the pattern when it became periodic (1 for a stable pattern)
+    pub period: Option<u64>,
+}
+
+/// Longest period found by the periodic stop condition, older generations being forgotten to bound the memory
+pub const MAX_PERIOD: u64 = 1024;
+
+/// Cropped generation kept to find periodic patterns
+struct Seen {
+    generation: i64,
+    origin: (i64, i64),
+    hash: u64,
+    cells: Vec<Vec<bool>>,
+}
+
+/// Set of stop conditions checked after every generation
+pub struct StopConditions {
+    conditions: Vec<StopCondition>,
+    start: Instant,
+    /// Last `MAX_PERIOD` cropped generations, the oldest first
+    history: VecDeque<Seen>,
+}
+
+impl StopConditions {
+    pub fn new(conditions: Vec<StopCondition>) -> StopConditions {
+        StopConditions { conditions, start: Instant::now(), history: VecDeque::new() }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.conditions.is_empty()
+    }
+
+    /// Check the conditions against a generation, in the order they were given
+    pub fn check(&mut self, c: &CellMap, generation: i64) -> Option<Stop> {
+        let stop = |condition| Some(Stop { condition, generation, period: None });
+        for &condition in self.conditions.iter() {
+            match condition {
+                StopCondition::Extinction => {
+                    if c.bounding_box().is_none() { return stop(condition) }
+                }
+                StopCondition::Periodic => {
+                    let cropped = c.auto_crop();
+                    let (origin, hash) = (cropped.origin(), shape_hash(&cropped));
+                    // The hashes are compared first, the cells only when they match
+                    let first = self.history.iter()
+                        .find(|s| s.origin == origin && s.hash == hash && s.cells.as_slice() == cropped.cells());
+                    if let Some(first) = first {
+                        return Some(Stop { condition, generation, period: Some(generation.abs_diff(first.generation)) })
+                    }
+                    if self.history.len() as u64 == MAX_PERIOD {
+                        self.history.pop_front();
+                    }
+                    self.history.push_back(Seen { generation, origin, hash, cells: cropped.cells().to_vec() });
+                }
+                StopCondition::MaxPopulation(n) => {
+                    if c.population() > n { return stop(condition) }
+                }
+                StopCondition::MinPopulation(n) => {
+                    if c.population() < n { return stop(condition) }
+                }
+                StopCondition::MaxSize(n) => {
+                    if let Some((min_x, min_y, max_x, max_y)) = c.bounding_box() {
+                        if max_x - min_x + 1 > n as i64 || max_y - min_y + 1 > n as i64 { return stop(condition) }
+                    }
+                }
+                StopCondition::Timeout(duration) => {
+                    if self.start.elapsed() > duration { return stop(condition) }
+                }
+            }
+        }
+        None
+    }
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Stopped at generation {}: ", self.generation)?;
+        match (self.condition, self.period) {
+            (StopCondition::Extinction, _) => write!(f, "the pattern died out"),
+            (StopCondition::Periodic, Some(1)) => write!(f, "the pattern is stable"),
+            (StopCondition::Periodic, Some(p)) => write!(f, "the pattern is periodic with period {}", p),
+            (StopCondition::Periodic, None) => write!(f, "the pattern is periodic"),
+            (StopCondition::MaxPopulation(n), _) => write!(f, "the population exceeds {}", n),
+            (StopCondition::MinPopulation(n), _) => write!(f, "the population dropped below {}", n),
+            (StopCondition::MaxSize(n), _) => write!(f, "the bounding box exceeds {} cells", n),
+            (StopCondition::Timeout(d), _) => write!(f, "timeout of {} seconds reached", d.as_secs_f64()),
+        }
+    }
+}
+
+
+// --------
+// Tests
+// --------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run a cell map until a condition fires
+    fn run(source: Vec<Vec<bool>>, conditions: Vec<StopCondition>, max: i64) -> Option<Stop> {
+        let mut c = CellMap::new(source).unwrap();
+        let mut stop_conditions = StopConditions::new(conditions);
+        for generation in 0..=max {
+            if generation > 0 {
+                c.generate_next();
+            }
+            if let Some(stop) = stop_conditions.check(&c, generation) {
+                return Some(stop)
+            }
+        }
+        None
+    }
+
+    fn glider() -> Vec<Vec<bool>> {
+        vec![
+            vec![false, true, false],
+            vec![false, false, true],
+            vec![true, true, true],
+        ]
+    }
+
+    // Test StopConditions.check
+
+    #[test]
+    fn test_check_extinction() {
+        let stop = run(vec![vec![true, false, true]], vec![StopCondition::Extinction], 10).unwrap();
+
+        assert_eq!(stop.generation, 1);
+        assert_eq!(stop.to_string(), "Stopped at generation 1: the pattern died out");
+    }
+
+    #[test]
+    fn test_check_periodic() {
+        let blinker = run(vec![vec![true, true, true]], vec![StopCondition::Periodic], 10).unwrap();
+        assert_eq!((blinker.generation, blinker.period), (2, Some(2)));
+
+        let block = run(vec![vec![true, true], vec![true, true]], vec![StopCondition::Periodic], 10).unwrap();
+        assert_eq!(block.to_string(), "Stopped at generation 1: the pattern is stable");
+
+        // A moving glider is not periodic
+        assert_eq!(run(glider(), vec![StopCondition::Periodic], 20), None);
+    }
+
+    #[test]
+    fn test_check_periodic_history() {
+        let mut c = CellMap::new(glider()).unwrap();
+        let mut stop_conditions = StopConditions::new(vec![StopCondition::Periodic]);
+        for generation in 0..2 * MAX_PERIOD as i64 {
+            if generation > 0 {
+                // Cropped so that the map does not grow with the glider
+                c.generate_next();
+                c = c.auto_crop();
+            }
+            assert_eq!(stop_conditions.check(&c, generation), None);
+        }
+
+        // Only the last generations are kept
+        assert_eq!(stop_conditions.history.len() as u64, MAX_PERIOD);
+        assert_eq!(stop_conditions.history.front().map(|s| s.generation), Some(MAX_PERIOD as i64));
+    }
+
+    #[test]
+    fn test_check_population() {
+        let stop = run(glider(), vec![StopCondition::MinPopulation(5)], 10);
+        assert_eq!(stop, None);
+
+        let stop = run(vec![vec![true, true, true]], vec![StopCondition::MaxPopulation(2)], 10).unwrap();
+        assert_eq!(stop.generation, 0);
+    }
+
+    #[test]
+    fn test_check_size() {
+        // R-pentomino
+        let stop = run(vec![
+            vec![false, true, true],
+            vec![true, true, false],
+            vec![false, true, false],
+        ], vec![StopCondition::Periodic, StopCondition::MaxSize(10)], 100).unwrap();
+
+        assert_eq!(stop.condition, StopCondition::MaxSize(10));
+    }
+
+    #[test]
+    fn test_check_timeout() {
+        let mut stop_conditions = StopConditions::new(vec![StopCondition::Timeout(Duration::ZERO)]);
+        std::thread::sleep(Duration::from_millis(1));
+
+        let c = CellMap::new(glider()).unwrap();
+        assert_eq!(stop_conditions.check(&c, 3).map(|s| s.condition), Some(StopCondition::Timeout(Duration::ZERO)));
+    }
+}
@@ -0,0 +1,189 @@
+use std::fmt;
+
+/// Shape of the universe, written as a suffix of the rule like Golly does (`B3/S23:T64,64`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Topology {
+    /// Infinite plane, the map grows with the pattern
+    #[default]
+    Plane,
+    /// Finite plane, the cells outside are always dead (`:P`)
+    BoundedPlane { w: u32, h: u32 },
+    /// Opposite edges are joined (`:T`)
+    Torus { w: u32, h: u32 },
+    /// Opposite edges are joined, one pair with a twist (`:K`).
+    /// The twisted pair is the top and bottom edges when `twisted_top_bottom` is set
+    KleinBottle { w: u32, h: u32, twisted_top_bottom: bool },
+    /// Both pairs of opposite edges are joined with a twist (`:C`)
+    CrossSurface { w: u32, h: u32 },
+}
+
+impl Topology {
+    fn parse_size(input: &str) -> Result<(u32, bool), &'static str> {
+        let (digits, twisted) = match input.strip_suffix('*') {
+            Some(d) => (d, true),
+            None => (input, false),
+        };
+        match digits.parse::<u32>() {
+            Ok(0) => Err("[Topology parser] Infinite dimensions are not supported"),
+            Ok(n) => Ok((n, twisted)),
+            Err(_) => Err("[Topology parser] The size of the grid must be a positive number"),
+        }
+    }
+
+    // ---------
+
+    /// Parse the part of the rule after the `:`, such as `T64,64` or `K20*,10`
+    pub fn parse(input: &str) -> Result<Topology, &'static str> {
+        let input = input.trim();
+        let mut chars = input.chars();
+        let kind = match chars.next() {
+            Some(c) => c.to_ascii_uppercase(),
+            None => return Ok(Topology::Plane),
+        };
+        let size = chars.as_str();
+        if size.contains(['+', '-']) {
+            return Err("[Topology parser] Shifted edges are not supported")
+        }
+        // A single number gives a square grid
+        let ((w, twisted_w), (h, twisted_h)) = match size.split_once(',') {
+            Some((w, h)) => (Self::parse_size(w)?, Self::parse_size(h)?),
+            None => (Self::parse_size(size)?, Self::parse_size(size.trim_end_matches('*'))?),
+        };
+        if kind != 'K' && (twisted_w || twisted_h) {
+            return Err("[Topology parser] Only Klein bottles have a twisted pair of edges")
+        }
+
+        match kind {
+            'P' => Ok(Topology::BoundedPlane { w, h }),
+            'T' => Ok(Topology::Torus { w, h }),
+            'K' => match (twisted_w, twisted_h) {
+                (true, false) => Ok(Topology::KleinBottle { w, h, twisted_top_bottom: true }),
+                (false, true) => Ok(Topology::KleinBottle { w, h, twisted_top_bottom: false }),
+                _ => Err("[Topology parser] A Klein bottle needs exactly one twisted pair of edges marked with *"),
+            },
+            'C' => Ok(Topology::CrossSurface { w, h }),
+            _ => Err("[Topology parser] Unknown topology, expected P, T, K or C"),
+        }
+    }
+
+    /// Size of the grid, None for the infinite plane
+    pub fn size(&self) -> Option<(u32, u32)> {
+        match *self {
+            Topology::Plane => None,
+            Topology::BoundedPlane { w, h }
+            | Topology::Torus { w, h }
+            | Topology::KleinBottle { w, h, .. }
+            | Topology::CrossSurface { w, h } => Some((w, h)),
+        }
+    }
+
+    /// Position in the grid of the cell seen at (x, y) from inside the grid, None if it is outside.
+    /// Coordinates are relative to the top left cell of the grid
+    pub fn wrap(&self, x: i64, y: i64) -> Option<(usize, usize)> {
+        let (w, h) = match self.size() {
+            Some((w, h)) => (w as i64, h as i64),
+            None => return if x >= 0 && y >= 0 { Some((x as usize, y as usize)) } else { None },
+        };
+        let (flip_x, flip_y) = match *self {
+            Topology::BoundedPlane { .. } => {
+                return if (0..w).contains(&x) && (0..h).contains(&y) { Some((x as usize, y as usize)) } else { None }
+            }
+            Topology::KleinBottle { twisted_top_bottom, .. } => (twisted_top_bottom, !twisted_top_bottom),
+            Topology::CrossSurface { .. } => (true, true),
+            _ => (false, false),
+        };
+
+        let (mut x, mut y) = (x, y);
+        if !(0..h).contains(&y) {
+            y = y.rem_euclid(h);
+            if flip_x { x = w - 1 - x }
+        }
+        if !(0..w).contains(&x) {
+            x = x.rem_euclid(w);
+            if flip_y { y = h - 1 - y }
+        }
+        Some((x as usize, y as usize))
+    }
+}
+
+impl fmt::Display for Topology {
+    /// Suffix of the rule, empty for the infinite plane
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Topology::Plane => Ok(()),
+            Topology::BoundedPlane { w, h } => write!(f, ":P{},{}", w, h),
+            Topology::Torus { w, h } => write!(f, ":T{},{}", w, h),
+            Topology::KleinBottle { w, h, twisted_top_bottom: true } => write!(f, ":K{}*,{}", w, h),
+            Topology::KleinBottle { w, h, twisted_top_bottom: false } => write!(f, ":K{},{}*", w, h),
+            Topology::CrossSurface { w, h } => write!(f, ":C{},{}", w, h),
+        }
+    }
+}
+
+
+// --------
+// Tests
+// --------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Test Topology::parse
+
+    #[test]
+    fn test_parse_1() {
+        assert_eq!(Topology::parse("T64,32"), Ok(Topology::Torus { w: 64, h: 32 }));
+        assert_eq!(Topology::parse("p10,20"), Ok(Topology::BoundedPlane { w: 10, h: 20 }));
+        assert_eq!(Topology::parse("C8,8"), Ok(Topology::CrossSurface { w: 8, h: 8 }));
+        assert_eq!(Topology::parse("T30"), Ok(Topology::Torus { w: 30, h: 30 }));
+        assert_eq!(Topology::parse(""), Ok(Topology::Plane));
+    }
+
+    #[test]
+    fn test_parse_2() {
+        assert_eq!(Topology::parse("K20*,10"), Ok(Topology::KleinBottle { w: 20, h: 10, twisted_top_bottom: true }));
+        assert_eq!(Topology::parse("K20,10*"), Ok(Topology::KleinBottle { w: 20, h: 10, twisted_top_bottom: false }));
+    }
+
+    #[test]
+    fn test_parse_3() {
+        assert!(Topology::parse("K20,10").is_err());
+        assert!(Topology::parse("T20*,10").is_err());
+        assert!(Topology::parse("T20+3,10").is_err());
+        assert!(Topology::parse("T0,10").is_err());
+        assert!(Topology::parse("X20,10").is_err());
+        assert!(Topology::parse("Tab").is_err());
+    }
+
+    // Test Topology.to_string
+
+    #[test]
+    fn test_display() {
+        for suffix in [":P10,20", ":T64,32", ":K20*,10", ":K20,10*", ":C8,8"] {
+            assert_eq!(Topology::parse(&suffix[1..]).unwrap().to_string(), suffix);
+        }
+        assert_eq!(Topology::Plane.to_string(), "");
+    }
+
+    // Test Topology.wrap
+
+    #[test]
+    fn test_wrap() {
+        let torus = Topology::Torus { w: 4, h: 3 };
+        assert_eq!(torus.wrap(-1, -1), Some((3, 2)));
+        assert_eq!(torus.wrap(4, 1), Some((0, 1)));
+
+        let plane = Topology::BoundedPlane { w: 4, h: 3 };
+        assert_eq!(plane.wrap(-1, 0), None);
+        assert_eq!(plane.wrap(3, 2), Some((3, 2)));
+
+        let klein = Topology::KleinBottle { w: 4, h: 3, twisted_top_bottom: true };
+        assert_eq!(klein.wrap(0, -1), Some((3, 2)));
+        assert_eq!(klein.wrap(-1, 0), Some((3, 0)));
+
+        let cross = Topology::CrossSurface { w: 4, h: 3 };
+        assert_eq!(cross.wrap(-1, 0), Some((3, 2)));
+        assert_eq!(cross.wrap(1, 3), Some((2, 0)));
+    }
+}
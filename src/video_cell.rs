@@ -1,66 +1,213 @@
-use std::fs;
-use std::process::{Command, Stdio};
-use std::io::{Cursor, Write};
-use image::{DynamicImage, Rgb, RgbImage};
+use std::io::{self, ErrorKind, Read, Write};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
 use crate::cell_map::CellMap;
-use crate::img_cell::ImgCell;
+use crate::error::Error;
+use crate::img_cell::{Renderable, Viewport};
 
-pub struct VideoCell {
+/// Settings of the video exporter
+#[derive(Debug, Clone)]
+pub struct VideoOptions {
+    /// Number of generations displayed per second
+    pub framerate: u32,
+    /// Size of the video in pixels, both must be even
+    pub width: u32,
+    pub height: u32,
+    /// Minimum margin in pixels between the pattern and the borders of the video
+    pub padding: u32,
+    /// ffmpeg video codec, chosen from the file extension when None
+    pub codec: Option<String>,
+    /// Display live cells in white on a black background
+    pub inverted: bool,
+    /// ffmpeg executable
+    pub ffmpeg: PathBuf,
+}
+
+impl Default for VideoOptions {
+    fn default() -> Self {
+        VideoOptions {
+            framerate: 10,
+            width: 1920,
+            height: 1080,
+            padding: 40,
+            codec: None,
+            inverted: true,
+            ffmpeg: PathBuf::from("ffmpeg"),
+        }
+    }
+}
 
+pub struct VideoCell {
+    options: VideoOptions,
 }
 
 impl VideoCell {
-    // WIP
-    fn  generate() -> std::io::Result<()> {
-        let mut ffmpeg = Command::new("ffmpeg")
-            .arg("-y")
-            .arg("-f").arg("image2pipe")
-            .arg("-framerate").arg("2")
-            .arg("-i").arg("-")
-            .arg("-pix_fmt").arg("yuv420p")
-            .arg("-vf").arg("scale=1080:1080,pad=1920:1080:(ow-iw)/2:(oh-ih)/2")
-            .arg("tests/samples/output.mp4")
-            .stdin(Stdio::piped())
-            .stdout(Stdio::inherit())
-            .spawn()?;
+    fn error(message: String) -> Error {
+        Error::Export(format!("[Video exporter] {}", message))
+    }
 
-        //let c = CellMap::new(vec![vec![true; 1080]; 1080]).unwrap();
-        let c = CellMap::new(vec![
-            vec![false, true, false],
-            vec![false, false, true],
-            vec![true, true, true]
-        ]).unwrap();
+    fn codec_for(&self, output: &Path) -> Option<String> {
+        if self.options.codec.is_some() {
+            return self.options.codec.clone()
+        }
+        match output.extension().and_then(|e| e.to_str()) {
+            Some("mp4") | Some("mkv") | Some("mov") => Some(String::from("libx264")),
+            Some("webm") => Some(String::from("libvpx-vp9")),
+            _ => None,
+        }
+    }
 
-        if let Some(mut stdin) = ffmpeg.stdin.take() {
-            for i in 0..10 {
+    // ---------
 
-                let mut img = ImgCell::from_cell_map(&c, Some(true), None).img;
+    pub fn new(options: VideoOptions) -> VideoCell {
+        VideoCell { options }
+    }
 
-                let buffer = Vec::new();
-                let mut writer = Cursor::new(buffer);
-                img.write_to(&mut writer, image::ImageFormat::Png)
-                    .expect("Failed to write image to FFmpeg");
+    /// Render the generations of a cell map and encode them into a video file with ffmpeg
+    pub fn generate(&self, c: &CellMap, generations: RangeInclusive<u64>, output: &Path) -> Result<(), Error> {
+        if generations.is_empty() {
+            return Err(Self::error(String::from("The range of generations is empty")))
+        }
+        // A first run finds the viewport, the frames are then sent to ffmpeg as they are computed
+        let mut writer = self.start(Viewport::of_generations(c, &generations), output)?;
+        let mut c = c.clone();
+        for _ in 0..*generations.start() {
+            c.generate_next();
+        }
+        writer.write_frame(&c)?;
+        for _ in *generations.start()..*generations.end() {
+            c.generate_next();
+            writer.write_frame(&c)?;
+        }
+        writer.finish()
+    }
 
+    /// Encode generations computed by a simulation into a video file with ffmpeg, one frame each
+    pub fn generate_frames<F: Renderable>(&self, frames: &[F], output: &Path) -> Result<(), Error> {
+        let viewport = Viewport::of_frames(frames)
+            .ok_or_else(|| Self::error(String::from("There is no generation to render")))?;
+        let mut writer = self.start(viewport, output)?;
+        for c in frames {
+            writer.write_frame(c)?;
+        }
+        writer.finish()
+    }
 
-                stdin.write_all(writer.get_ref())
-                    .expect("Failed to write image to FFmpeg");
-            }
+    /// Check the options and prepare a video drawing the same viewport in every frame, ffmpeg being
+    /// started with the first frame since the size of the frames depends on the pattern
+    pub fn start(&self, viewport: Viewport, output: &Path) -> Result<VideoWriter<'_>, Error> {
+        let (width, height, padding) = (self.options.width, self.options.height, self.options.padding);
+        if width % 2 != 0 || height % 2 != 0 {
+            return Err(Self::error(String::from("The width and height of the video must be even")))
+        }
+        if 2 * padding >= width.min(height) {
+            return Err(Self::error(String::from("The padding is larger than the video")))
+        }
+        if self.options.framerate == 0 {
+            return Err(Self::error(String::from("The framerate must be positive")))
+        }
+        Ok(VideoWriter { video: self, viewport, output: output.to_path_buf(), ffmpeg: None })
+    }
+
+    /// Start ffmpeg reading raw frames of the given size on its standard input
+    fn spawn(&self, (frame_width, frame_height): (u32, u32), output: &Path) -> Result<Child, Error> {
+        let (width, height, padding) = (self.options.width, self.options.height, self.options.padding);
 
-            // Fermer stdin pour signaler la fin de l'entrée
-            drop(stdin);
+        // Nearest neighbour scaling keeps the cells sharp, the rest is filled with the background
+        let background = if self.options.inverted { "black" } else { "white" };
+        let filter = format!(
+            "scale={}:{}:force_original_aspect_ratio=decrease:flags=neighbor,pad={}:{}:(ow-iw)/2:(oh-ih)/2:color={},format=yuv420p",
+            width - 2 * padding, height - 2 * padding, width, height, background
+        );
+
+        let mut command = Command::new(&self.options.ffmpeg);
+        command
+            .arg("-y")
+            .arg("-loglevel").arg("error")
+            .arg("-f").arg("rawvideo")
+            .arg("-pix_fmt").arg("rgb24")
+            .arg("-s").arg(format!("{}x{}", frame_width, frame_height))
+            .arg("-framerate").arg(self.options.framerate.to_string())
+            .arg("-i").arg("-")
+            .arg("-vf").arg(filter);
+        if let Some(codec) = self.codec_for(output) {
+            command.arg("-c:v").arg(codec);
         }
+        command.arg(output)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped());
 
-        // Attendre que la commande se termine et obtenir le statut
-        let status = ffmpeg.wait()?;
+        command.spawn().map_err(|e| match e.kind() {
+            ErrorKind::NotFound => Self::error(format!(
+                "ffmpeg was not found ({}), install it or add it to the PATH to generate videos",
+                self.options.ffmpeg.display()
+            )),
+            _ => Self::error(format!("Unable to start ffmpeg: {}", e)),
+        })
+    }
+
+    /// Close the input of ffmpeg and wait for the end of the encoding. A write failure means
+    /// that ffmpeg stopped, its exit status explains why
+    fn wait(mut ffmpeg: Child, write_result: io::Result<()>) -> Result<(), Error> {
+        drop(ffmpeg.stdin.take());
+
+        let mut stderr = String::new();
+        if let Some(mut pipe) = ffmpeg.stderr.take() {
+            pipe.read_to_string(&mut stderr)
+                .map_err(|e| Self::error(format!("Unable to read the output of ffmpeg: {}", e)))?;
+        }
+        let status = ffmpeg.wait()
+            .map_err(|e| Self::error(format!("Unable to wait for ffmpeg: {}", e)))?;
         if !status.success() {
-            eprintln!("FFmpeg a échoué avec le statut : {:?}", status);
+            return Err(Self::error(format!("ffmpeg failed ({}): {}", status, stderr.trim())))
+        }
+        if let Err(e) = write_result {
+            return Err(Self::error(format!("Unable to send the frames to ffmpeg: {}", e)))
         }
-        println!("Statut de sortie : {}", status);
 
         Ok(())
     }
 }
 
+/// Video being encoded, the frames being sent to ffmpeg one at a time
+pub struct VideoWriter<'a> {
+    video: &'a VideoCell,
+    viewport: Viewport,
+    output: PathBuf,
+    ffmpeg: Option<Child>,
+}
+
+impl VideoWriter<'_> {
+    /// Render a generation and send it to ffmpeg
+    pub fn write_frame<F: Renderable + ?Sized>(&mut self, c: &F) -> Result<(), Error> {
+        let img = c.render(&self.viewport, Some(self.video.options.inverted)).into_image();
+        let ffmpeg = match &mut self.ffmpeg {
+            Some(ffmpeg) => ffmpeg,
+            None => self.ffmpeg.insert(self.video.spawn(img.dimensions(), &self.output)?),
+        };
+        let write_result = match &mut ffmpeg.stdin {
+            Some(stdin) => stdin.write_all(img.as_raw()),
+            None => Ok(()),
+        };
+        if write_result.is_err() {
+            if let Some(ffmpeg) = self.ffmpeg.take() {
+                return VideoCell::wait(ffmpeg, write_result)
+            }
+        }
+        Ok(())
+    }
+
+    /// Wait for ffmpeg to write the video
+    pub fn finish(mut self) -> Result<(), Error> {
+        match self.ffmpeg.take() {
+            Some(ffmpeg) => VideoCell::wait(ffmpeg, Ok(())),
+            None => Err(VideoCell::error(String::from("There is no generation to render"))),
+        }
+    }
+}
+
 
 // --------
 // Tests
@@ -84,11 +231,76 @@ mod tests {
         }
     }
 
-    // Test VideoCell::generate
+    fn glider() -> CellMap {
+        CellMap::new(vec![
+            vec![false, true, false],
+            vec![false, false, true],
+            vec![true, true, true]
+        ]).unwrap()
+    }
+
+    // Test VideoCell.generate
 
     #[test]
     fn test_generate_1() {
         initialize(PATH);
-        //VideoCell::generate().unwrap()
+        let video = VideoCell::new(VideoOptions {
+            ffmpeg: PathBuf::from("alife-missing-ffmpeg"),
+            ..VideoOptions::default()
+        });
+
+        match video.generate(&glider(), 0..=10, Path::new(&(PATH.to_string() + "/video_cell_test_1.mp4"))) {
+            Ok(_) => panic!("The result should not be Ok"),
+            Err(e) => assert!(e.to_string().contains("ffmpeg was not found")),
+        }
+    }
+
+    #[test]
+    fn test_generate_2() {
+        let video = VideoCell::new(VideoOptions { width: 1921, ..VideoOptions::default() });
+
+        assert!(video.generate(&glider(), 0..=10, Path::new("output.mp4")).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_generate_3() {
+        use std::os::unix::fs::PermissionsExt;
+
+        // Fake ffmpeg consuming the frames and failing
+        initialize(PATH);
+        let script = PATH.to_string() + "/failing_ffmpeg.sh";
+        fs::write(&script, "#!/bin/sh\ncat > /dev/null\necho 'Unknown encoder' >&2\nexit 1\n").unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+        let video = VideoCell::new(VideoOptions { ffmpeg: PathBuf::from(&script), ..VideoOptions::default() });
+
+        match video.generate(&glider(), 0..=10, Path::new(&(PATH.to_string() + "/video_cell_test_3.mp4"))) {
+            Ok(_) => panic!("The result should not be Ok"),
+            Err(e) => assert!(e.to_string().contains("Unknown encoder")),
+        }
     }
-}
\ No newline at end of file
+
+    #[cfg(unix)]
+    #[test]
+    fn test_start() {
+        use std::os::unix::fs::PermissionsExt;
+
+        // Fake ffmpeg keeping the raw frames
+        initialize(PATH);
+        let (script, raw) = (PATH.to_string() + "/raw_ffmpeg.sh", PATH.to_string() + "/video_cell_test_start.raw");
+        fs::write(&script, format!("#!/bin/sh\ncat > {}\n", raw)).unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+        let video = VideoCell::new(VideoOptions { ffmpeg: PathBuf::from(&script), ..VideoOptions::default() });
+
+        let mut c = glider();
+        let mut writer = video.start(Viewport { x: 0, y: 0, w: 3, h: 3 }, Path::new("output.mp4")).unwrap();
+        for _ in 0..4 {
+            writer.write_frame(&c).unwrap();
+            c.generate_next();
+        }
+        writer.finish().unwrap();
+
+        // 4 frames of 3x3 cells of 15 pixels and the grid, 3 bytes per pixel
+        assert_eq!(fs::metadata(&raw).unwrap().len(), 4 * 46 * 46 * 3);
+    }
+}
@@ -0,0 +1,183 @@
+use std::fmt;
+use std::str::FromStr;
+use crate::cell_map::CellMap;
+use crate::error::Error;
+
+/// Elementary one-dimensional cellular automaton written `W30`, `W110`..., bit `4 * left + 2 * center + right`
+/// of the number giving the next state of a cell from its own state and the ones of its two neighbours.
+/// Its generations are stacked into a spacetime diagram, one row per generation like Golly does
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Wolfram {
+    number: u8,
+}
+
+impl Wolfram {
+    /// Parse a rule written `W30`, only the even rules being supported so that the background stays dead
+    pub fn parse(input: &str) -> Result<Wolfram, &'static str> {
+        let lower = input.trim().to_ascii_lowercase();
+        let Some(digits) = lower.strip_prefix('w') else {
+            return Err("[Rule parser] A Wolfram rule starts with W")
+        };
+        match digits.parse::<u8>() {
+            Ok(number) if number % 2 == 0 => Ok(Wolfram { number }),
+            Ok(_) => Err("[Rule parser] Odd Wolfram rules turn the dead background alive and are not supported"),
+            Err(_) => Err("[Rule parser] The number of a Wolfram rule must be between 0 and 255"),
+        }
+    }
+
+    /// Number of the rule, between 0 and 254
+    pub fn number(&self) -> u8 {
+        self.number
+    }
+
+    /// Compute the next state of a cell from the states of its left neighbour, itself and its right neighbour
+    pub fn next_state(&self, left: bool, center: bool, right: bool) -> bool {
+        let index = (left as u8) << 2 | (center as u8) << 1 | right as u8;
+        self.number >> index & 1 == 1
+    }
+
+    /// Next generation of a line of cells, one cell longer on each side since the pattern can grow
+    pub fn next_line(&self, line: &[bool]) -> Vec<bool> {
+        let cell = |i: usize| i.checked_sub(2).and_then(|i| line.get(i)).copied().unwrap_or(false);
+        (0..line.len() + 2).map(|i| self.next_state(cell(i), cell(i + 1), cell(i + 2))).collect()
+    }
+
+    /// Spacetime diagram of the first row of a cell map and of its next `generations` generations,
+    /// each generation being the row below the previous one. The map has the rule, so that it can be
+    /// exported as it is or simulated further
+    pub fn spacetime(&self, seed: &CellMap, generations: u32) -> Result<CellMap, Error> {
+        let g = generations as usize;
+        let width = seed.cells()[0].len() as u64 + 2 * generations as u64;
+        if width.saturating_mul(generations as u64 + 1) > CellMap::MAX_CELLS {
+            return Err(Error::CellMap("[Wolfram] The spacetime diagram is too large to be converted to a cell map"))
+        }
+        let mut line = seed.cells()[0].clone();
+        let mut rows = Vec::with_capacity(g + 1);
+        for t in 0..=g {
+            // Every row is as wide as the last one, the line growing by one cell on each side
+            let margin = vec![false; g - t];
+            rows.push([margin.as_slice(), &line, &margin].concat());
+            if t < g {
+                line = self.next_line(&line);
+            }
+        }
+
+        let mut c = CellMap::new(rows)?;
+        let (x, y) = seed.origin();
+        c.set_origin((x - generations as i64, y));
+        c.set_rule((*self).into());
+        Ok(c)
+    }
+
+    /// Spacetime diagram of the generations of a single live cell at (0, 0)
+    pub fn spacetime_from_cell(&self, generations: u32) -> Result<CellMap, Error> {
+        self.spacetime(&CellMap::new(vec![vec![true]])?, generations)
+    }
+}
+
+impl fmt::Display for Wolfram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "W{}", self.number)
+    }
+}
+
+impl FromStr for Wolfram {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Wolfram::parse(s)
+    }
+}
+
+
+// --------
+// Tests
+// --------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(text: &str) -> Vec<bool> {
+        text.chars().map(|c| c == 'O').collect()
+    }
+
+    // Test Wolfram::parse
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Wolfram::parse("W30").unwrap().number(), 30);
+        assert_eq!(Wolfram::parse("w110").unwrap().to_string(), "W110");
+        assert!(Wolfram::parse("W31").is_err());
+        assert!(Wolfram::parse("W256").is_err());
+        assert!(Wolfram::parse("B3/S23").is_err());
+    }
+
+    // Test Wolfram.next_state
+
+    #[test]
+    fn test_next_state() {
+        let rule = Wolfram::parse("W30").unwrap();
+        assert!(rule.next_state(true, false, false));
+        assert!(rule.next_state(false, true, true));
+        assert!(!rule.next_state(true, true, false));
+        assert!(!rule.next_state(false, false, false));
+    }
+
+    // Test Wolfram.spacetime
+
+    #[test]
+    fn test_spacetime_from_cell() {
+        let c = Wolfram::parse("W30").unwrap().spacetime_from_cell(3).unwrap();
+
+        assert_eq!(c.cells(), vec![
+            row("...O..."),
+            row("..OOO.."),
+            row(".OO..O."),
+            row("OO.OOOO"),
+        ]);
+        assert_eq!(c.origin(), (-3, 0));
+        assert_eq!(c.rule().to_string(), "W30");
+    }
+
+    #[test]
+    fn test_spacetime_too_large() {
+        let result = Wolfram::parse("W30").unwrap().spacetime_from_cell(u32::MAX);
+
+        assert!(matches!(result, Err(Error::CellMap(message)) if message.contains("too large")));
+    }
+
+    #[test]
+    fn test_spacetime() {
+        // Only the first row of the seed is used
+        let mut seed = CellMap::new(vec![row("O.O"), row("OOO")]).unwrap();
+        seed.set_origin((5, 2));
+
+        let c = Wolfram::parse("W110").unwrap().spacetime(&seed, 2).unwrap();
+
+        assert_eq!(c.cells(), vec![
+            row("..O.O.."),
+            row(".OOOO.."),
+            row("OO..O.."),
+        ]);
+        assert_eq!(c.origin(), (3, 2));
+    }
+
+    // Test Rule::from
+
+    #[test]
+    fn test_generate_next() {
+        // The two-dimensional rule adds a row to the spacetime diagram every generation
+        let rule = Wolfram::parse("W110").unwrap();
+        let mut c = CellMap::new(vec![vec![true]]).unwrap();
+        c.set_rule(rule.into());
+
+        for _ in 0..20 {
+            c.generate_next();
+        }
+
+        let expected = rule.spacetime_from_cell(20).unwrap().auto_crop();
+        assert_eq!(c.auto_crop().cells(), expected.cells());
+        assert_eq!(c.bounding_box(), expected.bounding_box());
+    }
+}